@@ -0,0 +1,356 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::events::{CanonicalEvent, EventIdFormat};
+use crate::program_parsers::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::rpc::AlchemyRPCClient;
+use base58::ToBase58;
+use base64::Engine;
+use borsh::BorshDeserialize;
+use chrono::{DateTime, Utc};
+use futures::{future, stream, StreamExt};
+use serde_json::{json, Value};
+use std::io::Read;
+use tracing::{info, warn};
+
+/// Decodes raw account data for one program into a structured payload
+pub trait ProgramAccountDecoder: Send + Sync {
+    fn program_id(&self) -> &str;
+    /// Event type and payload for the account, or `None` for unknown layouts
+    fn decode(&self, data: &[u8]) -> Option<(&'static str, Value)>;
+}
+
+/// Decoders for the built-in account layouts
+pub fn default_decoders() -> Vec<Box<dyn ProgramAccountDecoder>> {
+    vec![
+        Box::new(SplTokenAccountDecoder(TOKEN_PROGRAM_ID)),
+        Box::new(SplTokenAccountDecoder(TOKEN_2022_PROGRAM_ID)),
+    ]
+}
+
+type Pubkey = [u8; 32];
+
+/// `solana_program::program_option::COption`: a u32 tag followed by the
+/// value, which is present (zeroed) even when the tag is 0
+#[derive(Debug)]
+struct COption<T>(Option<T>);
+
+impl<T: BorshDeserialize> BorshDeserialize for COption<T> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let tag = u32::deserialize_reader(reader)?;
+        let value = T::deserialize_reader(reader)?;
+        match tag {
+            0 => Ok(COption(None)),
+            1 => Ok(COption(Some(value))),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid COption tag {}", tag))),
+        }
+    }
+}
+
+/// SPL token mint (82 bytes)
+#[derive(Debug, BorshDeserialize)]
+struct Mint {
+    mint_authority: COption<Pubkey>,
+    supply: u64,
+    decimals: u8,
+    is_initialized: bool,
+    freeze_authority: COption<Pubkey>,
+}
+
+/// SPL token account (165 bytes)
+#[derive(Debug, BorshDeserialize)]
+struct TokenAccount {
+    mint: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+    delegate: COption<Pubkey>,
+    state: u8,
+    is_native: COption<u64>,
+    delegated_amount: u64,
+    close_authority: COption<Pubkey>,
+}
+
+const MINT_LEN: usize = 82;
+const TOKEN_ACCOUNT_LEN: usize = 165;
+
+/// Token-2022 marks extended accounts with a type byte right after the base
+/// account length (mints are padded to it)
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// Mint and token account layouts of the SPL Token programs. Token-2022
+/// extensions are ignored; only the base fields are decoded.
+pub struct SplTokenAccountDecoder(pub &'static str);
+
+impl SplTokenAccountDecoder {
+    fn decode_mint(data: &[u8]) -> Option<Value> {
+        let mint = Mint::deserialize(&mut &data[..MINT_LEN]).ok()?;
+        Some(json!({
+            "mint_authority": mint.mint_authority.0.map(|k| k.to_base58()),
+            "supply": mint.supply.to_string(),
+            "decimals": mint.decimals,
+            "is_initialized": mint.is_initialized,
+            "freeze_authority": mint.freeze_authority.0.map(|k| k.to_base58()),
+        }))
+    }
+
+    fn decode_account(data: &[u8]) -> Option<Value> {
+        let account = TokenAccount::deserialize(&mut &data[..TOKEN_ACCOUNT_LEN]).ok()?;
+        let state = match account.state {
+            0 => "uninitialized",
+            1 => "initialized",
+            2 => "frozen",
+            _ => return None,
+        };
+        Some(json!({
+            "mint": account.mint.to_base58(),
+            "owner": account.owner.to_base58(),
+            "amount": account.amount.to_string(),
+            "delegate": account.delegate.0.map(|k| k.to_base58()),
+            "state": state,
+            "is_native": account.is_native.0.is_some(),
+            "rent_exempt_reserve": account.is_native.0.map(|r| r.to_string()),
+            "delegated_amount": account.delegated_amount.to_string(),
+            "close_authority": account.close_authority.0.map(|k| k.to_base58()),
+        }))
+    }
+}
+
+impl ProgramAccountDecoder for SplTokenAccountDecoder {
+    fn program_id(&self) -> &str {
+        self.0
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<(&'static str, Value)> {
+        match data.len() {
+            MINT_LEN => Self::decode_mint(data).map(|p| ("token_mint_account", p)),
+            TOKEN_ACCOUNT_LEN => Self::decode_account(data).map(|p| ("token_account", p)),
+            len if len > TOKEN_ACCOUNT_LEN => match data[TOKEN_ACCOUNT_LEN] {
+                ACCOUNT_TYPE_MINT => Self::decode_mint(data).map(|p| ("token_mint_account", p)),
+                ACCOUNT_TYPE_ACCOUNT => Self::decode_account(data).map(|p| ("token_account", p)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Turn `getProgramAccounts` entries (base64 encoded) into snapshot events.
+///
+/// Accounts a decoder understands become `token_mint_account` etc. with
+/// decoded fields; everything else is kept as a `program_account` event
+/// with the raw base64 data. Event ids are encoded in `format`.
+pub fn decode_program_accounts(
+    program_id: &str,
+    accounts: &[Value],
+    decoder: Option<&dyn ProgramAccountDecoder>,
+    slot: u64,
+    snapshot_time: DateTime<Utc>,
+    format: EventIdFormat,
+) -> Vec<CanonicalEvent> {
+    let mut events = Vec::with_capacity(accounts.len());
+
+    for entry in accounts {
+        let Some(pubkey) = entry.get("pubkey").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let account = entry.get("account").unwrap_or(&Value::Null);
+        let encoded = account
+            .get("data")
+            .and_then(|d| d.get(0))
+            .and_then(|d| d.as_str())
+            .unwrap_or_default();
+        let data = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Skipping account {}: invalid base64 data: {}", pubkey, e);
+                continue;
+            }
+        };
+
+        let (event_type, mut payload) = match decoder.and_then(|d| d.decode(&data)) {
+            Some((event_type, payload)) => (event_type, payload),
+            None => ("program_account", json!({ "data": encoded, "data_len": data.len() })),
+        };
+        payload["pubkey"] = json!(pubkey);
+        payload["lamports"] = account.get("lamports").cloned().unwrap_or(Value::Null);
+
+        let mut event = CanonicalEvent::new(
+            slot,
+            snapshot_time,
+            String::new(),
+            Some(program_id.to_string()),
+            -1,
+            event_type.to_string(),
+            payload,
+        );
+        // Snapshots have no transaction; the account address keeps ids unique
+        event.event_id = CanonicalEvent::generate_event_id_as(format, slot, pubkey, -1, event_type);
+        event.accounts = vec![pubkey.to_string()];
+        events.push(event);
+    }
+
+    events
+}
+
+/// Snapshot every account owned by `program_id` into the warehouse
+pub async fn run_snapshot_accounts(config: Config, program_id: String, data_size: Option<u64>) -> Result<()> {
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    let format: EventIdFormat = config.etl.event_id_format.parse()?;
+    let warehouse = crate::warehouse::create_warehouse(config.warehouse.clone())?;
+    warehouse.connect().await?;
+    warehouse.check_event_id_format(format).await?;
+
+    let slot = rpc_client.get_slot().await?;
+    let snapshot_time = Utc::now();
+    let decoders = default_decoders();
+    let decoder = decoders.iter().find(|d| d.program_id() == program_id).map(|d| d.as_ref());
+    if decoder.is_none() {
+        info!("No account decoder for {}; storing raw data", program_id);
+    }
+
+    // Stream decoded events into the warehouse so a large program never sits
+    // in memory at once; a failed page ends the stream and is returned below
+    let filters = data_size.map(|size| json!([{ "dataSize": size }]));
+    let pages = rpc_client.get_program_accounts_stream(&program_id, Some("base64"), filters, None);
+    let mut fetch_error = None;
+    let events = pages
+        .scan(&mut fetch_error, |fetch_error, page| {
+            let accounts = page.map_err(|e| **fetch_error = Some(e)).ok();
+            future::ready(accounts)
+        })
+        .flat_map(|accounts| {
+            info!("Fetched {} account(s) owned by {} at slot {}", accounts.len(), program_id, slot);
+            stream::iter(decode_program_accounts(&program_id, &accounts, decoder, slot, snapshot_time, format))
+        })
+        .boxed();
+    let stats = warehouse.insert_events_stream(events).await?;
+    if let Some(e) = fetch_error {
+        return Err(e);
+    }
+    warehouse.flush().await?;
+    let count = stats.events;
+
+    if count == 0 {
+        info!("No accounts to store for {}", program_id);
+    } else {
+        info!("Stored {} account snapshot(s) for {}", count, program_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coption(key: Option<[u8; 32]>) -> Vec<u8> {
+        let mut bytes = (key.is_some() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&key.unwrap_or_default());
+        bytes
+    }
+
+    /// USDC-like mint: 6 decimals, a mint authority and no freeze authority
+    fn mint_bytes() -> Vec<u8> {
+        let mut data = coption(Some([1; 32]));
+        data.extend_from_slice(&1_000_000_000_000u64.to_le_bytes());
+        data.push(6);
+        data.push(1);
+        data.extend(coption(None));
+        assert_eq!(data.len(), MINT_LEN);
+        data
+    }
+
+    fn token_account_bytes() -> Vec<u8> {
+        let mut data = [2u8; 32].to_vec();
+        data.extend_from_slice(&[3; 32]);
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend(coption(None));
+        data.push(2);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&2_039_280u64.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend(coption(Some([4; 32])));
+        assert_eq!(data.len(), TOKEN_ACCOUNT_LEN);
+        data
+    }
+
+    fn expected_mint() -> Value {
+        json!({
+            "mint_authority": ([1u8; 32]).to_base58(),
+            "supply": "1000000000000",
+            "decimals": 6,
+            "is_initialized": true,
+            "freeze_authority": null,
+        })
+    }
+
+    fn expected_account() -> Value {
+        json!({
+            "mint": ([2u8; 32]).to_base58(),
+            "owner": ([3u8; 32]).to_base58(),
+            "amount": u64::MAX.to_string(),
+            "delegate": null,
+            "state": "frozen",
+            "is_native": true,
+            "rent_exempt_reserve": "2039280",
+            "delegated_amount": "0",
+            "close_authority": ([4u8; 32]).to_base58(),
+        })
+    }
+
+    #[test]
+    fn spl_token_mints_and_accounts_are_decoded() {
+        let decoder = SplTokenAccountDecoder(TOKEN_PROGRAM_ID);
+        assert_eq!(decoder.decode(&mint_bytes()), Some(("token_mint_account", expected_mint())));
+        assert_eq!(decoder.decode(&token_account_bytes()), Some(("token_account", expected_account())));
+
+        // Other sizes and an invalid COption tag are unknown layouts
+        assert_eq!(decoder.decode(&[0; 100]), None);
+        let mut bad_tag = mint_bytes();
+        bad_tag[0] = 7;
+        assert_eq!(decoder.decode(&bad_tag), None);
+    }
+
+    #[test]
+    fn token_2022_accounts_with_extensions_decode_their_base_fields() {
+        let decoder = SplTokenAccountDecoder(TOKEN_2022_PROGRAM_ID);
+        let extension = [9u8; 12];
+
+        // Mints are padded to the account length before the type byte
+        let mut mint = mint_bytes();
+        mint.resize(TOKEN_ACCOUNT_LEN, 0);
+        mint.push(ACCOUNT_TYPE_MINT);
+        mint.extend_from_slice(&extension);
+        assert_eq!(decoder.decode(&mint), Some(("token_mint_account", expected_mint())));
+
+        let mut account = token_account_bytes();
+        account.push(ACCOUNT_TYPE_ACCOUNT);
+        account.extend_from_slice(&extension);
+        assert_eq!(decoder.decode(&account), Some(("token_account", expected_account())));
+
+        let mut unknown = token_account_bytes();
+        unknown.push(0);
+        assert_eq!(decoder.decode(&unknown), None);
+    }
+
+    #[test]
+    fn program_accounts_become_snapshot_events() {
+        let encode = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let accounts = vec![
+            json!({ "pubkey": "MintA", "account": { "lamports": 1_461_600, "data": [encode(&mint_bytes()), "base64"] } }),
+            json!({ "pubkey": "Other", "account": { "lamports": 1, "data": [encode(&[1, 2, 3]), "base64"] } }),
+            json!({ "pubkey": "Broken", "account": { "lamports": 1, "data": ["not base64!", "base64"] } }),
+        ];
+        let decoder = SplTokenAccountDecoder(TOKEN_PROGRAM_ID);
+        let events = decode_program_accounts(TOKEN_PROGRAM_ID, &accounts, Some(&decoder), 100, Utc::now(), EventIdFormat::Sha256Hex);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, "token_mint_account");
+        assert_eq!(events[0].raw_payload["decimals"], 6);
+        assert_eq!(events[0].raw_payload["pubkey"], "MintA");
+        assert_eq!(events[0].raw_payload["lamports"], 1_461_600);
+        assert_eq!(events[0].accounts, vec!["MintA".to_string()]);
+        assert_eq!(events[1].event_type, "program_account");
+        assert_eq!(events[1].raw_payload["data_len"], 3);
+        assert_ne!(events[0].event_id, events[1].event_id);
+    }
+}
@@ -1,39 +1,447 @@
-use crate::config::Config;
+use crate::config::{AnalyticsConfig, Config};
+use crate::enrich::create_tokens_table;
+use crate::epoch::EpochSchedule;
 use crate::error::{ETLError, Result};
+use crate::rpc::AlchemyRPCClient;
+use crate::migrations::run_migrations;
+use crate::sql::quote_ident;
+use crate::warehouse::connect_postgres;
 use chrono::{DateTime, Utc, NaiveDate};
-use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Row};
+use serde::Serialize;
+use futures::future::BoxFuture;
+use sqlx::{PgConnection, PgPool, Row};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Consolidated analytics report, read back from the analytics tables
+#[derive(Debug, Serialize)]
+pub struct AnalyticsReport {
+    pub transaction_volume: Vec<PeriodVolume>,
+    pub top_programs: Vec<ProgramStats>,
+    pub top_tokens: Vec<TokenStats>,
+    pub failed_transactions: Option<FailureStats>,
+    pub wallet_activity: Option<WalletActivityStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeriodVolume {
+    pub period_type: String,
+    pub transaction_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProgramStats {
+    pub program_id: String,
+    pub transaction_count: i64,
+    pub unique_wallets: i64,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenStats {
+    pub token_mint: String,
+    pub transfer_count: i64,
+    pub unique_wallets: i64,
+    pub decimals: Option<i16>,  // From `tokens`, once enrich-tokens has run
+    pub supply: Option<String>, // Raw supply (smallest units)
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailureStats {
+    pub total_failed: i64,
+    pub failure_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletActivityStats {
+    pub total_unique_wallets: i64,
+    pub active_today: i64,
+    pub active_this_week: i64,
+    pub approximate: bool, // Estimated from a sample (`--approximate`)
+}
+
+/// Analytics computations by `--only` name, in run order
+const ANALYTICS_STEP_NAMES: [&str; 9] =
+    ["volume", "programs", "tokens", "failed", "wallets", "trends", "rewards", "leaders", "blockhashes"];
+
+/// Compute analytics. `only` restricts the run to the named computations
+/// (see `ANALYTICS_STEP_NAMES`); empty means all of them.
+pub async fn run_analytics(
+    config: Config,
+    json: bool,
+    create_views: bool,
+    refresh_views: bool,
+    only: Vec<String>,
+    approximate: bool,
+) -> Result<()> {
+    if let Some(unknown) = only.iter().find(|name| !ANALYTICS_STEP_NAMES.contains(&name.as_str())) {
+        return Err(ETLError::Config(format!(
+            "Unknown analytics computation: {}. Use one of: {}",
+            unknown,
+            ANALYTICS_STEP_NAMES.join(", ")
+        )));
+    }
 
-pub async fn run_analytics(config: Config) -> Result<()> {
     // Get database connection
-    let conn_str = config.warehouse.connection_string
-        .ok_or_else(|| ETLError::Config("WAREHOUSE_CONNECTION not set".to_string()))?;
-    
     tracing::info!("Connecting to database for analytics...");
-    let pool = PgPool::connect(&conn_str).await
-        .map_err(|e| ETLError::Database(format!("Failed to connect: {}", e)))?;
+    let pool = connect_postgres(&config.warehouse).await?;
+
+    // SQL-native mode: aggregation lives in materialized views instead of
+    // being computed here and written to the analytics tables
+    if create_views || refresh_views {
+        if create_views {
+            create_materialized_views(&pool).await?;
+        }
+        if refresh_views {
+            refresh_materialized_views(&pool).await?;
+        }
+        return Ok(());
+    }
     
     // Create analytics tables
     create_analytics_tables(&pool).await?;
     
     tracing::info!("Computing and storing analytics...");
     
-    // Compute and store all analytics
-    compute_and_store_transaction_volume(&pool).await?;
-    compute_and_store_active_programs(&pool).await?;
-    compute_and_store_token_transfers(&pool).await?;
-    compute_and_store_failed_transactions(&pool).await?;
-    compute_and_store_wallet_activity(&pool).await?;
-    compute_and_store_program_trends(&pool).await?;
+    // Compute and store all analytics, each in its own transaction
+    let ctx = StepContext {
+        epoch_schedule: resolve_epoch_schedule(&config).await,
+        sample_fraction: approximate.then_some(config.analytics.sample_fraction),
+    };
+    if approximate {
+        tracing::warn!(
+            "Approximate mode: token and wallet counts are estimated from a {:.2}% sample",
+            config.analytics.sample_fraction * 100.0
+        );
+    }
+
+    let steps: [AnalyticsStep; 9] = [
+        |c, _| Box::pin(compute_and_store_transaction_volume(c)),
+        |c, _| Box::pin(compute_and_store_active_programs(c)),
+        |c, ctx| Box::pin(compute_and_store_token_transfers(c, ctx.sample_fraction)),
+        |c, _| Box::pin(compute_and_store_failed_transactions(c)),
+        |c, ctx| Box::pin(compute_and_store_wallet_activity(c, ctx.sample_fraction)),
+        |c, _| Box::pin(compute_and_store_program_trends(c)),
+        |c, ctx| Box::pin(compute_and_store_rewards(c, ctx.epoch_schedule)),
+        |c, _| Box::pin(compute_and_store_leaders(c)),
+        |c, _| Box::pin(compute_and_store_blockhash_reuse(c)),
+    ];
+    // A failing or timed-out computation is rolled back and the rest still
+    // run; both are reported together at the end and fail the command.
+    let mut succeeded = Vec::new();
+    let mut timed_out = Vec::new();
+    let mut failed = Vec::new();
+    for (name, step) in ANALYTICS_STEP_NAMES.into_iter().zip(steps) {
+        if !only.is_empty() && !only.iter().any(|o| o == name) {
+            continue;
+        }
+        match run_step(&pool, name, step, &ctx, &config.analytics).await {
+            Ok(()) => succeeded.push(name),
+            Err(ETLError::StatementTimeout(msg)) => {
+                tracing::warn!(
+                    "Analytics computation '{}' timed out after {}s, skipping: {}",
+                    name,
+                    config.analytics.query_timeout_seconds,
+                    msg
+                );
+                timed_out.push(name);
+            }
+            Err(e) => {
+                tracing::error!("Analytics computation '{}' failed: {}", name, e);
+                failed.push(name);
+            }
+        }
+    }
     
-    tracing::info!("Analytics computed and stored in database tables");
+    tracing::info!(
+        "Analytics stored in database tables: {} succeeded [{}], {} timed out [{}], {} failed [{}]",
+        succeeded.len(),
+        succeeded.join(", "),
+        timed_out.len(),
+        timed_out.join(", "),
+        failed.len(),
+        failed.join(", ")
+    );
+
+    if json {
+        let report = load_report(&pool).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    if !failed.is_empty() || !timed_out.is_empty() {
+        let unfinished: Vec<String> = failed
+            .iter()
+            .map(|name| name.to_string())
+            .chain(timed_out.iter().map(|name| format!("{} (timed out)", name)))
+            .collect();
+        let rerun: Vec<&str> = failed.iter().chain(&timed_out).copied().collect();
+        return Err(ETLError::Generic(anyhow::anyhow!(
+            "Analytics computations failed: {} (rerun with --only {})",
+            unfinished.join(", "),
+            rerun.join(",")
+        )));
+    }
     
     Ok(())
 }
 
+/// Inputs shared by all analytics computations
+struct StepContext {
+    epoch_schedule: EpochSchedule,
+    /// Sample fraction when running with `--approximate`
+    sample_fraction: Option<f64>,
+}
+
+type AnalyticsStep = for<'c> fn(&'c mut PgConnection, &'c StepContext) -> BoxFuture<'c, Result<()>>;
+
+/// Epoch schedule from config, else from the RPC, else mainnet defaults
+async fn resolve_epoch_schedule(config: &Config) -> EpochSchedule {
+    if let Some(schedule) = config.etl.epoch_schedule {
+        return schedule;
+    }
+    match AlchemyRPCClient::new(config.alchemy.clone()).get_epoch_schedule().await {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            tracing::warn!("Failed to fetch epoch schedule, assuming mainnet: {}", e);
+            EpochSchedule::mainnet()
+        }
+    }
+}
+
+/// SQLSTATE of a statement cancelled by `statement_timeout`
+const QUERY_CANCELED: &str = "57014";
+
+/// Wrap a query error, keeping statement timeouts distinguishable
+fn db_error(e: sqlx::Error, context: &str) -> ETLError {
+    let timed_out = e
+        .as_database_error()
+        .and_then(|db| db.code())
+        .is_some_and(|code| code == QUERY_CANCELED);
+    if timed_out {
+        ETLError::StatementTimeout(format!("{}: {}", context, e))
+    } else {
+        ETLError::Database(format!("{}: {}", context, e))
+    }
+}
+
+/// Run one analytics computation, retrying up to `step_retries` times with
+/// exponential backoff. Each attempt runs in its own transaction, so a
+/// failed attempt leaves the computation's tables as they were. A statement
+/// timeout is returned without retrying, since it would time out again.
+async fn run_step(pool: &PgPool, name: &str, step: AnalyticsStep, ctx: &StepContext, config: &AnalyticsConfig) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match run_step_once(pool, name, step, ctx, config.query_timeout_seconds).await {
+            Ok(()) => return Ok(()),
+            Err(e @ ETLError::StatementTimeout(_)) => return Err(e),
+            Err(e) if attempt < config.step_retries => {
+                attempt += 1;
+                let backoff = Duration::from_secs(2_u64.saturating_pow(attempt));
+                tracing::warn!(
+                    "Analytics computation '{}' failed: {}, retrying in {:?} (attempt {}/{})",
+                    name,
+                    e,
+                    backoff,
+                    attempt,
+                    config.step_retries
+                );
+                sleep(backoff).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run one attempt of an analytics computation in a transaction with a
+/// statement timeout; on error the transaction is rolled back
+async fn run_step_once(pool: &PgPool, name: &str, step: AnalyticsStep, ctx: &StepContext, timeout_seconds: u64) -> Result<()> {
+    let mut tx = pool.begin().await
+        .map_err(|e| db_error(e, "Failed to begin transaction"))?;
+
+    // statement_timeout = 0 disables the timeout
+    sqlx::query(&format!("SET LOCAL statement_timeout = '{}s'", timeout_seconds))
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db_error(e, "Failed to set statement timeout"))?;
+
+    step(&mut tx, ctx).await?;
+    tx.commit().await
+        .map_err(|e| db_error(e, &format!("Failed to commit {}", name)))?;
+    Ok(())
+}
+
+/// Materialized views: (name, query, unique index columns). Each needs a
+/// unique index so it can be refreshed CONCURRENTLY without blocking readers.
+const MATERIALIZED_VIEWS: &[(&str, &str, &str)] = &[
+    (
+        "mv_daily_volume",
+        "SELECT DATE(block_time) as date, COUNT(*)::bigint as transaction_count
+         FROM fact_transactions
+         WHERE event_type = 'transaction'
+         GROUP BY DATE(block_time)",
+        "date",
+    ),
+    (
+        "mv_active_programs",
+        "SELECT
+            program_id,
+            COUNT(*)::bigint as transaction_count,
+            COUNT(DISTINCT (raw_payload->'transaction'->'message'->'accountKeys'->>0))::bigint as unique_wallets,
+            MAX(block_time)::timestamptz as last_seen
+         FROM fact_transactions
+         WHERE program_id IS NOT NULL
+         AND event_type = 'program_instruction'
+         GROUP BY program_id",
+        "program_id",
+    ),
+    (
+        "mv_top_tokens",
+        "SELECT
+            raw_payload->>'mint' as token_mint,
+            COUNT(*)::bigint as transfer_count,
+            COUNT(DISTINCT raw_payload->'to')::bigint as unique_wallets
+         FROM fact_transactions
+         WHERE event_type = 'token_transfer'
+         AND raw_payload->>'mint' IS NOT NULL
+         GROUP BY raw_payload->>'mint'",
+        "token_mint",
+    ),
+    (
+        "mv_program_trends",
+        "SELECT program_id, DATE(block_time) as date, COUNT(*)::bigint as transaction_count
+         FROM fact_transactions
+         WHERE program_id IS NOT NULL
+         AND event_type = 'program_instruction'
+         GROUP BY program_id, DATE(block_time)",
+        "program_id, date",
+    ),
+];
+
+/// Create any missing materialized views (populated on creation)
+async fn create_materialized_views(pool: &PgPool) -> Result<()> {
+    for (name, query, unique_columns) in MATERIALIZED_VIEWS {
+        let view = quote_ident(name)?;
+        tracing::info!("Creating materialized view {}", name);
+
+        sqlx::query(&format!("CREATE MATERIALIZED VIEW IF NOT EXISTS {} AS {}", view, query))
+            .execute(pool)
+            .await
+            .map_err(|e| db_error(e, &format!("Failed to create {}", name)))?;
+
+        let index = quote_ident(&format!("idx_{}_unique", name))?;
+        sqlx::query(&format!("CREATE UNIQUE INDEX IF NOT EXISTS {} ON {} ({})", index, view, unique_columns))
+            .execute(pool)
+            .await
+            .map_err(|e| db_error(e, &format!("Failed to index {}", name)))?;
+    }
+
+    Ok(())
+}
+
+/// Refresh every materialized view without locking out readers
+async fn refresh_materialized_views(pool: &PgPool) -> Result<()> {
+    for (name, _, _) in MATERIALIZED_VIEWS {
+        tracing::info!("Refreshing materialized view {}", name);
+        sqlx::query(&format!("REFRESH MATERIALIZED VIEW CONCURRENTLY {}", quote_ident(name)?))
+            .execute(pool)
+            .await
+            .map_err(|e| db_error(e, &format!("Failed to refresh {} (create it with --views first)", name)))?;
+    }
+
+    Ok(())
+}
+
+/// Read the freshly computed analytics tables into a single report
+async fn load_report(pool: &PgPool) -> Result<AnalyticsReport> {
+    let transaction_volume = sqlx::query(
+        "SELECT period_type, transaction_count FROM analytics_transaction_volume ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to read transaction volume"))?
+    .into_iter()
+    .map(|row| PeriodVolume {
+        period_type: row.get(0),
+        transaction_count: row.get(1),
+    })
+    .collect();
+
+    let top_programs = sqlx::query(
+        "SELECT program_id, transaction_count, unique_wallets, last_seen
+         FROM analytics_active_programs
+         ORDER BY transaction_count DESC"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to read active programs"))?
+    .into_iter()
+    .map(|row| ProgramStats {
+        program_id: row.get(0),
+        transaction_count: row.get(1),
+        unique_wallets: row.get(2),
+        last_seen: row.get(3),
+    })
+    .collect();
+
+    let top_tokens = sqlx::query(
+        "SELECT a.token_mint, a.transfer_count, a.unique_wallets, t.decimals, t.supply::text
+         FROM analytics_top_tokens a
+         LEFT JOIN tokens t ON t.mint = a.token_mint
+         ORDER BY a.transfer_count DESC"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to read top tokens"))?
+    .into_iter()
+    .map(|row| TokenStats {
+        token_mint: row.get(0),
+        transfer_count: row.get(1),
+        unique_wallets: row.get(2),
+        decimals: row.get(3),
+        supply: row.get(4),
+    })
+    .collect();
+
+    let failed_transactions = sqlx::query(
+        "SELECT total_failed, failure_rate::float8 FROM analytics_failed_transactions WHERE id = 1"
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to read failed transactions"))?
+    .map(|row| FailureStats {
+        total_failed: row.get(0),
+        failure_rate: row.get(1),
+    });
+
+    let wallet_activity = sqlx::query(
+        "SELECT total_unique_wallets, active_today, active_this_week, approximate
+         FROM analytics_wallet_activity WHERE id = 1"
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to read wallet activity"))?
+    .map(|row| WalletActivityStats {
+        total_unique_wallets: row.get(0),
+        active_today: row.get(1),
+        active_this_week: row.get(2),
+        approximate: row.get(3),
+    });
+
+    Ok(AnalyticsReport {
+        transaction_volume,
+        top_programs,
+        top_tokens,
+        failed_transactions,
+        wallet_activity,
+    })
+}
+
 async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
-    // Migrate existing tables if they have wrong timestamp types
-    migrate_timestamp_columns(pool).await?;
+    // Converts analytics tables left from before TIMESTAMPTZ (migration 015)
+    run_migrations(pool).await?;
+
+    // Token dimension joined into the top-tokens report
+    create_tokens_table(pool).await?;
     
     // Transaction volume summary
     sqlx::query(
@@ -49,7 +457,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create transaction volume table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create transaction volume table"))?;
 
     // Hourly volume
     sqlx::query(
@@ -66,7 +474,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create hourly volume table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create hourly volume table"))?;
 
     // Active programs
     sqlx::query(
@@ -84,13 +492,13 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create active programs table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create active programs table"))?;
 
     // Token transfer stats
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS analytics_token_transfers (
-            id SERIAL PRIMARY KEY,
+            id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1), -- Singleton row
             total_transfers BIGINT NOT NULL,
             unique_tokens BIGINT NOT NULL,
             unique_senders BIGINT NOT NULL,
@@ -101,7 +509,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create token transfers table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create token transfers table"))?;
 
     // Top tokens
     sqlx::query(
@@ -118,13 +526,13 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create top tokens table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create top tokens table"))?;
 
     // Failed transactions
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS analytics_failed_transactions (
-            id SERIAL PRIMARY KEY,
+            id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1), -- Singleton row
             total_failed BIGINT NOT NULL,
             failure_rate NUMERIC(5,2) NOT NULL,
             updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
@@ -133,7 +541,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create failed transactions table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create failed transactions table"))?;
 
     // Top errors
     sqlx::query(
@@ -149,13 +557,13 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create top errors table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create top errors table"))?;
 
     // Wallet activity
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS analytics_wallet_activity (
-            id SERIAL PRIMARY KEY,
+            id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1), -- Singleton row
             total_unique_wallets BIGINT NOT NULL,
             active_today BIGINT NOT NULL,
             active_this_week BIGINT NOT NULL,
@@ -165,7 +573,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create wallet activity table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create wallet activity table"))?;
 
     // Top wallets
     sqlx::query(
@@ -183,7 +591,7 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create top wallets table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create top wallets table"))?;
 
     // Program trends (daily volume)
     sqlx::query(
@@ -200,7 +608,68 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     )
     .execute(pool)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to create program trends table: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to create program trends table"))?;
+
+    // Rewards by type per epoch
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS analytics_rewards (
+            id SERIAL PRIMARY KEY,
+            epoch BIGINT NOT NULL,
+            reward_type TEXT NOT NULL,
+            reward_count BIGINT NOT NULL,
+            total_lamports BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            UNIQUE(epoch, reward_type)
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to create rewards table"))?;
+
+    // Block producers ranked by transactions included
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS analytics_leaders (
+            leader TEXT PRIMARY KEY,
+            block_count BIGINT NOT NULL,
+            transaction_count BIGINT NOT NULL,
+            total_fees BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to create leaders table"))?;
+
+    // Blockhashes shared by the most transactions
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS analytics_blockhash_reuse (
+            recent_blockhash TEXT PRIMARY KEY,
+            transaction_count BIGINT NOT NULL,
+            first_slot BIGINT NOT NULL,
+            last_slot BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to create blockhash reuse table"))?;
+
+    // Tables created before `--approximate` existed lack the flag
+    for table in ["analytics_token_transfers", "analytics_wallet_activity"] {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS approximate BOOLEAN NOT NULL DEFAULT FALSE",
+            table
+        ))
+        .execute(pool)
+        .await
+        .map_err(|e| db_error(e, &format!("Failed to add approximate column to {}", table)))?;
+    }
 
     // Create indexes
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_analytics_hourly_date ON analytics_hourly_volume(date, hour)")
@@ -217,43 +686,91 @@ async fn create_analytics_tables(pool: &PgPool) -> Result<()> {
     Ok(())
 }
 
-async fn migrate_timestamp_columns(pool: &PgPool) -> Result<()> {
-    // Drop and recreate tables with correct types (simplest approach)
-    // This will lose existing data, but analytics are recomputed anyway
-    let drop_queries = vec![
-        "DROP TABLE IF EXISTS analytics_transaction_volume CASCADE",
-        "DROP TABLE IF EXISTS analytics_hourly_volume CASCADE",
-        "DROP TABLE IF EXISTS analytics_active_programs CASCADE",
-        "DROP TABLE IF EXISTS analytics_token_transfers CASCADE",
-        "DROP TABLE IF EXISTS analytics_top_tokens CASCADE",
-        "DROP TABLE IF EXISTS analytics_failed_transactions CASCADE",
-        "DROP TABLE IF EXISTS analytics_top_errors CASCADE",
-        "DROP TABLE IF EXISTS analytics_wallet_activity CASCADE",
-        "DROP TABLE IF EXISTS analytics_top_wallets CASCADE",
-        "DROP TABLE IF EXISTS analytics_program_trends CASCADE",
-    ];
+/// Delete all rows from an analytics table before recomputing it
+async fn clear_table(conn: &mut PgConnection, table: &str) -> Result<()> {
+    let query = format!("DELETE FROM {}", quote_ident(table)?);
+    sqlx::query(&query)
+        .execute(conn)
+        .await
+        .map_err(|e| db_error(e, &format!("Failed to clear {}", table)))?;
+    Ok(())
+}
 
-    for query in drop_queries {
-        sqlx::query(query).execute(pool).await.ok();
+/// `COUNT(*)` of fact_transactions matching `filter`. With a sample fraction,
+/// counts a `TABLESAMPLE SYSTEM` sample and scales it up.
+async fn count_rows(conn: &mut PgConnection, filter: &str, sample_fraction: Option<f64>) -> sqlx::Result<i64> {
+    match sample_fraction {
+        None => {
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM fact_transactions WHERE {}", filter))
+                .fetch_one(conn)
+                .await
+        }
+        Some(fraction) => {
+            let sampled: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM fact_transactions TABLESAMPLE SYSTEM ($1::real) WHERE {}",
+                filter
+            ))
+            .bind(fraction * 100.0)
+            .fetch_one(conn)
+            .await?;
+            Ok((sampled as f64 / fraction).round() as i64)
+        }
     }
+}
 
-    Ok(())
+/// `COUNT(DISTINCT expr)` over fact_transactions matching `filter`.
+///
+/// With a sample fraction `f`, uses the GEE estimator on a `TABLESAMPLE`:
+/// values seen once in the sample are scaled by `sqrt(1/f)`, values seen
+/// more often are counted as-is. Plain scaling by `1/f` would badly
+/// overcount values that repeat (e.g. busy wallets).
+async fn count_distinct(
+    conn: &mut PgConnection,
+    expr: &str,
+    filter: &str,
+    sample_fraction: Option<f64>,
+) -> sqlx::Result<i64> {
+    match sample_fraction {
+        None => {
+            sqlx::query_scalar(&format!(
+                "SELECT COUNT(DISTINCT {}) FROM fact_transactions WHERE {}",
+                expr, filter
+            ))
+            .fetch_one(conn)
+            .await
+        }
+        Some(fraction) => {
+            let (singletons, repeated): (i64, i64) = sqlx::query_as(&format!(
+                "SELECT COUNT(*) FILTER (WHERE n = 1), COUNT(*) FILTER (WHERE n > 1)
+                 FROM (
+                     SELECT {} AS value, COUNT(*) AS n
+                     FROM fact_transactions TABLESAMPLE SYSTEM ($1::real)
+                     WHERE {}
+                     GROUP BY 1
+                 ) sampled
+                 WHERE value IS NOT NULL",
+                expr, filter
+            ))
+            .bind(fraction * 100.0)
+            .fetch_one(conn)
+            .await?;
+            Ok(((1.0 / fraction).sqrt() * singletons as f64).round() as i64 + repeated)
+        }
+    }
 }
 
-async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
+async fn compute_and_store_transaction_volume(conn: &mut PgConnection) -> Result<()> {
     // Clear existing data
-    sqlx::query("DELETE FROM analytics_transaction_volume")
-        .execute(pool).await.ok();
-    sqlx::query("DELETE FROM analytics_hourly_volume")
-        .execute(pool).await.ok();
+    clear_table(&mut *conn, "analytics_transaction_volume").await?;
+    clear_table(&mut *conn, "analytics_hourly_volume").await?;
 
     // Total
     let total: i64 = sqlx::query_scalar(
         "SELECT COUNT(*) FROM fact_transactions WHERE event_type = 'transaction'"
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute total: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute total"))?;
 
     sqlx::query(
         "INSERT INTO analytics_transaction_volume (period_type, transaction_count) 
@@ -261,9 +778,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          ON CONFLICT (period_type) DO UPDATE SET transaction_count = EXCLUDED.transaction_count, updated_at = NOW()"
     )
     .bind(total)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert total: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert total"))?;
 
     // Today
     let today: i64 = sqlx::query_scalar(
@@ -271,9 +788,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          WHERE event_type = 'transaction' 
          AND DATE(block_time) = CURRENT_DATE"
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute today: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute today"))?;
 
     sqlx::query(
         "INSERT INTO analytics_transaction_volume (period_type, transaction_count) 
@@ -281,9 +798,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          ON CONFLICT (period_type) DO UPDATE SET transaction_count = EXCLUDED.transaction_count, updated_at = NOW()"
     )
     .bind(today)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert today: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert today"))?;
 
     // This week
     let this_week: i64 = sqlx::query_scalar(
@@ -291,9 +808,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          WHERE event_type = 'transaction' 
          AND block_time >= CURRENT_DATE - INTERVAL '7 days'"
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute week: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute week"))?;
 
     sqlx::query(
         "INSERT INTO analytics_transaction_volume (period_type, transaction_count) 
@@ -301,9 +818,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          ON CONFLICT (period_type) DO UPDATE SET transaction_count = EXCLUDED.transaction_count, updated_at = NOW()"
     )
     .bind(this_week)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert week: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert week"))?;
 
     // This month
     let this_month: i64 = sqlx::query_scalar(
@@ -311,9 +828,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          WHERE event_type = 'transaction' 
          AND block_time >= CURRENT_DATE - INTERVAL '30 days'"
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute month: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute month"))?;
 
     sqlx::query(
         "INSERT INTO analytics_transaction_volume (period_type, transaction_count) 
@@ -321,9 +838,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          ON CONFLICT (period_type) DO UPDATE SET transaction_count = EXCLUDED.transaction_count, updated_at = NOW()"
     )
     .bind(this_month)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert month: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert month"))?;
 
     // Hourly volume (last 24 hours)
     let hourly_rows = sqlx::query(
@@ -335,9 +852,9 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
          AND block_time >= NOW() - INTERVAL '24 hours'
          GROUP BY DATE(block_time), EXTRACT(HOUR FROM block_time)"
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute hourly: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute hourly"))?;
 
     for row in hourly_rows {
         sqlx::query(
@@ -348,17 +865,16 @@ async fn compute_and_store_transaction_volume(pool: &PgPool) -> Result<()> {
         .bind(row.get::<NaiveDate, _>(0))
         .bind(row.get::<i32, _>(1))
         .bind(row.get::<i64, _>(2))
-        .execute(pool)
+        .execute(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to insert hourly: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to insert hourly"))?;
     }
 
     Ok(())
 }
 
-async fn compute_and_store_active_programs(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM analytics_active_programs")
-        .execute(pool).await.ok();
+async fn compute_and_store_active_programs(conn: &mut PgConnection) -> Result<()> {
+    clear_table(&mut *conn, "analytics_active_programs").await?;
 
     let rows = sqlx::query(
         "SELECT 
@@ -373,9 +889,9 @@ async fn compute_and_store_active_programs(pool: &PgPool) -> Result<()> {
          ORDER BY tx_count DESC
          LIMIT 50"
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute active programs: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute active programs"))?;
 
     for row in rows {
         sqlx::query(
@@ -391,69 +907,46 @@ async fn compute_and_store_active_programs(pool: &PgPool) -> Result<()> {
         .bind(row.get::<i64, _>(1))
         .bind(row.get::<i64, _>(2))
         .bind(row.get::<DateTime<Utc>, _>(3))
-        .execute(pool)
+        .execute(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to insert program: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to insert program"))?;
     }
 
     Ok(())
 }
 
-async fn compute_and_store_token_transfers(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM analytics_token_transfers").execute(pool).await.ok();
-    sqlx::query("DELETE FROM analytics_top_tokens").execute(pool).await.ok();
-
-    let total: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM fact_transactions WHERE event_type = 'token_transfer'"
-    )
-    .fetch_one(pool)
-    .await
-    .unwrap_or(0);
-
-    let unique_tokens: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT raw_payload->'mint') 
-         FROM fact_transactions 
-         WHERE event_type = 'token_transfer'"
-    )
-    .fetch_one(pool)
-    .await
-    .unwrap_or(0);
+async fn compute_and_store_token_transfers(conn: &mut PgConnection, sample_fraction: Option<f64>) -> Result<()> {
+    clear_table(&mut *conn, "analytics_top_tokens").await?;
 
-    let unique_senders: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT raw_payload->'from') 
-         FROM fact_transactions 
-         WHERE event_type = 'token_transfer'"
-    )
-    .fetch_one(pool)
-    .await
-    .unwrap_or(0);
-
-    let unique_receivers: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT raw_payload->'to') 
-         FROM fact_transactions 
-         WHERE event_type = 'token_transfer'"
-    )
-    .fetch_one(pool)
-    .await
-    .unwrap_or(0);
+    let filter = "event_type = 'token_transfer'";
+    let total = count_rows(&mut *conn, filter, sample_fraction).await
+        .map_err(|e| db_error(e, "Failed to compute total transfers"))?;
+    let unique_tokens = count_distinct(&mut *conn, "raw_payload->'mint'", filter, sample_fraction).await
+        .map_err(|e| db_error(e, "Failed to compute unique tokens"))?;
+    let unique_senders = count_distinct(&mut *conn, "raw_payload->'from'", filter, sample_fraction).await
+        .map_err(|e| db_error(e, "Failed to compute unique senders"))?;
+    let unique_receivers = count_distinct(&mut *conn, "raw_payload->'to'", filter, sample_fraction).await
+        .map_err(|e| db_error(e, "Failed to compute unique receivers"))?;
 
     sqlx::query(
-        "INSERT INTO analytics_token_transfers (total_transfers, unique_tokens, unique_senders, unique_receivers) 
-         VALUES ($1, $2, $3, $4)
+        "INSERT INTO analytics_token_transfers (id, total_transfers, unique_tokens, unique_senders, unique_receivers, approximate) 
+         VALUES (1, $1, $2, $3, $4, $5)
          ON CONFLICT (id) DO UPDATE SET 
             total_transfers = EXCLUDED.total_transfers,
             unique_tokens = EXCLUDED.unique_tokens,
             unique_senders = EXCLUDED.unique_senders,
             unique_receivers = EXCLUDED.unique_receivers,
+            approximate = EXCLUDED.approximate,
             updated_at = NOW()"
     )
     .bind(total)
     .bind(unique_tokens)
     .bind(unique_senders)
     .bind(unique_receivers)
-    .execute(pool)
+    .bind(sample_fraction.is_some())
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert token transfers: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert token transfers"))?;
 
     // Top tokens
     let token_rows = sqlx::query(
@@ -468,9 +961,9 @@ async fn compute_and_store_token_transfers(pool: &PgPool) -> Result<()> {
          ORDER BY transfer_count DESC
          LIMIT 20"
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute top tokens: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute top tokens"))?;
 
     for row in token_rows {
         sqlx::query(
@@ -484,33 +977,47 @@ async fn compute_and_store_token_transfers(pool: &PgPool) -> Result<()> {
         .bind(row.get::<Option<String>, _>(0).unwrap_or_else(|| "unknown".to_string()))
         .bind(row.get::<i64, _>(1))
         .bind(row.get::<i64, _>(2))
-        .execute(pool)
+        .execute(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to insert token: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to insert token"))?;
     }
 
     Ok(())
 }
 
-async fn compute_and_store_failed_transactions(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM analytics_failed_transactions").execute(pool).await.ok();
-    sqlx::query("DELETE FROM analytics_top_errors").execute(pool).await.ok();
+/// Failed transaction rows. `success` is set on rows written since it was
+/// added; older rows fall back to `meta.err`, which is JSON `null` (not SQL
+/// NULL) on success.
+const FAILED_TRANSACTION: &str =
+    "COALESCE(success, COALESCE(jsonb_typeof(raw_payload->'meta'->'err'), 'null') = 'null') = false";
 
-    let total_failed: i64 = sqlx::query_scalar(
+/// Error name from `meta.err`: a bare string (`"AccountInUse"`) or the
+/// variant key of an object (`{"InstructionError": [...]}`)
+const ERROR_KIND: &str = "CASE jsonb_typeof(raw_payload->'meta'->'err')
+                WHEN 'string' THEN raw_payload->'meta'->>'err'
+                WHEN 'object' THEN (SELECT k FROM jsonb_object_keys(raw_payload->'meta'->'err') k LIMIT 1)
+                ELSE 'unknown'
+            END";
+
+async fn compute_and_store_failed_transactions(conn: &mut PgConnection) -> Result<()> {
+    clear_table(&mut *conn, "analytics_top_errors").await?;
+
+    let total_failed: i64 = sqlx::query_scalar(&format!(
         "SELECT COUNT(*) FROM fact_transactions 
          WHERE event_type = 'transaction' 
-         AND raw_payload->'meta'->'err' IS NOT NULL"
-    )
-    .fetch_one(pool)
+         AND {}",
+        FAILED_TRANSACTION
+    ))
+    .fetch_one(&mut *conn)
     .await
-    .unwrap_or(0);
+    .map_err(|e| db_error(e, "Failed to compute total failed"))?;
 
     let total: i64 = sqlx::query_scalar(
         "SELECT COUNT(*) FROM fact_transactions WHERE event_type = 'transaction'"
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await
-    .unwrap_or(1);
+    .map_err(|e| db_error(e, "Failed to compute total"))?;
 
     let failure_rate = if total > 0 {
         (total_failed as f64 / total as f64) * 100.0
@@ -519,8 +1026,8 @@ async fn compute_and_store_failed_transactions(pool: &PgPool) -> Result<()> {
     };
 
     sqlx::query(
-        "INSERT INTO analytics_failed_transactions (total_failed, failure_rate) 
-         VALUES ($1, $2)
+        "INSERT INTO analytics_failed_transactions (id, total_failed, failure_rate) 
+         VALUES (1, $1, $2)
          ON CONFLICT (id) DO UPDATE SET 
             total_failed = EXCLUDED.total_failed,
             failure_rate = EXCLUDED.failure_rate,
@@ -528,25 +1035,26 @@ async fn compute_and_store_failed_transactions(pool: &PgPool) -> Result<()> {
     )
     .bind(total_failed)
     .bind(failure_rate)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert failed transactions: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert failed transactions"))?;
 
     // Top errors
-    let error_rows = sqlx::query(
+    let error_rows = sqlx::query(&format!(
         "SELECT 
-            COALESCE(raw_payload->'meta'->'err'->>'type', 'unknown') as error_type,
+            {} as error_type,
             COUNT(*)::bigint as count
          FROM fact_transactions 
          WHERE event_type = 'transaction' 
-         AND raw_payload->'meta'->'err' IS NOT NULL
-         GROUP BY raw_payload->'meta'->'err'->>'type'
+         AND {}
+         GROUP BY 1
          ORDER BY count DESC
-         LIMIT 10"
-    )
-    .fetch_all(pool)
+         LIMIT 10",
+        ERROR_KIND, FAILED_TRANSACTION
+    ))
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute errors: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute errors"))?;
 
     for row in error_rows {
         sqlx::query(
@@ -558,62 +1066,56 @@ async fn compute_and_store_failed_transactions(pool: &PgPool) -> Result<()> {
         )
         .bind(row.get::<String, _>(0))
         .bind(row.get::<i64, _>(1))
-        .execute(pool)
+        .execute(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to insert error: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to insert error"))?;
     }
 
     Ok(())
 }
 
-async fn compute_and_store_wallet_activity(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM analytics_wallet_activity").execute(pool).await.ok();
-    sqlx::query("DELETE FROM analytics_top_wallets").execute(pool).await.ok();
+async fn compute_and_store_wallet_activity(conn: &mut PgConnection, sample_fraction: Option<f64>) -> Result<()> {
+    clear_table(&mut *conn, "analytics_top_wallets").await?;
 
-    let total_unique: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT (raw_payload->'transaction'->'message'->'accountKeys'->>0)) 
-         FROM fact_transactions
-         WHERE raw_payload->'transaction'->'message'->'accountKeys'->>0 IS NOT NULL"
-    )
-    .fetch_one(pool)
-    .await
-    .unwrap_or(0);
+    let wallet = "(raw_payload->'transaction'->'message'->'accountKeys'->>0)";
+    let has_wallet = "raw_payload->'transaction'->'message'->'accountKeys'->>0 IS NOT NULL";
 
-    let active_today: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT (raw_payload->'transaction'->'message'->'accountKeys'->>0)) 
-         FROM fact_transactions 
-         WHERE DATE(block_time) = CURRENT_DATE
-         AND raw_payload->'transaction'->'message'->'accountKeys'->>0 IS NOT NULL"
+    let total_unique = count_distinct(&mut *conn, wallet, has_wallet, sample_fraction).await
+        .map_err(|e| db_error(e, "Failed to compute unique wallets"))?;
+    let active_today = count_distinct(
+        &mut *conn,
+        wallet,
+        &format!("DATE(block_time) = CURRENT_DATE AND {}", has_wallet),
+        sample_fraction,
     )
-    .fetch_one(pool)
     .await
-    .unwrap_or(0);
-
-    let active_week: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT (raw_payload->'transaction'->'message'->'accountKeys'->>0)) 
-         FROM fact_transactions 
-         WHERE block_time >= CURRENT_DATE - INTERVAL '7 days'
-         AND raw_payload->'transaction'->'message'->'accountKeys'->>0 IS NOT NULL"
+    .map_err(|e| db_error(e, "Failed to compute active today"))?;
+    let active_week = count_distinct(
+        &mut *conn,
+        wallet,
+        &format!("block_time >= CURRENT_DATE - INTERVAL '7 days' AND {}", has_wallet),
+        sample_fraction,
     )
-    .fetch_one(pool)
     .await
-    .unwrap_or(0);
+    .map_err(|e| db_error(e, "Failed to compute active week"))?;
 
     sqlx::query(
-        "INSERT INTO analytics_wallet_activity (total_unique_wallets, active_today, active_this_week) 
-         VALUES ($1, $2, $3)
+        "INSERT INTO analytics_wallet_activity (id, total_unique_wallets, active_today, active_this_week, approximate) 
+         VALUES (1, $1, $2, $3, $4)
          ON CONFLICT (id) DO UPDATE SET 
             total_unique_wallets = EXCLUDED.total_unique_wallets,
             active_today = EXCLUDED.active_today,
             active_this_week = EXCLUDED.active_this_week,
+            approximate = EXCLUDED.approximate,
             updated_at = NOW()"
     )
     .bind(total_unique)
     .bind(active_today)
     .bind(active_week)
-    .execute(pool)
+    .bind(sample_fraction.is_some())
+    .execute(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to insert wallet activity: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to insert wallet activity"))?;
 
     // Top wallets
     let wallet_rows = sqlx::query(
@@ -628,9 +1130,9 @@ async fn compute_and_store_wallet_activity(pool: &PgPool) -> Result<()> {
          ORDER BY tx_count DESC
          LIMIT 20"
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute wallet activity: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute wallet activity"))?;
 
     for row in wallet_rows {
         sqlx::query(
@@ -646,16 +1148,16 @@ async fn compute_and_store_wallet_activity(pool: &PgPool) -> Result<()> {
         .bind(row.get::<i64, _>(1))
         .bind(row.get::<DateTime<Utc>, _>(2))
         .bind(row.get::<DateTime<Utc>, _>(3))
-        .execute(pool)
+        .execute(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to insert wallet: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to insert wallet"))?;
     }
 
     Ok(())
 }
 
-async fn compute_and_store_program_trends(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM analytics_program_trends").execute(pool).await.ok();
+async fn compute_and_store_program_trends(conn: &mut PgConnection) -> Result<()> {
+    clear_table(&mut *conn, "analytics_program_trends").await?;
 
     // Get top 10 programs
     let program_rows = sqlx::query(
@@ -667,9 +1169,9 @@ async fn compute_and_store_program_trends(pool: &PgPool) -> Result<()> {
          ORDER BY tx_count DESC
          LIMIT 10"
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
-    .map_err(|e| ETLError::Database(format!("Failed to compute program trends: {}", e)))?;
+    .map_err(|e| db_error(e, "Failed to compute program trends"))?;
 
     for row in program_rows {
         let program_id: String = row.get(0);
@@ -687,9 +1189,9 @@ async fn compute_and_store_program_trends(pool: &PgPool) -> Result<()> {
              ORDER BY date"
         )
         .bind(&program_id)
-        .fetch_all(pool)
+        .fetch_all(&mut *conn)
         .await
-        .map_err(|e| ETLError::Database(format!("Failed to compute daily volume: {}", e)))?;
+        .map_err(|e| db_error(e, "Failed to compute daily volume"))?;
 
         for daily_row in daily_rows {
             sqlx::query(
@@ -702,11 +1204,298 @@ async fn compute_and_store_program_trends(pool: &PgPool) -> Result<()> {
             .bind(&program_id)
             .bind(daily_row.get::<NaiveDate, _>(0))
             .bind(daily_row.get::<i64, _>(1))
-            .execute(pool)
+            .execute(&mut *conn)
             .await
-            .map_err(|e| ETLError::Database(format!("Failed to insert trend: {}", e)))?;
+            .map_err(|e| db_error(e, "Failed to insert trend"))?;
         }
     }
 
     Ok(())
 }
+
+async fn compute_and_store_rewards(conn: &mut PgConnection, schedule: EpochSchedule) -> Result<()> {
+    clear_table(&mut *conn, "analytics_rewards").await?;
+
+    let rows = sqlx::query(&format!(
+        "SELECT 
+            {} as epoch,
+            COALESCE(raw_payload->>'reward_type', 'unknown') as reward_type,
+            COUNT(*)::bigint as reward_count,
+            COALESCE(SUM((raw_payload->>'lamports')::bigint), 0)::bigint as total_lamports
+         FROM fact_transactions 
+         WHERE event_type = 'reward'
+         GROUP BY 1, 2
+         ORDER BY 1, 2",
+        schedule.sql_epoch_expr("slot")
+    ))
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| db_error(e, "Failed to compute rewards"))?;
+
+    for row in rows {
+        sqlx::query(
+            "INSERT INTO analytics_rewards (epoch, reward_type, reward_count, total_lamports) 
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (epoch, reward_type) DO UPDATE SET 
+                reward_count = EXCLUDED.reward_count,
+                total_lamports = EXCLUDED.total_lamports,
+                updated_at = NOW()"
+        )
+        .bind(row.get::<i64, _>(0))
+        .bind(row.get::<String, _>(1))
+        .bind(row.get::<i64, _>(2))
+        .bind(row.get::<i64, _>(3))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| db_error(e, "Failed to insert rewards"))?;
+    }
+
+    Ok(())
+}
+
+/// Rank block producers by the transactions (and fees) in their blocks.
+/// Only rows ingested with `ETL_RESOLVE_LEADERS` carry a leader.
+async fn compute_and_store_leaders(conn: &mut PgConnection) -> Result<()> {
+    clear_table(&mut *conn, "analytics_leaders").await?;
+
+    let rows = sqlx::query(
+        "SELECT 
+            leader,
+            COUNT(DISTINCT slot)::bigint as block_count,
+            COUNT(*)::bigint as tx_count,
+            COALESCE(SUM((raw_payload->'meta'->>'fee')::bigint), 0)::bigint as total_fees
+         FROM fact_transactions 
+         WHERE event_type = 'transaction'
+         AND leader IS NOT NULL
+         GROUP BY leader
+         ORDER BY tx_count DESC
+         LIMIT 100"
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| db_error(e, "Failed to compute leaders"))?;
+
+    for row in rows {
+        sqlx::query(
+            "INSERT INTO analytics_leaders (leader, block_count, transaction_count, total_fees) 
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (leader) DO UPDATE SET 
+                block_count = EXCLUDED.block_count,
+                transaction_count = EXCLUDED.transaction_count,
+                total_fees = EXCLUDED.total_fees,
+                updated_at = NOW()"
+        )
+        .bind(row.get::<String, _>(0))
+        .bind(row.get::<i64, _>(1))
+        .bind(row.get::<i64, _>(2))
+        .bind(row.get::<i64, _>(3))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| db_error(e, "Failed to insert leader"))?;
+    }
+
+    Ok(())
+}
+
+/// Rank blockhashes by how many transactions were signed against them, to
+/// surface unusual reuse (bots, stuck retries). `first_slot`..`last_slot`
+/// shows how long a blockhash stayed in use. Rows stored before
+/// `recent_blockhash` existed fall back to the payload.
+async fn compute_and_store_blockhash_reuse(conn: &mut PgConnection) -> Result<()> {
+    clear_table(&mut *conn, "analytics_blockhash_reuse").await?;
+
+    let rows = sqlx::query(
+        "SELECT 
+            COALESCE(recent_blockhash, raw_payload->'transaction'->'message'->>'recentBlockhash') as blockhash,
+            COUNT(*)::bigint as tx_count,
+            MIN(slot)::bigint as first_slot,
+            MAX(slot)::bigint as last_slot
+         FROM fact_transactions 
+         WHERE event_type = 'transaction'
+         AND COALESCE(recent_blockhash, raw_payload->'transaction'->'message'->>'recentBlockhash') IS NOT NULL
+         GROUP BY 1
+         HAVING COUNT(*) > 1
+         ORDER BY tx_count DESC
+         LIMIT 100"
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| db_error(e, "Failed to compute blockhash reuse"))?;
+
+    for row in rows {
+        sqlx::query(
+            "INSERT INTO analytics_blockhash_reuse (recent_blockhash, transaction_count, first_slot, last_slot) 
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (recent_blockhash) DO UPDATE SET 
+                transaction_count = EXCLUDED.transaction_count,
+                first_slot = EXCLUDED.first_slot,
+                last_slot = EXCLUDED.last_slot,
+                updated_at = NOW()"
+        )
+        .bind(row.get::<String, _>(0))
+        .bind(row.get::<i64, _>(1))
+        .bind(row.get::<i64, _>(2))
+        .bind(row.get::<i64, _>(3))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| db_error(e, "Failed to insert blockhash reuse"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::parse_block;
+    use crate::test_support::{block, config, fresh_database, postgres_config, transaction};
+    use crate::warehouse::{PostgresWarehouse, Warehouse};
+    use serde_json::json;
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn statement_timeouts_are_told_apart_by_sqlstate() {
+        let pool = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+
+        sqlx::query("SET statement_timeout = '10ms'").execute(&mut *conn).await.unwrap();
+        let timeout = sqlx::query("SELECT pg_sleep(1)").execute(&mut *conn).await.unwrap_err();
+        assert!(matches!(db_error(timeout, "sleep"), ETLError::StatementTimeout(_)));
+
+        let missing = sqlx::query("SELECT * FROM no_such_table").execute(&mut *conn).await.unwrap_err();
+        assert!(matches!(db_error(missing, "select"), ETLError::Database(_)));
+    }
+
+    async fn row_count(pool: &PgPool, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table)).fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_runs_the_named_computation() {
+        let mut config = config();
+        config.warehouse = postgres_config(&fresh_database().await);
+        config.etl.epoch_schedule = Some(EpochSchedule::mainnet());
+
+        let mut tx = transaction("sig1", &["payer", "receiver", "ProgA"], json!([
+            { "programIdIndex": 2, "accounts": [0, 1], "data": "" }
+        ]));
+        tx["meta"]["postTokenBalances"] = json!([
+            { "accountIndex": 1, "mint": "MintA", "owner": "receiver", "uiTokenAmount": { "amount": "6", "decimals": 0 } },
+        ]);
+        let warehouse = PostgresWarehouse::new(config.warehouse.clone()).unwrap();
+        warehouse.insert_events(parse_block(&block(vec![tx]), 100).unwrap()).await.unwrap();
+
+        run_analytics(config.clone(), false, false, false, vec!["tokens".to_string()], false).await.unwrap();
+        let pool = connect_postgres(&config.warehouse).await.unwrap();
+        assert_eq!(row_count(&pool, "analytics_top_tokens").await, 1);
+        assert_eq!(row_count(&pool, "analytics_active_programs").await, 0);
+        assert_eq!(row_count(&pool, "analytics_program_trends").await, 0);
+
+        let unknown = run_analytics(config, false, false, false, vec!["nope".to_string()], false).await;
+        assert!(matches!(unknown, Err(ETLError::Config(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failing_computation_is_rolled_back_and_the_rest_still_run() {
+        let mut config = config();
+        config.warehouse = postgres_config(&fresh_database().await);
+        config.etl.epoch_schedule = Some(EpochSchedule::mainnet());
+        config.analytics.step_retries = 0;
+
+        let mut events = parse_block(&block(vec![transaction("sig1", &["payer", "ProgA"], json!([]))]), 100).unwrap();
+        events[0].leader = Some("validator".to_string());
+        let warehouse = PostgresWarehouse::new(config.warehouse.clone()).unwrap();
+        warehouse.insert_events(events).await.unwrap();
+        run_analytics(config.clone(), false, false, false, Vec::new(), false).await.unwrap();
+        let pool = connect_postgres(&config.warehouse).await.unwrap();
+        assert_eq!(row_count(&pool, "analytics_leaders").await, 1);
+
+        // Break only the leaders query, and add data the others will see
+        sqlx::query("ALTER TABLE fact_transactions RENAME COLUMN leader TO leader_renamed").execute(&pool).await.unwrap();
+        let more = parse_block(&block(vec![transaction("sig2", &["payer", "ProgB"], json!([]))]), 101).unwrap();
+        sqlx::query(
+            "INSERT INTO fact_transactions (event_id, slot, block_time, tx_signature, instruction_index, event_type, raw_payload) \
+             VALUES ($1, 101, NOW(), 'sig2', -1, 'transaction', $2)",
+        )
+        .bind(&more[0].event_id)
+        .bind(&more[0].raw_payload)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let err = run_analytics(config, false, false, false, Vec::new(), false).await.unwrap_err().to_string();
+        assert!(err.contains("Analytics computations failed: leaders (rerun with --only leaders)"), "{}", err);
+        // The failed computation kept its previous rows; the others were recomputed
+        assert_eq!(row_count(&pool, "analytics_leaders").await, 1);
+        let total: i64 = sqlx::query_scalar(
+            "SELECT transaction_count FROM analytics_transaction_volume WHERE period_type = 'total'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(total, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_timed_out_computation_fails_the_run_after_the_rest() {
+        let mut config = config();
+        config.warehouse = postgres_config(&fresh_database().await);
+        config.etl.epoch_schedule = Some(EpochSchedule::mainnet());
+        let warehouse = PostgresWarehouse::new(config.warehouse.clone()).unwrap();
+        let tx = transaction("sig1", &["payer", "ProgA"], json!([{ "programIdIndex": 1, "accounts": [0], "data": "" }]));
+        warehouse.insert_events(parse_block(&block(vec![tx]), 100).unwrap()).await.unwrap();
+        let only = vec!["volume".to_string(), "programs".to_string()];
+        run_analytics(config.clone(), false, false, false, only.clone(), false).await.unwrap();
+
+        // Hold the volume table so its computation waits out the statement timeout
+        let pool = connect_postgres(&config.warehouse).await.unwrap();
+        let mut lock = pool.begin().await.unwrap();
+        sqlx::query("LOCK TABLE analytics_transaction_volume IN ACCESS EXCLUSIVE MODE").execute(&mut *lock).await.unwrap();
+        sqlx::query("DELETE FROM analytics_active_programs").execute(&pool).await.unwrap();
+        config.analytics.query_timeout_seconds = 1;
+
+        let err = run_analytics(config, false, false, false, only, false).await.unwrap_err().to_string();
+        assert!(err.contains("Analytics computations failed: volume (timed out) (rerun with --only volume)"), "{}", err);
+        // The computation after it still ran
+        assert_eq!(row_count(&pool, "analytics_active_programs").await, 1);
+        lock.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn approximate_counts_stay_close_to_exact_ones() {
+        let pool = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+        // 200k transfers between 5k wallets, spread over a few thousand pages
+        sqlx::query(
+            r#"
+            INSERT INTO fact_transactions (event_id, slot, block_time, tx_signature, instruction_index, event_type, raw_payload)
+            SELECT 'e' || i, i / 100, NOW(), 'sig' || i, 0, 'token_transfer',
+                   jsonb_build_object('to', 'wallet' || (i % 5000), 'mint', 'mint' || (i % 7))
+            FROM generate_series(1, 200000) AS i
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("ANALYZE fact_transactions").execute(&pool).await.unwrap();
+
+        let filter = "event_type = 'token_transfer'";
+        let mut conn = pool.acquire().await.unwrap();
+        let exact_rows = count_rows(&mut conn, filter, None).await.unwrap();
+        let exact_wallets = count_distinct(&mut conn, "raw_payload->'to'", filter, None).await.unwrap();
+        let exact_mints = count_distinct(&mut conn, "raw_payload->'mint'", filter, None).await.unwrap();
+        assert_eq!((exact_rows, exact_wallets, exact_mints), (200_000, 5_000, 7));
+
+        let rows = count_rows(&mut conn, filter, Some(0.2)).await.unwrap();
+        let wallets = count_distinct(&mut conn, "raw_payload->'to'", filter, Some(0.2)).await.unwrap();
+        let mints = count_distinct(&mut conn, "raw_payload->'mint'", filter, Some(0.2)).await.unwrap();
+        let within = |estimate: i64, exact: i64| (estimate - exact).abs() as f64 <= exact as f64 * 0.25;
+        assert!(within(rows, exact_rows), "{} rows estimated", rows);
+        assert!(within(wallets, exact_wallets), "{} wallets estimated", wallets);
+        assert_eq!(mints, exact_mints);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::error::{ETLError, Result};
+use crate::events::CanonicalEvent;
+use apache_avro::types::{Record, Value as AvroValue};
+use apache_avro::{from_avro_datum, to_avro_datum, Schema, Writer};
+use chrono::DateTime;
+use std::sync::OnceLock;
+
+/// Avro schema for `CanonicalEvent`.
+///
+/// `raw_payload` is carried as a JSON-encoded string so the schema stays
+/// stable regardless of what the payload contains.
+const CANONICAL_EVENT_SCHEMA: &str = r#"
+{
+    "type": "record",
+    "name": "CanonicalEvent",
+    "namespace": "solana.etl",
+    "fields": [
+        {"name": "event_id", "type": "string"},
+        {"name": "slot", "type": "long"},
+        {"name": "block_time", "type": {"type": "long", "logicalType": "timestamp-micros"}},
+        {"name": "tx_signature", "type": "string"},
+        {"name": "transaction_index", "type": "long"},
+        {"name": "program_id", "type": ["null", "string"], "default": null},
+        {"name": "instruction_index", "type": "int"},
+        {"name": "event_type", "type": "string"},
+        {"name": "raw_payload", "type": "string"},
+        {"name": "accounts", "type": {"type": "array", "items": "string"}, "default": []},
+        {"name": "block_height", "type": ["null", "long"], "default": null},
+        {"name": "success", "type": ["null", "boolean"], "default": null},
+        {"name": "leader", "type": ["null", "string"], "default": null}
+    ]
+}
+"#;
+
+/// Parsed `CanonicalEvent` schema (e.g. for registering with a schema registry)
+pub fn schema() -> &'static Schema {
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::parse_str(CANONICAL_EVENT_SCHEMA).expect("Invalid CanonicalEvent Avro schema"))
+}
+
+/// Encode a single event as a bare Avro datum (no container header)
+pub fn to_avro(event: &CanonicalEvent) -> Result<Vec<u8>> {
+    let record = to_record(event)?;
+    Ok(to_avro_datum(schema(), record)?)
+}
+
+/// Decode a bare Avro datum produced by `to_avro`
+pub fn from_avro(bytes: &[u8]) -> Result<CanonicalEvent> {
+    let mut reader = bytes;
+    let value = from_avro_datum(schema(), &mut reader, None)?;
+    from_record(value)
+}
+
+/// Encode a batch of events as an Avro object container file (schema embedded)
+pub fn write_batch(events: &[CanonicalEvent]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new(schema(), Vec::new());
+    for event in events {
+        writer.append(to_record(event)?)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+fn to_record(event: &CanonicalEvent) -> Result<Record<'static>> {
+    let mut record = Record::new(schema())
+        .ok_or_else(|| ETLError::Parse("CanonicalEvent Avro schema is not a record".to_string()))?;
+
+    record.put("event_id", event.event_id.clone());
+    record.put("slot", event.slot as i64);
+    record.put("block_time", AvroValue::TimestampMicros(event.block_time.timestamp_micros()));
+    record.put("tx_signature", event.tx_signature.clone());
+    record.put("transaction_index", event.transaction_index as i64);
+    record.put("program_id", event.program_id.clone());
+    record.put("instruction_index", event.instruction_index);
+    record.put("event_type", event.event_type.clone());
+    record.put("raw_payload", serde_json::to_string(&event.raw_payload)?);
+    record.put(
+        "accounts",
+        AvroValue::Array(event.accounts.iter().cloned().map(AvroValue::String).collect()),
+    );
+    record.put("block_height", event.block_height.map(|h| h as i64));
+    record.put("success", event.success);
+    record.put("leader", event.leader.clone());
+
+    Ok(record)
+}
+
+fn from_record(value: AvroValue) -> Result<CanonicalEvent> {
+    let fields = match value {
+        AvroValue::Record(fields) => fields,
+        other => return Err(ETLError::Parse(format!("Expected Avro record, got {:?}", other))),
+    };
+
+    let mut event_id = None;
+    let mut slot = None;
+    let mut block_time = None;
+    let mut tx_signature = None;
+    let mut transaction_index = 0;
+    let mut program_id = None;
+    let mut instruction_index = None;
+    let mut event_type = None;
+    let mut raw_payload = None;
+    let mut accounts = Vec::new();
+    let mut block_height = None;
+    let mut success = None;
+    let mut leader = None;
+
+    for (name, value) in fields {
+        match (name.as_str(), value) {
+            ("event_id", AvroValue::String(s)) => event_id = Some(s),
+            ("slot", AvroValue::Long(n)) => slot = Some(n as u64),
+            ("block_time", AvroValue::TimestampMicros(micros)) => {
+                block_time = DateTime::from_timestamp_micros(micros);
+            }
+            ("tx_signature", AvroValue::String(s)) => tx_signature = Some(s),
+            ("transaction_index", AvroValue::Long(n)) => transaction_index = n as u32,
+            ("program_id", AvroValue::Union(_, inner)) => {
+                if let AvroValue::String(s) = *inner {
+                    program_id = Some(s);
+                }
+            }
+            ("instruction_index", AvroValue::Int(n)) => instruction_index = Some(n),
+            ("event_type", AvroValue::String(s)) => event_type = Some(s),
+            ("raw_payload", AvroValue::String(s)) => raw_payload = Some(serde_json::from_str(&s)?),
+            ("accounts", AvroValue::Array(items)) => {
+                accounts = items
+                    .into_iter()
+                    .filter_map(|item| match item {
+                        AvroValue::String(s) => Some(s),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            ("block_height", AvroValue::Union(_, inner)) => {
+                if let AvroValue::Long(n) = *inner {
+                    block_height = Some(n as u64);
+                }
+            }
+            ("success", AvroValue::Union(_, inner)) => {
+                if let AvroValue::Boolean(b) = *inner {
+                    success = Some(b);
+                }
+            }
+            ("leader", AvroValue::Union(_, inner)) => {
+                if let AvroValue::String(s) = *inner {
+                    leader = Some(s);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let missing = |field: &str| ETLError::Parse(format!("Avro record missing {}", field));
+    Ok(CanonicalEvent {
+        event_id: event_id.ok_or_else(|| missing("event_id"))?,
+        slot: slot.ok_or_else(|| missing("slot"))?,
+        block_time: block_time.ok_or_else(|| missing("block_time"))?,
+        tx_signature: tx_signature.ok_or_else(|| missing("tx_signature"))?,
+        transaction_index,
+        program_id,
+        instruction_index: instruction_index.ok_or_else(|| missing("instruction_index"))?,
+        event_type: event_type.ok_or_else(|| missing("event_type"))?,
+        raw_payload: raw_payload.ok_or_else(|| missing("raw_payload"))?,
+        accounts,
+        block_height,
+        success,
+        leader,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::Reader;
+    use serde_json::{json, Value};
+
+    fn event() -> CanonicalEvent {
+        let mut event = CanonicalEvent::new(
+            250_000_000,
+            DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            "sig1".to_string(),
+            Some("Prog111".to_string()),
+            3,
+            "program_instruction".to_string(),
+            json!({ "data": "3Bxs", "nested": { "lamports": u64::MAX } }),
+        );
+        event.transaction_index = 7;
+        event.accounts = vec!["payer".to_string(), "receiver".to_string()];
+        event.block_height = Some(230_000_000);
+        event.success = Some(false);
+        event.leader = Some("validator".to_string());
+        event
+    }
+
+    fn as_json(event: &CanonicalEvent) -> Value {
+        serde_json::to_value(event).unwrap()
+    }
+
+    #[test]
+    fn datum_round_trips_every_field() {
+        let event = event();
+        assert_eq!(as_json(&from_avro(&to_avro(&event).unwrap()).unwrap()), as_json(&event));
+    }
+
+    #[test]
+    fn datum_round_trips_absent_optional_fields() {
+        let mut event = event();
+        event.program_id = None;
+        event.accounts.clear();
+        event.block_height = None;
+        event.success = None;
+        event.leader = None;
+        assert_eq!(as_json(&from_avro(&to_avro(&event).unwrap()).unwrap()), as_json(&event));
+    }
+
+    #[test]
+    fn batch_is_a_readable_container_file() {
+        let events = vec![event(), event()];
+        let bytes = write_batch(&events).unwrap();
+
+        let read: Vec<CanonicalEvent> = Reader::new(&bytes[..])
+            .unwrap()
+            .map(|value| from_record(value.unwrap()).unwrap())
+            .collect();
+        assert_eq!(read.len(), 2);
+        assert_eq!(as_json(&read[1]), as_json(&events[1]));
+    }
+}
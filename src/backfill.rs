@@ -1,22 +1,116 @@
-use crate::config::Config;
+use crate::block_source::{create_archive_source, BlockSource};
+use crate::config::{AlchemyConfig, Config};
 use crate::error::{ETLError, Result};
-use crate::parsers::{flatten_instructions, parse_block};
-use crate::rpc::AlchemyRPCClient;
-use crate::warehouse::Warehouse;
-use std::sync::Arc;
-use tokio::sync::Semaphore;
+use crate::leaders::LeaderSchedules;
+use crate::parsers::{
+    aggregate_instructions, drop_failed_effects, flatten_instructions, limit_payload_size, retain_event_types, widen_transactions,
+    ParseOptions, ParsePool, ParseWarning,
+};
+use crate::rpc::{AlchemyRPCClient, EndpointRateLimits};
+use crate::stats::{BackfillSummary, BatchThreshold, IngestionWindow};
+use crate::warehouse::{record_parse_warnings, DerivedRows, Progress, Warehouse};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use tracing::{info, warn};
 
 /// Run backfill for slot range
+///
+/// Chunks are written to the warehouse job queue up front and workers claim
+/// them one at a time, so a crashed run can be picked up again with `resume`
+/// (and, with `ETL_BACKFILL_SHARED_QUEUE` on Postgres, several machines can
+/// share one backfill).
+///
+/// With `fail_fast`, the first failed chunk (including a block or transaction
+/// that fails to parse) stops all workers and is returned as the error;
+/// otherwise failed chunks are logged, marked failed and skipped.
+///
+/// Slots listed in `processed_manifest` (see `ProcessedManifest`) are treated
+/// as already processed and never fetched.
+///
+/// Every run, failed or not, ends with a summary that is logged and, on
+/// Postgres, stored in `backfill_runs`. A failed run returns it alongside
+/// the error that stopped it.
 pub async fn run_backfill(
     config: Config,
     start_slot: u64,
     end_slot: u64,
     workers: usize,
+    resume: bool,
+    fail_fast: bool,
+    processed_manifest: Option<String>,
+) -> std::result::Result<BackfillSummary, Box<BackfillFailed>> {
+    let started = Instant::now();
+    let mut run = BackfillRun {
+        start_slot,
+        end_slot,
+        totals: Arc::default(),
+        budget_spent: Arc::default(),
+        recorder: None,
+    };
+    let result = backfill(config, workers, resume, fail_fast, processed_manifest, &mut run).await;
+    run.finish(result, started.elapsed()).await
+}
+
+/// The work of `run_backfill`, counting into `run` as it goes
+async fn backfill(
+    config: Config,
+    workers: usize,
+    resume: bool,
+    fail_fast: bool,
+    processed_manifest: Option<String>,
+    run: &mut BackfillRun,
 ) -> Result<()> {
+    let (start_slot, mut end_slot) = (run.start_slot, run.end_slot);
+    let totals = run.totals.clone();
+    let budget_spent = run.budget_spent.clone();
+
+    // A chunk's events can't be written early, so a cap would only stall workers
+    if config.etl.atomic_chunks && config.etl.max_in_flight_events > 0 {
+        return Err(ETLError::Config(
+            "ETL_MAX_IN_FLIGHT_EVENTS has no effect with ETL_ATOMIC_CHUNKS; size chunks instead".to_string(),
+        ));
+    }
+    let archive = create_archive_source(&config.etl)?;
+    // Every client comes from here so they all draw on one request budget
+    let endpoint_limits = EndpointRateLimits::default();
+    if config.etl.backfill_finalized_only {
+        let rpc_client = endpoint_limits.client(config.alchemy.clone());
+        let finalized = rpc_client.get_slot_with_commitment("finalized").await?;
+        end_slot = cap_to_finalized(start_slot, end_slot, finalized)?;
+        run.end_slot = end_slot;
+    }
+    let end_slot = end_slot;
     info!("Starting backfill from slot {} to {} with {} workers", start_slot, end_slot, workers);
 
-    let rpc_client = Arc::new(AlchemyRPCClient::new(config.alchemy.clone()));
+    let manifest = match processed_manifest {
+        Some(path) => {
+            let manifest = ProcessedManifest::load(&path)?;
+            info!(
+                "Skipping {} slot(s) of the backfill range listed in {}",
+                manifest.count_in(start_slot, end_slot),
+                path
+            );
+            Some(Arc::new(manifest))
+        }
+        None => None,
+    };
+
+    // A shared client means one rate limiter (and circuit breaker) for the
+    // whole backfill. Per-worker clients have their own connections and
+    // circuit breaker, but share the rate limit of the endpoint they call,
+    // so only workers on different endpoints each get the full rate.
+    let shared_client = if config.alchemy.rate_limit_scope == "per_worker" {
+        info!(
+            "Per-worker RPC clients over {} endpoint(s), each rate limited separately",
+            config.alchemy.worker_rpc_urls.len().max(1)
+        );
+        None
+    } else {
+        Some(Arc::new(endpoint_limits.client(config.alchemy.clone())))
+    };
 
     // Divide slot range into chunks
     let chunk_size = config.etl.backfill_chunk_size;
@@ -30,110 +124,1225 @@ pub async fn run_backfill(
 
     info!("Split into {} chunks", chunks.len());
 
-    // Process chunks in parallel with semaphore for rate limiting
-    let semaphore = Arc::new(Semaphore::new(workers));
+    // Shared job queue
+    let queue: Arc<dyn Warehouse> = Arc::from(crate::warehouse::create_warehouse(config.warehouse.clone())?);
+    queue.connect().await?;
+    run.recorder = Some(queue.clone());
+    queue.check_event_id_format(config.etl.event_id_format.parse()?).await?;
+    if config.etl.backfill_shared_queue && !queue.has_durable_queue() {
+        return Err(ETLError::Config(format!(
+            "ETL_BACKFILL_SHARED_QUEUE needs a Postgres warehouse; the {} backfill queue only lives in this process",
+            config.warehouse.warehouse_type
+        )));
+    }
+    queue
+        .enqueue_backfill_chunks(&chunks, resume, config.etl.backfill_claim_timeout_seconds)
+        .await?;
+    if resume {
+        info!("Resuming: picking up pending and stale chunks");
+    }
+
     let mut handles = Vec::new();
+    let aborted = Arc::new(AtomicBool::new(false));
+
+    // Cap on events fetched but not yet written, shared by all workers
+    let in_flight = match config.etl.max_in_flight_events {
+        0 => None,
+        cap => {
+            info!("Limiting buffered events to {} across workers", cap);
+            Some(Arc::new(Semaphore::new(cap)))
+        }
+    };
 
-    for (chunk_start, chunk_end) in chunks {
-        let permit = semaphore.clone().acquire_owned().await
-            .map_err(|e| ETLError::Generic(anyhow::anyhow!("Semaphore acquire error: {}", e)))?;
-        let rpc = rpc_client.clone();
+    // Workers bound how many chunks run at once; these cap RPC fetches and
+    // warehouse writes across all of them independently. A worker fetches
+    // one block at a time, so fetches can only be capped below the worker
+    // count, never raised above it.
+    if config.etl.fetch_concurrency > workers {
+        warn!(
+            "ETL_FETCH_CONCURRENCY={} has no effect above the worker count ({}); raise --workers for more simultaneous fetches",
+            config.etl.fetch_concurrency, workers
+        );
+    }
+    let fetch_limit = concurrency_limit(config.etl.fetch_concurrency);
+    let write_limit = concurrency_limit(config.etl.write_concurrency);
+    if fetch_limit.is_some() || write_limit.is_some() {
+        info!(
+            "Fetch concurrency {}, write concurrency {}",
+            describe_limit(config.etl.fetch_concurrency, workers),
+            describe_limit(config.etl.write_concurrency, workers)
+        );
+    }
+
+    // Parsing is CPU-bound; one pool bounds it across all workers
+    let parse_pool = Arc::new(ParsePool::new(config.etl.parse_threads, ParseOptions::for_ingestion(&config.etl)?));
+
+    // One leader schedule cache for all workers
+    let leaders = match &shared_client {
+        Some(client) => LeaderSchedules::for_ingestion(&config, client).await,
+        None => LeaderSchedules::for_ingestion(&config, &endpoint_limits.client(config.alchemy.clone())).await,
+    }
+    .map(Arc::new);
+
+    for worker_id in 0..workers {
+        let rpc = match &shared_client {
+            Some(client) => client.clone(),
+            None => Arc::new(endpoint_limits.client(worker_alchemy_config(&config, worker_id))),
+        };
+        let source: Arc<dyn BlockSource> = match &archive {
+            Some(archive) => archive.clone(),
+            None => rpc.clone(),
+        };
+        let queue = queue.clone();
         let warehouse_config = config.warehouse.clone();
         let config_clone = config.clone();
+        let aborted = aborted.clone();
+        let budget_spent = budget_spent.clone();
+        let in_flight = in_flight.clone();
+        let fetch_limit = fetch_limit.clone();
+        let write_limit = write_limit.clone();
+        let leaders = leaders.clone();
+        let manifest = manifest.clone();
+        let parse_pool = parse_pool.clone();
+        let totals = totals.clone();
 
         let handle = tokio::spawn(async move {
-            let _permit = permit;
             let wh = crate::warehouse::create_warehouse(warehouse_config)
                 .expect("Failed to create warehouse - check your WAREHOUSE_CONNECTION or WAREHOUSE_TYPE config");
             wh.connect().await.expect("Failed to connect to warehouse");
-            match process_chunk(rpc, &*wh, config_clone, chunk_start, chunk_end).await {
-                Ok(_) => {
-                    info!("Completed chunk {}-{}", chunk_start, chunk_end);
+
+            loop {
+                if aborted.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let claim = queue
+                    .claim_backfill_chunk(start_slot, end_slot, config_clone.etl.backfill_claim_timeout_seconds)
+                    .await;
+                let (chunk_start, chunk_end) = match claim {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Worker {} failed to claim chunk: {}", worker_id, e);
+                        break;
+                    }
+                };
+
+                let policy = ChunkPolicy {
+                    fail_fast,
+                    rpc: &rpc,
+                    in_flight: in_flight.as_deref(),
+                    fetch: fetch_limit.as_deref(),
+                    write: write_limit.as_deref(),
+                    leaders: leaders.as_deref(),
+                    processed: manifest.as_deref(),
+                    parse_pool: &parse_pool,
+                    totals: &totals,
+                };
+                let result = process_chunk(&*source, &*wh, config_clone.clone(), chunk_start, chunk_end, &policy).await;
+                match &result {
+                    Ok(_) => {
+                        info!("Completed chunk {}-{}", chunk_start, chunk_end);
+                        totals.chunks_completed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        warn!("Failed chunk {}-{}: {}", chunk_start, chunk_end, e);
+                        totals.chunks_failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+
+                if let Err(e) = queue.complete_backfill_chunk(chunk_start, chunk_end, result.is_ok()).await {
+                    warn!("Failed to record chunk {}-{} status: {}", chunk_start, chunk_end, e);
                 }
-                Err(e) => {
-                    warn!("Failed chunk {}-{}: {}", chunk_start, chunk_end, e);
+
+                // The request budget is shared, so no other chunk can finish either:
+                // stop claiming and end the run cleanly, leaving the rest for --resume
+                if let Err(e @ ETLError::RequestBudgetExhausted(_)) = &result {
+                    aborted.store(true, Ordering::SeqCst);
+                    budget_spent.lock().unwrap().get_or_insert_with(|| e.to_string());
+                    break;
+                }
+                if fail_fast {
+                    if let Err(e) = result {
+                        aborted.store(true, Ordering::SeqCst);
+                        return Err(e);
+                    }
                 }
             }
+
+            Ok(())
         });
 
         handles.push(handle);
     }
 
-    // Wait for all chunks to complete
+    // Wait for all workers to drain the queue (or stop after a fail-fast error)
+    let mut first_error = None;
     for handle in handles {
-        handle.await.map_err(|e| ETLError::Generic(anyhow::anyhow!("Join error: {}", e)))?;
+        let result = handle.await.map_err(|e| ETLError::Generic(anyhow::anyhow!("Join error: {}", e)))?;
+        if let Err(e) = result {
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
+}
 
-    info!("Backfill completed");
-    Ok(())
+/// One backfill's shared counters and where its summary is recorded
+struct BackfillRun {
+    start_slot: u64,
+    /// The requested end, until capped to the finalized slot
+    end_slot: u64,
+    totals: Arc<BackfillTotals>,
+    /// Why workers stopped early without failing the run (request budget spent)
+    budget_spent: Arc<Mutex<Option<String>>>,
+    /// The queue warehouse, once connected, also records the run
+    recorder: Option<Arc<dyn Warehouse>>,
+}
+
+impl BackfillRun {
+    /// Log the run's summary, store it in the warehouse, and return it with
+    /// the run's outcome
+    async fn finish(
+        self,
+        result: Result<()>,
+        elapsed: Duration,
+    ) -> std::result::Result<BackfillSummary, Box<BackfillFailed>> {
+        let mut summary = self.totals.summary(self.start_slot, self.end_slot, elapsed);
+        match &result {
+            Ok(()) => match self.budget_spent.lock().unwrap().take() {
+                Some(reason) => {
+                    warn!("Backfill stopped early: {} (continue with --resume)", reason);
+                    summary.error = Some(reason);
+                }
+                None => info!("Backfill completed"),
+            },
+            Err(e) => {
+                warn!("Backfill aborted: {}", e);
+                summary.error = Some(e.to_string());
+            }
+        }
+        summary.log();
+        if let Some(recorder) = self.recorder {
+            if let Err(e) = recorder.insert_backfill_run(&summary).await {
+                warn!("Failed to record backfill run {}: {}", summary.run_id, e);
+            }
+        }
+
+        match result {
+            Ok(()) => Ok(summary),
+            Err(error) => Err(Box::new(BackfillFailed { error, summary })),
+        }
+    }
+}
+
+/// A backfill stopped by an error, with the summary of what it did first
+#[derive(Debug)]
+pub struct BackfillFailed {
+    pub error: ETLError,
+    pub summary: BackfillSummary,
+}
+
+/// Counters shared by all workers of one backfill
+#[derive(Default)]
+struct BackfillTotals {
+    slots_attempted: AtomicU64,
+    slots_with_blocks: AtomicU64,
+    slots_skipped: AtomicU64,
+    slots_purged: AtomicU64,
+    slots_already_processed: AtomicU64,
+    oversized_transactions: AtomicU64,
+    chunks_completed: AtomicU64,
+    chunks_failed: AtomicU64,
+    /// Events of completed chunks; failed chunks are redone on resume
+    events_by_type: Mutex<BTreeMap<String, u64>>,
+}
+
+impl BackfillTotals {
+    fn summary(&self, start_slot: u64, end_slot: u64, elapsed: Duration) -> BackfillSummary {
+        let events_by_type = self.events_by_type.lock().unwrap().clone();
+        BackfillSummary {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            start_slot,
+            end_slot,
+            slots_attempted: self.slots_attempted.load(Ordering::Relaxed),
+            slots_with_blocks: self.slots_with_blocks.load(Ordering::Relaxed),
+            slots_skipped: self.slots_skipped.load(Ordering::Relaxed),
+            slots_purged: self.slots_purged.load(Ordering::Relaxed),
+            slots_already_processed: self.slots_already_processed.load(Ordering::Relaxed),
+            oversized_transactions: self.oversized_transactions.load(Ordering::Relaxed),
+            chunks_completed: self.chunks_completed.load(Ordering::Relaxed),
+            chunks_failed: self.chunks_failed.load(Ordering::Relaxed),
+            events_inserted: events_by_type.values().sum(),
+            events_by_type,
+            elapsed_seconds: elapsed.as_secs_f64(),
+            error: None,
+        }
+    }
+}
+
+/// RPC settings for a per-worker client: the worker's turn in
+/// `worker_rpc_urls`, or `rpc_url` when none are listed
+fn worker_alchemy_config(config: &Config, worker_id: usize) -> AlchemyConfig {
+    let mut alchemy = config.alchemy.clone();
+    let urls = &config.alchemy.worker_rpc_urls;
+    if !urls.is_empty() {
+        alchemy.rpc_url = urls[worker_id % urls.len()].clone();
+    }
+    alchemy
+}
+
+/// Exclusive end for a finalized-only backfill: `end_slot`, or just past
+/// the finalized slot when the range reaches beyond it. A range starting
+/// past finality is refused.
+fn cap_to_finalized(start_slot: u64, end_slot: u64, finalized: u64) -> Result<u64> {
+    if start_slot > finalized {
+        return Err(ETLError::Config(format!(
+            "Backfill starts at slot {}, past the finalized slot {}",
+            start_slot, finalized
+        )));
+    }
+    if end_slot > finalized + 1 {
+        warn!(
+            "Requested end slot {} is past the finalized slot {}; backfilling only through slot {}",
+            end_slot, finalized, finalized
+        );
+        return Ok(finalized + 1);
+    }
+    Ok(end_slot)
+}
+
+/// Effective limit for logging: 0 means bounded only by the worker count
+fn describe_limit(permits: usize, workers: usize) -> String {
+    match permits {
+        0 => format!("{} (workers)", workers),
+        n => n.to_string(),
+    }
 }
 
 /// Process a single chunk of slots
+///
+/// If the chunk fails partway, the chunk's own progress is first advanced to
+/// the last slot whose events were all inserted, so a resume doesn't redo
+/// them. The global checkpoint is left alone: other chunks below this one
+/// may still be unfinished.
 async fn process_chunk(
-    rpc_client: Arc<AlchemyRPCClient>,
+    source: &dyn BlockSource,
     warehouse: &dyn Warehouse,
     config: Config,
     start_slot: u64,
     end_slot: u64,
+    policy: &ChunkPolicy<'_>,
+) -> Result<()> {
+    let mut progress = ChunkProgress::default();
+
+    let result =
+        process_chunk_slots(source, warehouse, &config, start_slot, end_slot, policy, &mut progress).await;
+
+    if result.is_ok() {
+        let mut events_by_type = policy.totals.events_by_type.lock().unwrap();
+        for (event_type, count) in progress.events_by_type.drain() {
+            *events_by_type.entry(event_type).or_insert(0) += count;
+        }
+    }
+
+    info!(
+        "Chunk {}-{}: rpc {:.1}s, parse {:.1}s, db {:.1}s",
+        start_slot,
+        end_slot,
+        progress.rpc_time.as_secs_f64(),
+        progress.parse_time.as_secs_f64(),
+        progress.db_time.as_secs_f64()
+    );
+
+    if result.is_err() {
+        if let Some(inserted) = progress.inserted_through {
+            if progress.recorded_through < Some(inserted) {
+                match warehouse.insert_events_with_chunk_progress(Vec::new(), start_slot, end_slot, inserted).await {
+                    Ok(()) => info!("Recorded partial chunk {}-{} through slot {}", start_slot, end_slot, inserted),
+                    Err(e) => warn!("Failed to record partial chunk progress at slot {}: {}", inserted, e),
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// How a worker handles failures and buffering while processing a chunk
+struct ChunkPolicy<'a> {
+    /// Abort on parse failures instead of skipping
+    fail_fast: bool,
+    /// The worker's RPC client, for leader schedules whatever the block source
+    rpc: &'a AlchemyRPCClient,
+    /// Shared cap on buffered events (`ETL_MAX_IN_FLIGHT_EVENTS`)
+    in_flight: Option<&'a Semaphore>,
+    /// Shared cap on concurrent block fetches (`ETL_FETCH_CONCURRENCY`)
+    fetch: Option<&'a Semaphore>,
+    /// Shared cap on concurrent warehouse writes (`ETL_WRITE_CONCURRENCY`)
+    write: Option<&'a Semaphore>,
+    /// Shared leader schedule cache (`ETL_RESOLVE_LEADERS`)
+    leaders: Option<&'a LeaderSchedules>,
+    /// Slots known to be done from another system (`--processed-manifest`)
+    processed: Option<&'a ProcessedManifest>,
+    /// Shared blocking-thread pool for parsing (`ETL_PARSE_THREADS`)
+    parse_pool: &'a ParsePool,
+    /// Run-wide counters for the summary
+    totals: &'a BackfillTotals,
+}
+
+/// Slots already processed elsewhere, e.g. by the ETL system being migrated
+/// from. The file lists slots and inclusive ranges separated by commas or
+/// whitespace (`100-200,305`); `#` starts a comment.
+#[derive(Debug, Default)]
+pub struct ProcessedManifest {
+    /// Sorted, non-overlapping inclusive ranges
+    ranges: Vec<(u64, u64)>,
+}
+
+impl ProcessedManifest {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ETLError::Config(format!("Failed to read processed manifest {}: {}", path, e)))?;
+        Self::parse(&contents).map_err(|e| ETLError::Config(format!("Invalid processed manifest {}: {}", path, e)))
+    }
+
+    pub fn parse(contents: &str) -> std::result::Result<Self, String> {
+        let mut ranges = Vec::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for entry in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|e| !e.is_empty()) {
+                let parse_slot = |s: &str| s.trim().parse::<u64>().map_err(|_| format!("bad slot '{}'", entry));
+                let range = match entry.split_once('-') {
+                    Some((first, last)) => (parse_slot(first)?, parse_slot(last)?),
+                    None => {
+                        let slot = parse_slot(entry)?;
+                        (slot, slot)
+                    }
+                };
+                if range.0 > range.1 {
+                    return Err(format!("range '{}' ends before it starts", entry));
+                }
+                ranges.push(range);
+            }
+        }
+
+        // Merge overlapping and adjacent ranges so lookups can binary search
+        ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        Ok(Self { ranges: merged })
+    }
+
+    pub fn contains(&self, slot: u64) -> bool {
+        let idx = self.ranges.partition_point(|&(first, _)| first <= slot);
+        idx > 0 && self.ranges[idx - 1].1 >= slot
+    }
+
+    /// Listed slots within `[start_slot, end_slot)`
+    pub fn count_in(&self, start_slot: u64, end_slot: u64) -> u64 {
+        self.ranges
+            .iter()
+            .map(|&(first, last)| {
+                let first = first.max(start_slot);
+                let end = last.saturating_add(1).min(end_slot);
+                end.saturating_sub(first)
+            })
+            .sum()
+    }
+}
+
+/// Semaphore with `permits` slots, or None when 0 (unlimited)
+fn concurrency_limit(permits: usize) -> Option<Arc<Semaphore>> {
+    (permits > 0).then(|| Arc::new(Semaphore::new(permits)))
+}
+
+/// Wait for a slot under an optional concurrency limit
+async fn acquire(limit: Option<&Semaphore>) -> Result<Option<SemaphorePermit<'_>>> {
+    match limit {
+        Some(limit) => limit
+            .acquire()
+            .await
+            .map(Some)
+            .map_err(|e| ETLError::Generic(anyhow::anyhow!("Concurrency limiter closed: {}", e))),
+        None => Ok(None),
+    }
+}
+
+/// Highest slots within a chunk whose events are stored / recorded as the
+/// chunk's progress,
+/// cumulative time spent in RPC calls, parsing and warehouse writes, and
+/// buffered events by type
+#[derive(Default)]
+struct ChunkProgress {
+    inserted_through: Option<u64>,
+    recorded_through: Option<u64>,
+    events_by_type: HashMap<String, u64>,
+    rpc_time: Duration,
+    parse_time: Duration,
+    db_time: Duration,
+}
+
+async fn process_chunk_slots(
+    source: &dyn BlockSource,
+    warehouse: &dyn Warehouse,
+    config: &Config,
+    start_slot: u64,
+    end_slot: u64,
+    policy: &ChunkPolicy<'_>,
+    progress: &mut ChunkProgress,
 ) -> Result<()> {
     let mut slot = start_slot;
+    if !config.etl.atomic_chunks {
+        if let Some(done) = warehouse.backfill_chunk_progress(start_slot, end_slot).await? {
+            info!("Chunk {}-{} already done through slot {}; resuming after it", start_slot, end_slot, done);
+            slot = done + 1;
+        }
+    }
     let mut batch = Vec::new();
+    // Held while the batch is buffered; dropping them frees room for other workers
+    let mut permits: Vec<SemaphorePermit> = Vec::new();
+    let mut window = IngestionWindow::new("backfill");
+    let mut threshold = BatchThreshold::new(&config.etl);
+    // Rows derived from the buffered slots, written with their events
+    let mut derived = DerivedRows::default();
+
+    // Slots missing from getBlocks were skipped by their leader; knowing them
+    // up front saves a getBlock call per skipped slot
+    let produced: Option<HashSet<u64>> = if config.etl.use_get_blocks {
+        let _fetch = acquire(policy.fetch).await?;
+        let started = Instant::now();
+        let produced = source.get_blocks(start_slot, end_slot - 1).await?;
+        progress.rpc_time += started.elapsed();
+        info!(
+            "Chunk {}-{}: {} of {} slots produced blocks",
+            start_slot,
+            end_slot,
+            produced.len(),
+            end_slot - start_slot
+        );
+        Some(produced.into_iter().collect())
+    } else {
+        None
+    };
 
     while slot < end_slot {
         // Check if already processed
-        if warehouse.is_slot_processed(slot).await? {
+        if policy.processed.is_some_and(|manifest| manifest.contains(slot)) {
+            policy.totals.slots_already_processed.fetch_add(1, Ordering::Relaxed);
+            slot += 1;
+            continue;
+        }
+        let started = Instant::now();
+        let processed = warehouse.is_slot_processed(slot).await?;
+        progress.db_time += started.elapsed();
+        if processed {
+            policy.totals.slots_already_processed.fetch_add(1, Ordering::Relaxed);
             slot += 1;
             continue;
         }
+        policy.totals.slots_attempted.fetch_add(1, Ordering::Relaxed);
 
         // Fetch block
-        match rpc_client.get_block(slot, None).await? {
+        let started = Instant::now();
+        let mut purged = false;
+        let block = match &produced {
+            Some(produced) if !produced.contains(&slot) => None,
+            _ => {
+                let _fetch = acquire(policy.fetch).await?;
+                match source.get_block(slot, config.etl.fetch_rewards).await {
+                    Err(e @ ETLError::RequestBudgetExhausted(_)) => {
+                        // Keep the slots already done, with the chunk's progress, before stopping
+                        if slot > start_slot && !config.etl.atomic_chunks {
+                            drop(_fetch);
+                            let _write = acquire(policy.write).await?;
+                            let events = std::mem::take(&mut batch);
+                            let chunk = Progress::Chunk { start: start_slot, end: end_slot, through: slot - 1 };
+                            warehouse
+                                .insert_events_with_derived(events, &std::mem::take(&mut derived), Some(chunk), false)
+                                .await?;
+                            progress.inserted_through = Some(slot - 1);
+                            progress.recorded_through = Some(slot - 1);
+                        }
+                        return Err(e);
+                    }
+                    // Gone for good, so retrying the chunk can't help: record it and move on
+                    Err(e @ ETLError::SlotPurged(_)) => {
+                        warn!("{}", e);
+                        let warning = ParseWarning::new(slot, None, None, "purged", e.to_string());
+                        record_parse_warnings(warehouse, config.etl.record_parse_warnings, &[warning]).await;
+                        purged = true;
+                        None
+                    }
+                    result => result?,
+                }
+            }
+        };
+        progress.rpc_time += started.elapsed();
+        let counter = match block {
+            Some(_) => &policy.totals.slots_with_blocks,
+            None if purged => &policy.totals.slots_purged,
+            None => &policy.totals.slots_skipped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        match block {
             Some(block) => {
                 // Parse block into events
-                match parse_block(&block, slot) {
-                    Ok(mut events) => {
-                        // Flatten instructions
-                        events = flatten_instructions(events);
+                let started = Instant::now();
+                let parsed = policy.parse_pool.parse_raw(block, slot).await.map(|mut parsed| {
+                    policy.totals.oversized_transactions.fetch_add(parsed.oversized_transactions as u64, Ordering::Relaxed);
+                    if config.etl.skip_failed_instruction_events {
+                        drop_failed_effects(&mut parsed.events);
+                    }
+                    let mut derived = DerivedRows::default();
+                    if config.etl.wide_transactions {
+                        derived.wide = widen_transactions(&parsed.events);
+                    }
+                    if config.etl.aggregate_instructions {
+                        derived.invocations = aggregate_instructions(&mut parsed.events);
+                    }
+                    retain_event_types(&mut parsed.events, config.etl.event_types.as_deref(), config.etl.keep_transaction_events);
+                    limit_payload_size(&mut parsed.events, config.etl.max_payload_bytes);
+                    parsed.log_failures(slot, config.etl.parse_failure_alert_ratio);
+                    (flatten_instructions(parsed.events), parsed.warnings, derived)
+                });
+                progress.parse_time += started.elapsed();
+                match parsed {
+                    Ok((mut events, warnings, slot_derived)) => {
+                        if let Some(leaders) = policy.leaders {
+                            leaders.annotate(policy.rpc, slot, &mut events).await;
+                        }
+                        record_parse_warnings(warehouse, config.etl.record_parse_warnings, &warnings).await;
+                        if policy.fail_fast {
+                            // Duplicate ids are disambiguated, not dropped, so they don't count
+                            if let Some(skipped) = warnings.iter().find(|w| w.stage != "dedup") {
+                                return Err(ETLError::Parse(format!(
+                                    "Slot {}: {} skipped: {}",
+                                    slot, skipped.stage, skipped.message
+                                )));
+                            }
+                        }
+                        if let Some(in_flight) = policy.in_flight {
+                            // A block larger than the whole cap takes every permit
+                            let wanted = events.len().min(config.etl.max_in_flight_events).min(u32::MAX as usize) as u32;
+                            let permit = match in_flight.try_acquire_many(wanted) {
+                                Ok(permit) => permit,
+                                Err(_) => {
+                                    warn!("In-flight event cap reached, flushing and waiting for other workers");
+                                    // Flush our own buffer first so workers never wait
+                                    // on each other's held permits
+                                    if !batch.is_empty() {
+                                        let _write = acquire(policy.write).await?;
+                                        let started = Instant::now();
+                                        warehouse
+                                            .insert_events_with_derived(batch.clone(), &derived, None, false)
+                                            .await?;
+                                        progress.db_time += started.elapsed();
+                                        batch.clear();
+                                        derived = DerivedRows::default();
+                                        permits.clear();
+                                        progress.inserted_through = Some(slot - 1);
+                                    }
+                                    in_flight
+                                        .acquire_many(wanted)
+                                        .await
+                                        .map_err(|e| ETLError::Generic(anyhow::anyhow!("In-flight limiter closed: {}", e)))?
+                                }
+                            };
+                            permits.push(permit);
+                        }
+                        window.record_slot(events.len());
+                        threshold.record_slot(events.len());
+                        for event in &events {
+                            *progress.events_by_type.entry(event.event_type.clone()).or_insert(0) += 1;
+                        }
                         batch.extend(events);
+                        derived.extend(slot_derived);
 
-                        // Batch insert when batch size reached
-                        if batch.len() >= config.etl.batch_size {
-                            warehouse.insert_events(batch.clone()).await?;
+                        // Batch insert when batch size (or the adaptive target) is reached
+                        if !config.etl.atomic_chunks && threshold.should_flush(batch.len()) {
+                            let _write = acquire(policy.write).await?;
+                            let started = Instant::now();
+                            warehouse.insert_events_with_derived(batch.clone(), &derived, None, false).await?;
+                            progress.db_time += started.elapsed();
                             batch.clear();
+                            derived = DerivedRows::default();
+                            permits.clear();
+                            progress.inserted_through = Some(slot);
                         }
                     }
                     Err(e) => {
-                        warn!("Failed to parse block at slot {}: {}", slot, e);
+                        let warning = ParseWarning::new(slot, None, None, "block", e.to_string());
+                        record_parse_warnings(warehouse, config.etl.record_parse_warnings, &[warning]).await;
+                        window.record_slot(0);
+                        if policy.fail_fast {
+                            return Err(ETLError::Parse(format!("Failed to parse block at slot {}: {}", slot, e)));
+                        }
                         // Continue to next slot
                     }
                 }
             }
+            None if purged => window.record_slot(0),
+            // Known skipped slot
+            None if produced.as_ref().is_some_and(|produced| !produced.contains(&slot)) => window.record_slot(0),
+            None if produced.is_some() => {
+                return Err(ETLError::RPC(format!(
+                    "getBlocks listed slot {} but getBlock returned no block",
+                    slot
+                )));
+            }
             None => {
                 warn!("Block not found at slot {} (skipping)", slot);
+                window.record_slot(0);
             }
         }
 
         slot += 1;
 
-        // Checkpoint periodically
-        if (slot - start_slot) % config.etl.checkpoint_interval == 0 {
-            if !batch.is_empty() {
-                warehouse.insert_events(batch.clone()).await?;
-                batch.clear();
+        // Record the chunk's progress periodically
+        if !config.etl.atomic_chunks && (slot - start_slot).is_multiple_of(config.etl.checkpoint_interval) {
+            let _write = acquire(policy.write).await?;
+            let started = Instant::now();
+            let chunk = Progress::Chunk { start: start_slot, end: end_slot, through: slot - 1 };
+            warehouse.insert_events_with_derived(batch.clone(), &derived, Some(chunk), false).await?;
+            progress.db_time += started.elapsed();
+            batch.clear();
+            derived = DerivedRows::default();
+            permits.clear();
+            progress.inserted_through = Some(slot - 1);
+            progress.recorded_through = Some(slot - 1);
+            info!("Chunk {}-{} done through slot {}", start_slot, end_slot, slot - 1);
+            window.flush(warehouse).await;
+        }
+    }
+
+    // Insert remaining batch with the final checkpoint
+    let _write = acquire(policy.write).await?;
+    let started = Instant::now();
+    let checkpoint = Progress::Checkpoint(&config.alchemy.commitment, end_slot - 1);
+    warehouse
+        .insert_events_with_derived(batch, &derived, Some(checkpoint), config.etl.atomic_chunks)
+        .await?;
+    progress.db_time += started.elapsed();
+    window.flush(warehouse).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        block, config, fresh_database, postgres_config, transaction, FakeBlocks, MemoryWarehouse, MockRpc, Reply,
+    };
+    use serde_json::{json, Value};
+    use std::ops::RangeInclusive;
+
+    #[test]
+    fn workers_take_turns_over_the_listed_endpoints() {
+        let mut config = config();
+        assert_eq!(worker_alchemy_config(&config, 3).rpc_url, config.alchemy.rpc_url);
+
+        config.alchemy.worker_rpc_urls = vec!["http://a.invalid".to_string(), "http://b.invalid".to_string()];
+        let urls: Vec<String> = (0..3).map(|worker| worker_alchemy_config(&config, worker).rpc_url).collect();
+        assert_eq!(urls, vec!["http://a.invalid", "http://b.invalid", "http://a.invalid"]);
+    }
+
+    #[test]
+    fn finalized_only_ranges_stop_at_the_finalized_slot() {
+        // Entirely finalized: unchanged, up to and including the finalized slot
+        assert_eq!(cap_to_finalized(100, 150, 200).unwrap(), 150);
+        assert_eq!(cap_to_finalized(100, 201, 200).unwrap(), 201);
+        // Reaching past finality: capped just past the finalized slot
+        assert_eq!(cap_to_finalized(100, 202, 200).unwrap(), 201);
+        assert_eq!(cap_to_finalized(200, 300, 200).unwrap(), 201);
+        // Starting past finality is refused
+        let err = cap_to_finalized(201, 300, 200).unwrap_err();
+        assert!(matches!(err, ETLError::Config(_)), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn oversized_transactions_are_counted_in_the_summary() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(100)) => Reply::Result(block(vec![
+                transaction("small", &["payer", "Prog111"], json!([])),
+                transaction("large", &["payer", "Prog111"], json!([{ "programIdIndex": 1, "accounts": [0], "data": "1".repeat(2_000) }])),
+            ])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.max_transaction_bytes = 1_500;
+        // Keep the small transaction's events off the test's stdout
+        config.etl.event_types = Some(vec!["reward".to_string()]);
+
+        let summary = run_backfill(config, 100, 102, 1, false, false, None).await.unwrap();
+        assert_eq!(summary.oversized_transactions, 1);
+        assert_eq!((summary.slots_with_blocks, summary.chunks_completed), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn purged_slots_are_counted_without_failing_or_retrying_the_chunk() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(101)) => Reply::Error { code: -32007, message: "Slot 101 was skipped, or missing due to ledger jump".to_string() },
+            ("getBlock", Some(100)) => Reply::Result(block(vec![])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.alchemy.max_retries = 3;
+        config.warehouse.warehouse_type = "stdout".to_string();
+
+        let summary = run_backfill(config, 100, 103, 1, false, false, None).await.unwrap();
+        assert_eq!(summary.slots_purged, 1);
+        assert_eq!((summary.slots_with_blocks, summary.slots_skipped), (1, 1));
+        assert_eq!((summary.chunks_completed, summary.chunks_failed), (1, 0));
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls.iter().filter(|call| call.params[0].as_u64() == Some(101)).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_finalized_only_backfill_never_fetches_past_finality() {
+        let rpc = MockRpc::start(|method, _| match method {
+            "getSlot" => Reply::Result(json!(104)),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.backfill_finalized_only = true;
+
+        let summary = run_backfill(config, 100, 110, 1, false, false, None).await.unwrap();
+        assert_eq!((summary.start_slot, summary.end_slot, summary.slots_attempted), (100, 105, 5));
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls[0].params[0]["commitment"], "finalized");
+        assert!(calls.iter().filter(|call| call.method == "getBlock").all(|call| call.params[0].as_u64() < Some(105)));
+    }
+
+    async fn run_chunk(
+        source: &FakeBlocks,
+        warehouse: &MemoryWarehouse,
+        config: &Config,
+        chunk: (u64, u64),
+        fail_fast: bool,
+    ) -> Result<()> {
+        run_chunk_with_manifest(source, warehouse, config, chunk, fail_fast, None).await
+    }
+
+    async fn run_chunk_with_manifest(
+        source: &FakeBlocks,
+        warehouse: &MemoryWarehouse,
+        config: &Config,
+        chunk: (u64, u64),
+        fail_fast: bool,
+        processed: Option<&ProcessedManifest>,
+    ) -> Result<()> {
+        let rpc = AlchemyRPCClient::new(config.alchemy.clone());
+        let parse_pool = ParsePool::new(0, ParseOptions::for_ingestion(&config.etl).unwrap());
+        let totals = BackfillTotals::default();
+        let policy = ChunkPolicy {
+            fail_fast,
+            rpc: &rpc,
+            in_flight: None,
+            fetch: None,
+            write: None,
+            leaders: None,
+            processed,
+            parse_pool: &parse_pool,
+            totals: &totals,
+        };
+        process_chunk(source, warehouse, config.clone(), chunk.0, chunk.1, &policy).await
+    }
+
+    #[tokio::test]
+    async fn the_in_flight_cap_bounds_buffered_events_across_workers() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+        const WORKERS: u64 = 4;
+        const CHUNK: u64 = 20;
+        const CAP: usize = 10;
+
+        let mut config = config();
+        // Only the cap may force a write; otherwise every chunk buffers whole
+        config.etl.batch_size = 10_000;
+        config.etl.checkpoint_interval = 10_000;
+        config.etl.max_in_flight_events = CAP;
+        let source = FakeBlocks::with_slots(0..=WORKERS * CHUNK - 1);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.state.lock().unwrap().write_delay = Some(Duration::from_millis(20));
+
+        let rpc = AlchemyRPCClient::new(config.alchemy.clone());
+        let parse_pool = ParsePool::new(0, ParseOptions::for_ingestion(&config.etl).unwrap());
+        let totals = BackfillTotals::default();
+        let in_flight = Semaphore::new(CAP);
+        let policy = ChunkPolicy {
+            fail_fast: true,
+            rpc: &rpc,
+            in_flight: Some(&in_flight),
+            fetch: None,
+            write: None,
+            leaders: None,
+            processed: None,
+            parse_pool: &parse_pool,
+            totals: &totals,
+        };
+
+        // Sample (fetched blocks, stored events) while the workers run
+        let done = AtomicBool::new(false);
+        let samples = std::sync::Mutex::new(Vec::new());
+        let sample = async {
+            while !done.load(Ordering::SeqCst) {
+                let fetched = source.fetches.load(Ordering::SeqCst);
+                let stored = warehouse.state.lock().unwrap().events.len();
+                samples.lock().unwrap().push((fetched, stored));
+                tokio::time::sleep(Duration::from_millis(1)).await;
             }
-            warehouse.update_last_slot(slot - 1).await?;
-            info!("Checkpoint at slot {}", slot - 1);
+        };
+        let workers = async {
+            let chunks = (0..WORKERS).map(|w| {
+                process_chunk(&source, &warehouse, config.clone(), w * CHUNK, (w + 1) * CHUNK, &policy)
+            });
+            let results = futures::future::join_all(chunks).await;
+            done.store(true, Ordering::SeqCst);
+            results
+        };
+        let (results, ()) = tokio::join!(workers, sample);
+        for result in results {
+            result.unwrap();
         }
+
+        let slots = WORKERS * CHUNK;
+        let stored = warehouse.state.lock().unwrap().events.len();
+        assert_eq!(warehouse.slots().len() as u64, slots);
+        let per_slot = stored / slots as usize;
+        assert!(per_slot > 0 && per_slot <= CAP);
+        // Fetched-but-unstored events: those holding permits, plus at most the
+        // one block each worker holds while it waits for room
+        let most_buffered = samples
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(fetched, stored)| fetched as usize * per_slot - stored)
+            .max()
+            .unwrap();
+        assert!(
+            most_buffered <= CAP + WORKERS as usize * per_slot,
+            "{} events buffered against a cap of {}",
+            most_buffered,
+            CAP
+        );
+        // Every permit came back once the workers wrote out
+        assert_eq!(in_flight.available_permits(), CAP);
     }
 
-    // Insert remaining batch
-    if !batch.is_empty() {
-        warehouse.insert_events(batch).await?;
+    #[tokio::test]
+    async fn fetch_and_write_limits_cap_calls_across_workers() {
+        use std::sync::atomic::Ordering;
+        use std::time::Duration;
+        const WORKERS: u64 = 4;
+        const CHUNK: u64 = 6;
+
+        let mut config = config();
+        config.etl.batch_size = 2;
+        let mut source = FakeBlocks::with_slots(0..=WORKERS * CHUNK - 1);
+        source.fetch_delay = Some(Duration::from_millis(5));
+        let warehouse = MemoryWarehouse::default();
+        warehouse.state.lock().unwrap().write_delay = Some(Duration::from_millis(5));
+
+        let rpc = AlchemyRPCClient::new(config.alchemy.clone());
+        let parse_pool = ParsePool::new(0, ParseOptions::for_ingestion(&config.etl).unwrap());
+        let totals = BackfillTotals::default();
+        let (fetch, write) = (Semaphore::new(2), Semaphore::new(1));
+        let policy = ChunkPolicy {
+            fail_fast: true,
+            rpc: &rpc,
+            in_flight: None,
+            fetch: Some(&fetch),
+            write: Some(&write),
+            leaders: None,
+            processed: None,
+            parse_pool: &parse_pool,
+            totals: &totals,
+        };
+        let chunks = (0..WORKERS).map(|w| process_chunk(&source, &warehouse, config.clone(), w * CHUNK, (w + 1) * CHUNK, &policy));
+        for result in futures::future::join_all(chunks).await {
+            result.unwrap();
+        }
+
+        assert_eq!(warehouse.slots().len() as u64, WORKERS * CHUNK);
+        // Four workers, but never more fetches or writes at once than allowed
+        assert_eq!(source.most_fetching.load(Ordering::SeqCst), 2);
+        assert_eq!(warehouse.state.lock().unwrap().most_writing, 1);
     }
 
-    // Final checkpoint
-    warehouse.update_last_slot(end_slot - 1).await?;
+    /// Blocks for `slots`, where `bad_slot` also carries a transaction that fails to parse
+    fn blocks_with_a_bad_transaction(slots: RangeInclusive<u64>, bad_slot: u64) -> FakeBlocks {
+        let mut source = FakeBlocks::with_slots(slots);
+        let good = transaction("good", &["payer", "Prog111"], json!([]));
+        let bad = json!({ "meta": { "err": null } });
+        source.blocks.insert(bad_slot, block(vec![good, bad]).to_string());
+        source
+    }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn lenient_chunks_skip_unparseable_transactions() {
+        let mut config = config();
+        config.etl.record_parse_warnings = true;
+        let source = blocks_with_a_bad_transaction(100..=104, 102);
+        let warehouse = MemoryWarehouse::default();
+
+        run_chunk(&source, &warehouse, &config, (100, 105), false).await.unwrap();
+        assert_eq!(warehouse.slots(), (100..=104).collect());
+        assert_eq!(warehouse.state.lock().unwrap().warnings, 1);
+    }
+
+    #[tokio::test]
+    async fn fail_fast_chunks_stop_at_the_first_skipped_transaction() {
+        let config = config();
+        let source = blocks_with_a_bad_transaction(100..=104, 102);
+        let warehouse = MemoryWarehouse::default();
+
+        let err = run_chunk(&source, &warehouse, &config, (100, 105), true).await.unwrap_err();
+        assert!(matches!(err, ETLError::Parse(ref message) if message.contains("Slot 102")), "{}", err);
+        assert_eq!(source.fetches.load(Ordering::Relaxed), 3);
+        assert!(warehouse.slots().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fail_fast_backfill_returns_the_first_chunk_error() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(100)) => Reply::Result(block(vec![json!({ "meta": { "err": null } })])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.backfill_chunk_size = 5;
+
+        let failed = run_backfill(config, 100, 110, 1, false, true, None).await.unwrap_err();
+        assert!(matches!(failed.error, ETLError::Parse(_)), "{}", failed.error);
+        // The summary still covers what ran before the error
+        assert_eq!((failed.summary.chunks_failed, failed.summary.chunks_completed), (1, 0));
+        assert_eq!(failed.summary.error, Some(failed.error.to_string()));
+        // The worker stopped instead of claiming the second chunk
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls.iter().filter(|call| call.method == "getBlock").count(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failed_backfill_is_recorded_in_backfill_runs() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(100)) => Reply::Result(block(vec![json!({ "meta": { "err": null } })])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let url = fresh_database().await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse = postgres_config(&url);
+        config.etl.backfill_chunk_size = 5;
+
+        let failed = run_backfill(config, 100, 110, 1, false, true, None).await.unwrap_err();
+
+        let pool = sqlx::PgPool::connect(&url).await.unwrap();
+        let (run_id, chunks_failed, error): (String, i64, Option<String>) =
+            sqlx::query_as("SELECT run_id, chunks_failed, error FROM backfill_runs")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(run_id, failed.summary.run_id);
+        assert_eq!(chunks_failed, 1);
+        assert_eq!(error, Some(failed.error.to_string()));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_spent_request_budget_stops_cleanly_and_resumes_where_it_stopped() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(slot)) => {
+                Reply::Result(block(vec![transaction(&format!("sig{}", slot), &["payer", "Prog111"], json!([]))]))
+            }
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let url = fresh_database().await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.alchemy.max_requests_per_run = 5;
+        config.warehouse = postgres_config(&url);
+        config.etl.backfill_chunk_size = 10;
+
+        let summary = run_backfill(config.clone(), 100, 120, 1, false, false, None).await.unwrap();
+        assert!(summary.error.as_deref().is_some_and(|e| e.contains("budget")), "{:?}", summary.error);
+        assert_eq!((summary.chunks_completed, summary.chunks_failed), (0, 1));
+        assert_eq!(rpc.calls.lock().unwrap().len(), 5);
+
+        // The slots fetched are stored with the chunk's progress; the second chunk was never claimed
+        let pool = sqlx::PgPool::connect(&url).await.unwrap();
+        let jobs: Vec<(i64, String, Option<i64>)> =
+            sqlx::query_as("SELECT chunk_start, status, progress_slot FROM backfill_jobs ORDER BY chunk_start")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(jobs, vec![(100, "failed".to_string(), Some(104)), (110, "pending".to_string(), None)]);
+        let slots: Vec<i64> = sqlx::query_scalar("SELECT DISTINCT slot FROM fact_transactions ORDER BY slot")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(slots, (100..=104).collect::<Vec<i64>>());
 
+        // A resumed run with a fresh budget fetches only what is left
+        config.alchemy.max_requests_per_run = 0;
+        let summary = run_backfill(config, 100, 120, 1, true, false, None).await.unwrap();
+        assert_eq!(summary.error, None);
+        assert_eq!(rpc.calls.lock().unwrap().len(), 5 + 15);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(DISTINCT slot) FROM fact_transactions").fetch_one(&pool).await.unwrap();
+        assert_eq!(stored, 20);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failed_atomic_chunk_leaves_no_rows() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(105)) => Reply::Result(block(vec![json!({ "meta": { "err": null } })])),
+            ("getBlock", Some(slot)) => Reply::Result(block(vec![transaction(
+                &format!("sig{}", slot),
+                &["payer", "Prog111"],
+                json!([{ "programIdIndex": 1, "accounts": [0], "data": "" }]),
+            )])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let url = fresh_database().await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse = postgres_config(&url);
+        config.etl.atomic_chunks = true;
+        config.etl.checkpoint_interval = 2;
+        config.etl.wide_transactions = true;
+        config.etl.aggregate_instructions = true;
+
+        // Slot 105 fails the chunk after five slots were parsed
+        run_backfill(config.clone(), 100, 110, 1, false, true, None).await.unwrap_err();
+
+        let pool = sqlx::PgPool::connect(&url).await.unwrap();
+        let count = |table: &str| {
+            let pool = pool.clone();
+            let query = format!("SELECT COUNT(*) FROM {}", table);
+            async move { sqlx::query_scalar::<_, i64>(&query).fetch_one(&pool).await.unwrap() }
+        };
+        let tables = ["fact_transactions", "fact_transactions_wide", "program_invocations", "completed_slots"];
+        for table in tables {
+            assert_eq!(count(table).await, 0, "{}", table);
+        }
+
+        // Skipping the bad transaction instead, the whole chunk lands together
+        run_backfill(config, 100, 110, 1, true, false, None).await.unwrap();
+        for table in tables {
+            assert!(count(table).await > 0, "{}", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_unimplemented_block_source_fails_before_any_chunk() {
+        let rpc = MockRpc::start(|_, _| Reply::Result(Value::Null)).await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.block_source = "bigtable".to_string();
+
+        let failed = run_backfill(config, 100, 110, 1, false, false, None).await.unwrap_err();
+        assert!(matches!(failed.error, ETLError::Config(_)), "{}", failed.error);
+        assert_eq!(failed.summary.chunks_failed, 0);
+        assert!(rpc.calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn atomic_chunks_refuse_an_in_flight_cap() {
+        let mut config = config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.atomic_chunks = true;
+        config.etl.max_in_flight_events = 1_000;
+
+        let failed = run_backfill(config, 100, 110, 1, false, false, None).await.unwrap_err();
+        assert!(failed.error.to_string().contains("ETL_MAX_IN_FLIGHT_EVENTS"), "{}", failed.error);
+    }
+
+    #[tokio::test]
+    async fn listed_slot_without_a_block_fails_the_chunk() {
+        let mut config = config();
+        config.etl.use_get_blocks = true;
+        let mut source = FakeBlocks::with_slots(100..=109);
+        source.blocks.remove(&103);
+        source.pending.lock().unwrap().insert(105, 1);
+        let warehouse = MemoryWarehouse::default();
+
+        // 103 is absent from getBlocks (skipped); 105 is listed but comes back null
+        let err = run_chunk(&source, &warehouse, &config, (100, 110), false).await.unwrap_err();
+        assert!(err.to_string().contains("slot 105"), "{}", err);
+        assert_eq!(source.fetches.load(Ordering::Relaxed), 5);
+
+        run_chunk(&source, &warehouse, &config, (100, 110), false).await.unwrap();
+        assert!(warehouse.slots().contains(&105));
+        assert!(!warehouse.slots().contains(&103));
+    }
+
+    #[tokio::test]
+    async fn partial_chunk_failure_records_chunk_progress_not_the_checkpoint() {
+        let mut config = config();
+        config.etl.checkpoint_interval = 5;
+        let source = FakeBlocks::with_slots(100..=119);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.state.lock().unwrap().fail_slot = Some(112);
+
+        assert!(run_chunk(&source, &warehouse, &config, (100, 120), false).await.is_err());
+        // Chunks below this one may be unfinished, so the checkpoint stays put
+        assert_eq!(warehouse.checkpoint(&config.alchemy.commitment), None);
+        assert_eq!(warehouse.backfill_chunk_progress(100, 120).await.unwrap(), Some(109));
+
+        warehouse.state.lock().unwrap().fail_slot = None;
+        let fetched_before = source.fetches.load(Ordering::Relaxed);
+        run_chunk(&source, &warehouse, &config, (100, 120), false).await.unwrap();
+        assert_eq!(source.fetches.load(Ordering::Relaxed) - fetched_before, 10);
+        assert_eq!(warehouse.slots(), (100..=119).collect());
+    }
+
+    #[test]
+    fn manifests_merge_ranges_and_singletons() {
+        let manifest = ProcessedManifest::parse("100-102, 105\n# from the old loader\n104 101  # overlaps\n200-200").unwrap();
+        assert_eq!(manifest.ranges, vec![(100, 102), (104, 105), (200, 200)]);
+        let listed: Vec<u64> = (95..210).filter(|&slot| manifest.contains(slot)).collect();
+        assert_eq!(listed, vec![100, 101, 102, 104, 105, 200]);
+        assert_eq!(manifest.count_in(100, 106), 5);
+        assert_eq!(manifest.count_in(101, 105), 3);
+        assert_eq!(manifest.count_in(0, u64::MAX), 6);
+
+        assert!(ProcessedManifest::parse("").unwrap().ranges.is_empty());
+        assert!(ProcessedManifest::parse("7-3").unwrap_err().contains("ends before it starts"));
+        assert!(ProcessedManifest::parse("100,abc").unwrap_err().contains("bad slot 'abc'"));
+    }
+
+    #[tokio::test]
+    async fn slots_in_the_manifest_are_never_fetched() {
+        let source = FakeBlocks::with_slots(100..=109);
+        let warehouse = MemoryWarehouse::default();
+        let manifest = ProcessedManifest::parse("100-103,106").unwrap();
+
+        run_chunk_with_manifest(&source, &warehouse, &config(), (100, 110), false, Some(&manifest)).await.unwrap();
+        assert_eq!(source.fetches.load(Ordering::Relaxed), 5);
+        assert_eq!(warehouse.slots(), [104, 105, 107, 108, 109].into());
+    }
+}
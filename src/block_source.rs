@@ -0,0 +1,48 @@
+use crate::config::ETLConfig;
+use crate::error::{ETLError, Result};
+use crate::parsers::RawBlock;
+use crate::rpc::AlchemyRPCClient;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Where backfill reads blocks from: the RPC node, or a ledger archive for
+/// history the node has purged
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    /// The block at `slot`; None if the slot was skipped
+    async fn get_block(&self, slot: u64, rewards: bool) -> Result<Option<RawBlock>>;
+
+    /// Slots in `start_slot..=end_slot` that produced a block
+    async fn get_blocks(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>>;
+
+    /// Highest slot the source can serve
+    async fn get_slot(&self) -> Result<u64>;
+}
+
+#[async_trait]
+impl BlockSource for AlchemyRPCClient {
+    async fn get_block(&self, slot: u64, rewards: bool) -> Result<Option<RawBlock>> {
+        self.get_raw_block(slot, rewards).await
+    }
+
+    async fn get_blocks(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>> {
+        AlchemyRPCClient::get_blocks(self, start_slot, end_slot).await
+    }
+
+    async fn get_slot(&self) -> Result<u64> {
+        AlchemyRPCClient::get_slot(self).await
+    }
+}
+
+/// The archive named by `ETL_BLOCK_SOURCE`, shared by all workers; None means
+/// each worker reads blocks over its own RPC client
+pub fn create_archive_source(config: &ETLConfig) -> Result<Option<Arc<dyn BlockSource>>> {
+    match config.block_source.as_str() {
+        "rpc" => Ok(None),
+        // Refused at startup until Bigtable reads exist, rather than failing every chunk
+        "bigtable" => Err(ETLError::Config(
+            "ETL_BLOCK_SOURCE=bigtable is not implemented yet; use 'rpc'".to_string(),
+        )),
+        other => Err(ETLError::Config(format!("Unsupported block source: {}. Use 'rpc'", other))),
+    }
+}
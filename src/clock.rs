@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Source of the current time for time-based logic (stall detection,
+/// ingestion windows), so it can be driven by a `MockClock` instead of the
+/// real clock
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, for timestamps that are stored
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Monotonic time, for measuring durations; NTP steps don't move it
+    fn instant(&self) -> Instant;
+
+    /// Time since `earlier`, taken from `instant`
+    fn since(&self, earlier: Instant) -> Duration {
+        self.instant().saturating_duration_since(earlier)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real wall and monotonic clocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: std::sync::Mutex<(DateTime<Utc>, Instant)>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self { now: std::sync::Mutex::new((start, Instant::now())) }
+    }
+
+    /// Step the wall clock only, as NTP does
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.lock().unwrap().0 = now;
+    }
+
+    /// Move both clocks forward by `by`, stopping at the latest representable
+    /// time
+    pub fn advance(&self, by: Duration) {
+        // Instant has no maximum; one that would overflow moves a century instead
+        const CENTURY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        let (now, instant) = &mut *self.now.lock().unwrap();
+        *now = chrono::Duration::from_std(by)
+            .ok()
+            .and_then(|by| now.checked_add_signed(by))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        *instant = instant
+            .checked_add(by)
+            .or_else(|| instant.checked_add(CENTURY))
+            .unwrap_or(*instant);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.lock().unwrap().0
+    }
+
+    fn instant(&self) -> Instant {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_mock_clock_saturates_instead_of_overflowing() {
+        let clock = MockClock::new(Utc::now());
+        let start = clock.instant();
+        clock.advance(Duration::MAX);
+        assert_eq!(clock.now(), DateTime::<Utc>::MAX_UTC);
+        assert!(clock.since(start) > Duration::ZERO);
+    }
+}
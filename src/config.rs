@@ -1,3 +1,7 @@
+use crate::epoch::EpochSchedule;
+use crate::error::ETLError;
+use crate::events::EventIdFormat;
+use sqlx::postgres::PgSslMode;
 use std::env;
 
 #[derive(Debug, Clone)]
@@ -5,6 +9,7 @@ pub struct Config {
     pub alchemy: AlchemyConfig,
     pub warehouse: WarehouseConfig,
     pub etl: ETLConfig,
+    pub analytics: AnalyticsConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -12,32 +17,119 @@ pub struct AlchemyConfig {
     pub rpc_url: String,
     pub max_retries: u32,
     pub timeout_seconds: u64,
-    pub rate_limit_per_second: u32,
+    pub max_backoff_seconds: u64, // Cap on the sleep between retries
+    pub call_deadline_seconds: u64, // Bound on one call including all retries; 0 = none
+    pub max_requests_per_run: u64, // HTTP requests (retries included) allowed per run; 0 = unlimited
+    pub rate_limit_per_second: Option<u32>,
+    pub rate_limit_per_minute: Option<u32>, // Replaces per-second limiting when set
+    pub rate_limit_burst: Option<u32>,      // Max requests allowed back-to-back
+    pub control_rate_limit_per_second: u32, // Lane for getSlot/getBlockHeight, carved out of the main rate; 0 = share the main limiter
+    pub rate_limit_scope: String, // "shared": one client for all workers; "per_worker": a client per worker, rate limited per endpoint
+    pub worker_rpc_urls: Vec<String>, // per_worker: endpoints assigned to workers in turn; empty = rpc_url
+    pub circuit_failure_threshold: f64, // Failure ratio that opens the circuit
+    pub circuit_window: u32,            // Recent calls the failure ratio is taken over
+    pub circuit_cooldown_seconds: u64,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout_seconds: u64,
+    pub tcp_keepalive_seconds: u64,
+    pub http2: bool, // Negotiate HTTP/2 so concurrent requests share connections
+    pub accept_invalid_certs: bool, // Skip TLS verification, for self-signed private endpoints only
+    pub commitment: String, // "confirmed" or "finalized"; also selects the checkpoint stream
 }
 
 #[derive(Debug, Clone)]
 pub struct WarehouseConfig {
-    pub warehouse_type: String, // "bigquery", "snowflake", "postgres"
+    pub warehouse_type: String, // "bigquery", "postgres", "s3", "stdout"
     pub connection_string: Option<String>,
+    pub ssl_mode: Option<String>, // Postgres: overrides `sslmode` in the connection string
+    pub ssl_root_cert: Option<String>, // Postgres: CA certificate file for verify-ca / verify-full
     pub project_id: Option<String>, // For BigQuery
     pub dataset_id: Option<String>, // For BigQuery
     pub credentials_path: Option<String>,
+    pub compress_payload: bool, // Postgres: store raw_payload zstd-compressed instead of JSONB
+    pub insert_batch_size: usize, // Postgres: events per insert transaction; 0 = one per insert call
+    pub object_bytes: usize, // S3: buffer events into objects of about this many bytes
+    pub discriminator_names_path: Option<String>, // JSON map of Anchor discriminator hex -> instruction name
+    pub shadow: Option<Box<WarehouseConfig>>, // Second warehouse that event inserts are also written to
+    pub routes: Vec<(String, String)>, // event_type glob -> sink name ("primary" or a key of `sinks`)
+    pub sinks: Vec<(String, WarehouseConfig)>, // Named sinks referenced by `routes`
 }
 
 #[derive(Debug, Clone)]
 pub struct ETLConfig {
     pub batch_size: usize,
+    pub target_events_per_batch: usize, // Flush batches adaptively around this many events; 0 = fixed `batch_size`
     pub checkpoint_interval: u64,
     pub backfill_chunk_size: u64,
+    pub backfill_claim_timeout_seconds: u64, // Claimed chunks older than this are re-picked
+    pub backfill_shared_queue: bool, // Several machines work through one backfill queue; needs Postgres
+    pub backfill_finalized_only: bool, // Cap backfill ranges at the finalized slot
     pub incremental_interval_seconds: u64,
+    pub stall_timeout_seconds: u64, // Incremental: alert when no events are written this long while behind the tip; 0 = off
+    pub exit_on_stall: bool, // Incremental: exit non-zero instead of only logging a stall
+    pub poll_jitter_pct: f64, // Incremental: randomize the poll interval by up to ± this percent
+    pub dedup_window_slots: u64, // Incremental: drop transactions already seen in another slot this many slots back; 0 = off
     pub max_slot_lag: u64,
+    pub finality_confirmations: u64, // Slots behind tip before incremental ingests a slot; 0 = up to the tip
+    pub fetch_rewards: bool, // Request block rewards and emit `reward` events
+    pub resolve_leaders: bool, // Set the scheduled block producer on events (one getLeaderSchedule per epoch)
+    pub record_parse_warnings: bool, // Persist skipped parse failures to `parse_warnings`
+    pub max_payload_bytes: usize, // Truncate instruction `data` in payloads larger than this; 0 = no limit
+    pub max_transaction_bytes: usize, // Skip transactions whose JSON is larger than this; 0 = no limit
+    pub event_types: Option<Vec<String>>, // Only store these event types (`*` globs); None = all
+    pub keep_transaction_events: bool, // With `event_types`, still store the base `transaction` events
+    pub event_id_format: String, // "sha256_hex" or "base58_128"; see `EventIdFormat`
+    pub skip_failed_instruction_events: bool, // Store only the base `transaction` and balance-change events of failed transactions
+    pub wide_transactions: bool, // Also store one denormalized row per transaction in fact_transactions_wide
+    pub aggregate_instructions: bool, // Store per-slot program invocation counts instead of instruction events
+    pub parse_failure_alert_ratio: f64, // Fraction of a block's transactions failing to parse that logs an error
+    pub use_get_blocks: bool, // Backfill: list produced slots via getBlocks and only fetch those
+    pub max_in_flight_events: usize, // Backfill: cap on fetched-but-unwritten events across workers; 0 = no cap
+    pub fetch_concurrency: usize, // Backfill: cap on simultaneous getBlock calls across workers, each fetching one at a time; 0 = no cap
+    pub write_concurrency: usize, // Backfill: cap on simultaneous warehouse writes across workers; 0 = no cap
+    pub parse_threads: usize, // Blocks parsed at once on blocking threads; 0 = parse inline on the async workers
+    pub atomic_chunks: bool, // Backfill: hold a chunk's events and write them in one transaction at its end
+    pub block_source: String, // Backfill block source: "rpc" ("bigtable" is reserved, not implemented yet)
+    pub epoch_schedule: Option<EpochSchedule>, // None: fetched via getEpochSchedule when needed
+}
+
+#[derive(Debug, Clone)]
+pub struct AnalyticsConfig {
+    pub query_timeout_seconds: u64, // Per-statement timeout; 0 disables
+    pub sample_fraction: f64, // Share of fact_transactions scanned by `analytics --approximate`
+    pub step_retries: u32, // Extra attempts for a failing analytics computation
 }
 
 impl Default for Config {
     fn default() -> Self {
+        let rate_limit_per_minute: Option<u32> = env::var("ALCHEMY_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|s| s.parse().ok());
+
+        // WAREHOUSE_ROUTES="token_transfer=transfers,alt_*=lake"; each sink
+        // name other than "primary" is configured by SINK_<NAME>_* vars
+        let routes: Vec<(String, String)> = env::var("WAREHOUSE_ROUTES")
+            .ok()
+            .map(|s| {
+                s.split(',')
+                    .filter_map(|route| route.split_once('='))
+                    .map(|(pattern, sink)| (pattern.trim().to_string(), sink.trim().to_lowercase()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut sinks: Vec<(String, WarehouseConfig)> = Vec::new();
+        for (_, sink) in &routes {
+            if sink != "primary" && !sinks.iter().any(|(name, _)| name == sink) {
+                let prefix = format!("SINK_{}_", sink.to_uppercase());
+                let warehouse_type = env::var(format!("{}WAREHOUSE_TYPE", prefix)).unwrap_or_default();
+                sinks.push((sink.clone(), secondary_warehouse(&warehouse_type, &prefix)));
+            }
+        }
+
         Config {
             alchemy: AlchemyConfig {
-                rpc_url: "https://solana-mainnet.g.alchemy.com/v2/AFjoSzKjqv6Eq53OsF2xe".to_string(),
+                rpc_url: env_or_file("ALCHEMY_RPC_URL")
+                    .unwrap_or_else(|| "https://solana-mainnet.g.alchemy.com/v2/AFjoSzKjqv6Eq53OsF2xe".to_string()),
                 max_retries: env::var("ALCHEMY_MAX_RETRIES")
                     .ok()
                     .and_then(|s| s.parse().ok())
@@ -46,25 +138,118 @@ impl Default for Config {
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(30),
+                max_backoff_seconds: env::var("ALCHEMY_MAX_BACKOFF_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(16),
+                call_deadline_seconds: env::var("ALCHEMY_CALL_DEADLINE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(60),
+                max_requests_per_run: env::var("ALCHEMY_MAX_REQUESTS_PER_RUN")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                // Per-second limiting only defaults on when no per-minute quota is given
                 rate_limit_per_second: env::var("ALCHEMY_RATE_LIMIT")
                     .ok()
                     .and_then(|s| s.parse().ok())
-                    .unwrap_or(50),
+                    .or(if rate_limit_per_minute.is_none() { Some(50) } else { None }),
+                rate_limit_per_minute,
+                rate_limit_burst: env::var("ALCHEMY_RATE_LIMIT_BURST")
+                    .ok()
+                    .and_then(|s| s.parse().ok()),
+                control_rate_limit_per_second: env::var("ALCHEMY_CONTROL_RATE_LIMIT")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                rate_limit_scope: env::var("ALCHEMY_RATE_LIMIT_SCOPE")
+                    .unwrap_or_else(|_| "shared".to_string())
+                    .to_lowercase(),
+                worker_rpc_urls: env::var("ALCHEMY_WORKER_RPC_URLS")
+                    .ok()
+                    .map(|s| {
+                        s.split(',')
+                            .map(|url| url.trim().to_string())
+                            .filter(|url| !url.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                circuit_failure_threshold: env::var("ALCHEMY_CIRCUIT_FAILURE_THRESHOLD")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.5),
+                // ALCHEMY_CIRCUIT_MIN_REQUESTS is the setting's former name
+                circuit_window: env::var("ALCHEMY_CIRCUIT_WINDOW")
+                    .or_else(|_| env::var("ALCHEMY_CIRCUIT_MIN_REQUESTS"))
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(10),
+                circuit_cooldown_seconds: env::var("ALCHEMY_CIRCUIT_COOLDOWN_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(30),
+                pool_max_idle_per_host: env::var("ALCHEMY_POOL_MAX_IDLE_PER_HOST")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(32),
+                pool_idle_timeout_seconds: env::var("ALCHEMY_POOL_IDLE_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(90),
+                tcp_keepalive_seconds: env::var("ALCHEMY_TCP_KEEPALIVE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(60),
+                http2: env::var("ALCHEMY_HTTP2")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(true),
+                accept_invalid_certs: env::var("ALCHEMY_INSECURE_TLS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                commitment: env::var("ALCHEMY_COMMITMENT").unwrap_or_else(|_| "confirmed".to_string()),
             },
             warehouse: WarehouseConfig {
                 warehouse_type: env::var("WAREHOUSE_TYPE")
                     .unwrap_or_else(|_| "postgres".to_string())
                     .to_lowercase(),
-                connection_string: env::var("WAREHOUSE_CONNECTION").ok(),
+                connection_string: env_or_file("WAREHOUSE_CONNECTION"),
+                ssl_mode: env::var("WAREHOUSE_SSL_MODE").ok(),
+                ssl_root_cert: env::var("WAREHOUSE_SSL_ROOT_CERT").ok(),
                 project_id: env::var("BIGQUERY_PROJECT_ID").ok(),
                 dataset_id: env::var("BIGQUERY_DATASET_ID").ok().or(Some("solana_etl".to_string())),
                 credentials_path: env::var("GOOGLE_APPLICATION_CREDENTIALS").ok(),
+                compress_payload: env::var("ETL_COMPRESS_PAYLOAD")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                insert_batch_size: env::var("WAREHOUSE_INSERT_BATCH_SIZE")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                object_bytes: env::var("WAREHOUSE_OBJECT_BYTES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(128 * 1024 * 1024),
+                discriminator_names_path: env::var("ETL_DISCRIMINATOR_MAP").ok(),
+                // Shadow warehouse: enabled by SHADOW_WAREHOUSE_TYPE
+                shadow: env::var("SHADOW_WAREHOUSE_TYPE")
+                    .ok()
+                    .map(|warehouse_type| Box::new(secondary_warehouse(&warehouse_type, "SHADOW_"))),
+                routes,
+                sinks,
             },
             etl: ETLConfig {
                 batch_size: env::var("ETL_BATCH_SIZE")
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(1000),
+                target_events_per_batch: env::var("ETL_TARGET_EVENTS_PER_BATCH")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
                 checkpoint_interval: env::var("ETL_CHECKPOINT_INTERVAL")
                     .ok()
                     .and_then(|s| s.parse().ok())
@@ -73,14 +258,148 @@ impl Default for Config {
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(1000),
+                backfill_claim_timeout_seconds: env::var("ETL_BACKFILL_CLAIM_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(600),
+                backfill_shared_queue: env::var("ETL_BACKFILL_SHARED_QUEUE")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                backfill_finalized_only: env::var("ETL_BACKFILL_FINALIZED_ONLY")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
                 incremental_interval_seconds: env::var("ETL_INTERVAL_SECONDS")
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(30),
+                stall_timeout_seconds: env::var("ETL_STALL_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                exit_on_stall: env::var("ETL_EXIT_ON_STALL")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                poll_jitter_pct: env::var("ETL_POLL_JITTER_PCT")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(10.0),
+                dedup_window_slots: env::var("ETL_DEDUP_WINDOW_SLOTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
                 max_slot_lag: env::var("ETL_MAX_SLOT_LAG")
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(1000),
+                finality_confirmations: env::var("ETL_FINALITY_CONFIRMATIONS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                fetch_rewards: env::var("ETL_FETCH_REWARDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                resolve_leaders: env::var("ETL_RESOLVE_LEADERS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                record_parse_warnings: env::var("ETL_RECORD_PARSE_WARNINGS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                max_payload_bytes: env::var("ETL_MAX_PAYLOAD_BYTES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                max_transaction_bytes: env::var("ETL_MAX_TRANSACTION_BYTES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(10 * 1024 * 1024),
+                event_types: env::var("ETL_EVENT_TYPES").ok().map(|s| {
+                    s.split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect()
+                }),
+                keep_transaction_events: env::var("ETL_KEEP_TRANSACTION_EVENTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(true),
+                event_id_format: env::var("ETL_EVENT_ID_FORMAT").unwrap_or_else(|_| "sha256_hex".to_string()),
+                skip_failed_instruction_events: env::var("ETL_SKIP_FAILED_INSTRUCTION_EVENTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                wide_transactions: env::var("ETL_WIDE_TRANSACTIONS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                aggregate_instructions: env::var("ETL_AGGREGATE_INSTRUCTIONS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                parse_failure_alert_ratio: env::var("ETL_PARSE_FAILURE_ALERT_RATIO")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.05),
+                use_get_blocks: env::var("ETL_USE_GET_BLOCKS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                max_in_flight_events: env::var("ETL_MAX_IN_FLIGHT_EVENTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                fetch_concurrency: env::var("ETL_FETCH_CONCURRENCY")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                write_concurrency: env::var("ETL_WRITE_CONCURRENCY")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                parse_threads: env::var("ETL_PARSE_THREADS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+                atomic_chunks: env::var("ETL_ATOMIC_CHUNKS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(false),
+                block_source: env::var("ETL_BLOCK_SOURCE")
+                    .map(|s| s.to_lowercase())
+                    .unwrap_or_else(|_| "rpc".to_string()),
+                epoch_schedule: env::var("ETL_SLOTS_PER_EPOCH")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .map(|slots_per_epoch| EpochSchedule {
+                        slots_per_epoch,
+                        first_normal_epoch: env::var("ETL_FIRST_NORMAL_EPOCH")
+                            .ok()
+                            .and_then(|s| s.parse().ok())
+                            .unwrap_or(0),
+                        first_normal_slot: env::var("ETL_FIRST_NORMAL_SLOT")
+                            .ok()
+                            .and_then(|s| s.parse().ok())
+                            .unwrap_or(0),
+                    }),
+            },
+            analytics: AnalyticsConfig {
+                query_timeout_seconds: env::var("ANALYTICS_QUERY_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(300),
+                sample_fraction: env::var("ANALYTICS_SAMPLE_FRACTION")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.01),
+                step_retries: env::var("ANALYTICS_STEP_RETRIES")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1),
             },
         }
     }
@@ -88,7 +407,320 @@ impl Default for Config {
 
 impl Config {
     pub fn load() -> crate::Result<Self> {
+        // An unreadable secret file must fail loudly rather than silently
+        // fall back to the plain variable or a default
+        for (key, path) in env::vars() {
+            let Some(base) = key.strip_suffix("_FILE") else {
+                continue;
+            };
+            if is_secret_key(base) {
+                std::fs::read_to_string(&path)
+                    .map_err(|e| ETLError::Config(format!("Cannot read {} ({}): {}", key, path, e)))?;
+            }
+        }
+
         // Try to load from config file first, then fall back to env/defaults
         Ok(Config::default())
     }
+
+    /// Check the config for values that would only fail mid-run
+    pub fn validate(&self) -> crate::Result<()> {
+        let mut problems = Vec::new();
+
+        // RPC
+        match reqwest::Url::parse(&self.alchemy.rpc_url) {
+            Ok(url) if self.alchemy.accept_invalid_certs
+                && url.host_str().is_some_and(|host| host.ends_with(".alchemy.com")) =>
+            {
+                problems.push("ALCHEMY_INSECURE_TLS is only for private endpoints, not Alchemy".to_string());
+            }
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => problems.push(format!("RPC URL has unsupported scheme: {}", url.scheme())),
+            Err(e) => problems.push(format!("RPC URL is invalid: {}", e)),
+        }
+        if self.alchemy.timeout_seconds == 0 {
+            problems.push("ALCHEMY_TIMEOUT_SECONDS must be non-zero".to_string());
+        }
+        match (self.alchemy.rate_limit_per_second, self.alchemy.rate_limit_per_minute) {
+            (Some(_), Some(_)) => problems.push(
+                "Set only one of ALCHEMY_RATE_LIMIT and ALCHEMY_RATE_LIMIT_PER_MINUTE".to_string(),
+            ),
+            (None, None) => problems.push(
+                "One of ALCHEMY_RATE_LIMIT or ALCHEMY_RATE_LIMIT_PER_MINUTE is required".to_string(),
+            ),
+            (Some(0), None) => problems.push("ALCHEMY_RATE_LIMIT must be non-zero".to_string()),
+            (None, Some(0)) => problems.push("ALCHEMY_RATE_LIMIT_PER_MINUTE must be non-zero".to_string()),
+            _ => {}
+        }
+        // Compared per minute, so per-minute limits keep their fractional
+        // requests per second
+        let control = self.alchemy.control_rate_limit_per_second;
+        let main_rate = match (self.alchemy.rate_limit_per_second, self.alchemy.rate_limit_per_minute) {
+            (_, Some(per_minute)) => Some((u64::from(per_minute), format!("{}/min", per_minute))),
+            (Some(per_second), None) => Some((u64::from(per_second) * 60, format!("{}/s", per_second))),
+            (None, None) => None,
+        };
+        if let Some((main_per_minute, main_rate)) = main_rate {
+            if control > 0 && u64::from(control) * 60 >= main_per_minute {
+                problems.push(format!(
+                    "ALCHEMY_CONTROL_RATE_LIMIT ({}/s) is reserved out of the main rate limit and must be below it ({})",
+                    control, main_rate
+                ));
+            }
+        }
+        if self.alchemy.rate_limit_burst == Some(0) {
+            problems.push("ALCHEMY_RATE_LIMIT_BURST must be non-zero".to_string());
+        }
+        if !matches!(self.alchemy.rate_limit_scope.as_str(), "shared" | "per_worker") {
+            problems.push(format!(
+                "Unsupported ALCHEMY_RATE_LIMIT_SCOPE: {}. Use 'shared' or 'per_worker'",
+                self.alchemy.rate_limit_scope
+            ));
+        }
+        if !(0.0..=100.0).contains(&self.etl.poll_jitter_pct) {
+            problems.push("ETL_POLL_JITTER_PCT must be in [0, 100]".to_string());
+        }
+        if !(self.analytics.sample_fraction > 0.0 && self.analytics.sample_fraction <= 1.0) {
+            problems.push("ANALYTICS_SAMPLE_FRACTION must be in (0, 1]".to_string());
+        }
+        if !matches!(self.alchemy.commitment.as_str(), "confirmed" | "finalized") {
+            problems.push(format!(
+                "Unsupported ALCHEMY_COMMITMENT: {}. Use 'confirmed' or 'finalized'",
+                self.alchemy.commitment
+            ));
+        }
+        if !(self.alchemy.circuit_failure_threshold > 0.0 && self.alchemy.circuit_failure_threshold <= 1.0) {
+            problems.push("ALCHEMY_CIRCUIT_FAILURE_THRESHOLD must be in (0, 1]".to_string());
+        }
+
+        // Warehouse
+        validate_warehouse(&self.warehouse, "", &mut problems);
+        if let Some(shadow) = &self.warehouse.shadow {
+            validate_warehouse(shadow, "SHADOW_", &mut problems);
+        }
+        for (name, sink) in &self.warehouse.sinks {
+            validate_warehouse(sink, &format!("SINK_{}_", name.to_uppercase()), &mut problems);
+        }
+
+        // ETL
+        if self.etl.batch_size == 0 {
+            problems.push("ETL_BATCH_SIZE must be non-zero".to_string());
+        }
+        if self.etl.checkpoint_interval == 0 {
+            problems.push("ETL_CHECKPOINT_INTERVAL must be non-zero".to_string());
+        }
+        if self.etl.backfill_chunk_size == 0 {
+            problems.push("ETL_BACKFILL_CHUNK_SIZE must be non-zero".to_string());
+        }
+        if self.etl.backfill_shared_queue && self.warehouse.warehouse_type != "postgres" {
+            problems.push(format!(
+                "ETL_BACKFILL_SHARED_QUEUE needs a Postgres warehouse; {} only keeps the backfill queue in memory",
+                self.warehouse.warehouse_type
+            ));
+        }
+        if let Err(e) = self.etl.event_id_format.parse::<EventIdFormat>() {
+            problems.push(format!("ETL_EVENT_ID_FORMAT: {}", e));
+        }
+        if self.etl.atomic_chunks && self.etl.max_in_flight_events > 0 {
+            problems.push("ETL_MAX_IN_FLIGHT_EVENTS has no effect with ETL_ATOMIC_CHUNKS; size chunks instead".to_string());
+        }
+        match self.etl.block_source.as_str() {
+            "rpc" => {}
+            // Refused until Bigtable reads exist, rather than failing every chunk
+            "bigtable" => problems.push("ETL_BLOCK_SOURCE=bigtable is not implemented yet; use 'rpc'".to_string()),
+            other => problems.push(format!("Unsupported ETL_BLOCK_SOURCE: {}. Use 'rpc'", other)),
+        }
+        if self.etl.event_types.as_ref().is_some_and(|types| types.is_empty()) {
+            problems.push("ETL_EVENT_TYPES is set but lists no event types".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.etl.parse_failure_alert_ratio) {
+            problems.push("ETL_PARSE_FAILURE_ALERT_RATIO must be in [0, 1]".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ETLError::Config(problems.join("; ")))
+        }
+    }
+}
+
+/// Settings that may hold credentials and can also be read from a file
+/// named by `<KEY>_FILE` (e.g. a mounted Kubernetes secret).
+/// `GOOGLE_APPLICATION_CREDENTIALS` is already a path to a key file, so it
+/// has no `_FILE` variant.
+fn is_secret_key(key: &str) -> bool {
+    key == "ALCHEMY_RPC_URL" || key.ends_with("WAREHOUSE_CONNECTION")
+}
+
+/// Read `<key>_FILE`'s contents if set (trailing newlines trimmed), else the
+/// `key` variable itself. The file takes precedence when both are set.
+fn env_or_file(key: &str) -> Option<String> {
+    if let Ok(path) = env::var(format!("{}_FILE", key)) {
+        return std::fs::read_to_string(path)
+            .ok()
+            .map(|value| value.trim_end_matches(['\n', '\r']).to_string());
+    }
+    env::var(key).ok()
+}
+
+/// Warehouse configured by `<prefix>WAREHOUSE_CONNECTION`,
+/// `<prefix>BIGQUERY_PROJECT_ID` and `<prefix>BIGQUERY_DATASET_ID`; other
+/// settings are shared with the primary
+fn secondary_warehouse(warehouse_type: &str, prefix: &str) -> WarehouseConfig {
+    WarehouseConfig {
+        warehouse_type: warehouse_type.to_lowercase(),
+        connection_string: env_or_file(&format!("{}WAREHOUSE_CONNECTION", prefix)),
+        ssl_mode: env::var(format!("{}WAREHOUSE_SSL_MODE", prefix)).ok(),
+        ssl_root_cert: env::var(format!("{}WAREHOUSE_SSL_ROOT_CERT", prefix)).ok(),
+        project_id: env::var(format!("{}BIGQUERY_PROJECT_ID", prefix)).ok(),
+        dataset_id: env::var(format!("{}BIGQUERY_DATASET_ID", prefix)).ok().or(Some("solana_etl".to_string())),
+        credentials_path: env::var("GOOGLE_APPLICATION_CREDENTIALS").ok(),
+        compress_payload: env::var("ETL_COMPRESS_PAYLOAD")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(false),
+        insert_batch_size: env::var(format!("{}WAREHOUSE_INSERT_BATCH_SIZE", prefix))
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        object_bytes: env::var(format!("{}WAREHOUSE_OBJECT_BYTES", prefix))
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(128 * 1024 * 1024),
+        discriminator_names_path: env::var("ETL_DISCRIMINATOR_MAP").ok(),
+        shadow: None,
+        routes: Vec::new(),
+        sinks: Vec::new(),
+    }
+}
+
+/// Check a warehouse's required fields; `env_prefix` names the shadow's env vars
+fn validate_warehouse(warehouse: &WarehouseConfig, env_prefix: &str, problems: &mut Vec<String>) {
+    match warehouse.warehouse_type.as_str() {
+        "postgres" => {
+            if warehouse.connection_string.is_none() {
+                problems.push(format!("Postgres requires {}WAREHOUSE_CONNECTION", env_prefix));
+            }
+            if let Some(mode) = &warehouse.ssl_mode {
+                if mode.parse::<PgSslMode>().is_err() {
+                    problems.push(format!(
+                        "{}WAREHOUSE_SSL_MODE must be disable, allow, prefer, require, verify-ca or verify-full (got {})",
+                        env_prefix, mode
+                    ));
+                }
+            }
+            if let Some(path) = &warehouse.ssl_root_cert {
+                if !std::path::Path::new(path).is_file() {
+                    problems.push(format!("{}WAREHOUSE_SSL_ROOT_CERT file not found: {}", env_prefix, path));
+                }
+            }
+        }
+        "bigquery" => {
+            if warehouse.project_id.is_none() {
+                problems.push(format!("BigQuery requires {}BIGQUERY_PROJECT_ID", env_prefix));
+            }
+        }
+        "s3" => match &warehouse.connection_string {
+            Some(location) if location.starts_with("s3://") => {}
+            _ => problems.push(format!("S3 requires {}WAREHOUSE_CONNECTION=s3://bucket/prefix", env_prefix)),
+        },
+        "stdout" => {}
+        other => problems.push(format!(
+            "Unsupported {}warehouse type: {}. Use 'postgres', 'bigquery', 's3' or 'stdout'",
+            env_prefix.to_lowercase().replace('_', " "),
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::config;
+
+    fn problems(config: &Config) -> String {
+        match config.validate() {
+            Ok(()) => String::new(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn shared_backfill_queue_needs_postgres() {
+        let mut config = config();
+        config.etl.backfill_shared_queue = true;
+        config.warehouse.warehouse_type = "s3".to_string();
+        config.warehouse.connection_string = Some("s3://bucket/prefix".to_string());
+        assert!(problems(&config).contains("ETL_BACKFILL_SHARED_QUEUE"));
+
+        config.warehouse.warehouse_type = "postgres".to_string();
+        config.warehouse.connection_string = Some("postgres://localhost/etl".to_string());
+        assert!(!problems(&config).contains("ETL_BACKFILL_SHARED_QUEUE"));
+    }
+
+    #[test]
+    fn insecure_tls_is_rejected_for_alchemy_urls_only() {
+        let mut config = config();
+        config.alchemy.accept_invalid_certs = true;
+        config.alchemy.rpc_url = "https://solana-mainnet.g.alchemy.com/v2/key".to_string();
+        assert!(problems(&config).contains("ALCHEMY_INSECURE_TLS"));
+
+        config.alchemy.rpc_url = "https://validator.internal:8899".to_string();
+        assert!(!problems(&config).contains("ALCHEMY_INSECURE_TLS"));
+
+        config.alchemy.accept_invalid_certs = false;
+        config.alchemy.rpc_url = "https://solana-mainnet.g.alchemy.com/v2/key".to_string();
+        assert!(!problems(&config).contains("ALCHEMY_INSECURE_TLS"));
+    }
+
+    #[test]
+    fn the_control_lane_must_fit_under_a_per_minute_limit() {
+        let mut config = config();
+        config.alchemy.rate_limit_per_second = None;
+        config.alchemy.rate_limit_per_minute = Some(90);
+        config.alchemy.control_rate_limit_per_second = 1;
+        assert!(!problems(&config).contains("ALCHEMY_CONTROL_RATE_LIMIT"), "{}", problems(&config));
+
+        config.alchemy.rate_limit_per_minute = Some(60);
+        assert!(problems(&config).contains("ALCHEMY_CONTROL_RATE_LIMIT (1/s)"));
+        assert!(problems(&config).contains("must be below it (60/min)"));
+
+        config.alchemy.rate_limit_per_minute = None;
+        config.alchemy.rate_limit_per_second = Some(2);
+        assert!(!problems(&config).contains("ALCHEMY_CONTROL_RATE_LIMIT"));
+        config.alchemy.control_rate_limit_per_second = 2;
+        assert!(problems(&config).contains("must be below it (2/s)"));
+    }
+
+    #[test]
+    fn the_bigtable_block_source_is_rejected_until_it_reads_blocks() {
+        let mut config = config();
+        config.etl.block_source = "bigtable".to_string();
+        assert!(problems(&config).contains("ETL_BLOCK_SOURCE=bigtable is not implemented yet"));
+
+        config.etl.block_source = "rpc".to_string();
+        assert!(!problems(&config).contains("ETL_BLOCK_SOURCE"));
+    }
+
+    #[test]
+    fn env_or_file_prefers_the_file_and_trims_trailing_newlines() {
+        let key = format!("ETL_TEST_SECRET_{}", uuid::Uuid::new_v4().simple());
+        let path = env::temp_dir().join(format!("{}.secret", key));
+        std::fs::write(&path, "postgres://user:from-file@db/etl\r\n\n").unwrap();
+
+        assert_eq!(env_or_file(&key), None);
+        env::set_var(&key, "postgres://user:from-env@db/etl");
+        assert_eq!(env_or_file(&key).as_deref(), Some("postgres://user:from-env@db/etl"));
+
+        env::set_var(format!("{}_FILE", key), &path);
+        assert_eq!(env_or_file(&key).as_deref(), Some("postgres://user:from-file@db/etl"));
+
+        // A file that can't be read doesn't fall back to the plain variable
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(env_or_file(&key), None);
+
+        env::remove_var(format!("{}_FILE", key));
+        env::remove_var(&key);
+    }
 }
@@ -0,0 +1,176 @@
+use crate::config::Config;
+use crate::error::{ETLError, Result};
+use crate::rpc::AlchemyRPCClient;
+use crate::warehouse::connect_postgres;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Row};
+use tracing::{info, warn};
+
+/// Create the `tokens` dimension table (mint → decimals, supply)
+pub async fn create_tokens_table(pool: &PgPool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tokens (
+            mint TEXT PRIMARY KEY,
+            decimals SMALLINT,
+            supply NUMERIC,
+            first_seen TIMESTAMPTZ,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create tokens table: {}", e)))?;
+
+    Ok(())
+}
+
+/// Fetch supply and decimals for every transferred mint not yet in `tokens`.
+///
+/// Mints already present are skipped, so repeated runs only hit the RPC for
+/// newly seen tokens. With `refresh`, every mint is fetched again.
+pub async fn run_enrich_tokens(config: Config, refresh: bool) -> Result<()> {
+    let pool = connect_postgres(&config.warehouse).await?;
+    create_tokens_table(&pool).await?;
+
+    let rows = sqlx::query(
+        "SELECT raw_payload->>'mint' as mint, MIN(block_time) as first_seen
+         FROM fact_transactions f
+         WHERE event_type = 'token_transfer'
+           AND raw_payload->>'mint' IS NOT NULL
+           AND ($1 OR NOT EXISTS (SELECT 1 FROM tokens t WHERE t.mint = f.raw_payload->>'mint'))
+         GROUP BY 1"
+    )
+    .bind(refresh)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to list mints: {}", e)))?;
+
+    info!("Enriching {} token mint(s)", rows.len());
+
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    let mut enriched = 0;
+
+    for row in rows {
+        let mint: String = row.get(0);
+        let first_seen: Option<DateTime<Utc>> = row.get(1);
+
+        let supply = match rpc_client.get_token_supply(&mint).await {
+            Ok(supply) => supply,
+            Err(e) => {
+                warn!("Failed to fetch supply for {}: {}", mint, e);
+                continue;
+            }
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO tokens (mint, decimals, supply, first_seen, updated_at)
+            VALUES ($1, $2, $3::numeric, $4, NOW())
+            ON CONFLICT (mint) DO UPDATE SET
+                decimals = EXCLUDED.decimals,
+                supply = EXCLUDED.supply,
+                first_seen = LEAST(tokens.first_seen, EXCLUDED.first_seen),
+                updated_at = EXCLUDED.updated_at
+            "#
+        )
+        .bind(&mint)
+        .bind(supply.get("decimals").and_then(|v| v.as_i64()).map(|d| d as i16))
+        .bind(supply.get("amount").and_then(|v| v.as_str()))
+        .bind(first_seen)
+        .execute(&pool)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to store token {}: {}", mint, e)))?;
+
+        enriched += 1;
+    }
+
+    info!("Enriched {} token mint(s)", enriched);
+    Ok(())
+}
+
+/// Create the `validators` dimension: one row per vote account per snapshot
+pub async fn create_validators_table(pool: &PgPool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS validators (
+            snapshot_at TIMESTAMPTZ NOT NULL,
+            vote_pubkey TEXT NOT NULL,
+            node_pubkey TEXT NOT NULL,
+            activated_stake BIGINT NOT NULL,
+            commission SMALLINT,
+            last_vote BIGINT,
+            delinquent BOOLEAN NOT NULL,
+            PRIMARY KEY (snapshot_at, vote_pubkey)
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create validators table: {}", e)))?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_validators_node ON validators(node_pubkey, snapshot_at)")
+        .execute(pool).await.ok();
+
+    Ok(())
+}
+
+/// Snapshot `getVoteAccounts` into `validators`. Each run adds a new
+/// snapshot, so stake and commission changes can be tracked over time.
+pub async fn run_refresh_validators(config: Config) -> Result<()> {
+    let pool = connect_postgres(&config.warehouse).await?;
+    create_validators_table(&pool).await?;
+
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    let vote_accounts = rpc_client.get_vote_accounts().await?;
+    let snapshot_at = Utc::now();
+
+    let mut tx = pool.begin().await
+        .map_err(|e| ETLError::Database(format!("Failed to begin transaction: {}", e)))?;
+    let mut stored = 0;
+
+    for (group, delinquent) in [("current", false), ("delinquent", true)] {
+        let accounts = vote_accounts
+            .get(group)
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| ETLError::RPC(format!("Missing {} in vote accounts response", group)))?;
+
+        for account in accounts {
+            let (Some(vote_pubkey), Some(node_pubkey)) = (
+                account.get("votePubkey").and_then(|v| v.as_str()),
+                account.get("nodePubkey").and_then(|v| v.as_str()),
+            ) else {
+                warn!("Skipping vote account without pubkeys: {}", account);
+                continue;
+            };
+
+            sqlx::query(
+                r#"
+                INSERT INTO validators (
+                    snapshot_at, vote_pubkey, node_pubkey, activated_stake, commission, last_vote, delinquent
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#
+            )
+            .bind(snapshot_at)
+            .bind(vote_pubkey)
+            .bind(node_pubkey)
+            .bind(account.get("activatedStake").and_then(|v| v.as_u64()).unwrap_or(0) as i64)
+            .bind(account.get("commission").and_then(|v| v.as_u64()).map(|c| c as i16))
+            .bind(account.get("lastVote").and_then(|v| v.as_u64()).map(|s| s as i64))
+            .bind(delinquent)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to store validator {}: {}", vote_pubkey, e)))?;
+
+            stored += 1;
+        }
+    }
+
+    tx.commit().await
+        .map_err(|e| ETLError::Database(format!("Failed to commit validators snapshot: {}", e)))?;
+
+    info!("Stored {} validators in snapshot {}", stored, snapshot_at);
+    Ok(())
+}
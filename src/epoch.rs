@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+/// Slots in the first warmup epoch; each warmup epoch doubles in length
+pub const MINIMUM_SLOTS_PER_EPOCH: u64 = 32;
+
+/// Cluster epoch schedule, as returned by `getEpochSchedule`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochSchedule {
+    pub slots_per_epoch: u64,
+    pub first_normal_epoch: u64,
+    pub first_normal_slot: u64,
+}
+
+impl EpochSchedule {
+    /// Mainnet-beta schedule (no warmup)
+    pub fn mainnet() -> Self {
+        Self {
+            slots_per_epoch: 432_000,
+            first_normal_epoch: 0,
+            first_normal_slot: 0,
+        }
+    }
+
+    /// Epoch containing `slot`, accounting for warmup epochs
+    pub fn epoch_for_slot(&self, slot: u64) -> u64 {
+        if slot < self.first_normal_slot {
+            // Warmup epoch N spans MINIMUM_SLOTS_PER_EPOCH * 2^N slots
+            let epoch = (slot + MINIMUM_SLOTS_PER_EPOCH + 1)
+                .next_power_of_two()
+                .trailing_zeros()
+                .saturating_sub(MINIMUM_SLOTS_PER_EPOCH.trailing_zeros())
+                .saturating_sub(1);
+            u64::from(epoch)
+        } else {
+            let normal_slot_index = slot - self.first_normal_slot;
+            self.first_normal_epoch + normal_slot_index.checked_div(self.slots_per_epoch).unwrap_or(0)
+        }
+    }
+
+    /// First slot of `epoch`, the inverse of `epoch_for_slot`
+    pub fn first_slot_in_epoch(&self, epoch: u64) -> u64 {
+        if epoch < self.first_normal_epoch {
+            (2u64.pow(epoch as u32) - 1) * MINIMUM_SLOTS_PER_EPOCH
+        } else {
+            self.first_normal_slot + (epoch - self.first_normal_epoch) * self.slots_per_epoch
+        }
+    }
+
+    /// SQL expression computing the epoch of a BIGINT slot column, matching
+    /// `epoch_for_slot`. Warmup epochs use the bit length of `slot + 32`.
+    pub fn sql_epoch_expr(&self, column: &str) -> String {
+        format!(
+            "(CASE WHEN {col} < {first_normal_slot} \
+                THEN length(ltrim(({col} + {min})::bit(64)::text, '0')) - {min_bits} - 1 \
+                ELSE {first_normal_epoch} + ({col} - {first_normal_slot}) / {slots_per_epoch} \
+             END)::bigint",
+            col = column,
+            first_normal_slot = self.first_normal_slot,
+            min = MINIMUM_SLOTS_PER_EPOCH,
+            min_bits = MINIMUM_SLOTS_PER_EPOCH.trailing_zeros(),
+            first_normal_epoch = self.first_normal_epoch,
+            slots_per_epoch = std::cmp::max(1, self.slots_per_epoch),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fresh_database;
+
+    /// Devnet-style warmup: epochs of 32, 64, ... 4096 slots, then 8192 from slot 8160
+    fn warmup() -> EpochSchedule {
+        EpochSchedule {
+            slots_per_epoch: 8192,
+            first_normal_epoch: 8,
+            first_normal_slot: 8160,
+        }
+    }
+
+    const BOUNDARIES: [(u64, u64); 9] =
+        [(0, 0), (31, 0), (32, 1), (95, 1), (96, 2), (8159, 7), (8160, 8), (8160 + 8191, 8), (8160 + 8192, 9)];
+
+    #[test]
+    fn warmup_and_normal_epochs_split_at_their_boundaries() {
+        let schedule = warmup();
+        for (slot, epoch) in BOUNDARIES {
+            assert_eq!(schedule.epoch_for_slot(slot), epoch, "slot {}", slot);
+        }
+        assert_eq!(EpochSchedule::mainnet().epoch_for_slot(431_999), 0);
+        assert_eq!(EpochSchedule::mainnet().epoch_for_slot(432_000), 1);
+    }
+
+    #[test]
+    fn first_slot_in_epoch_round_trips() {
+        for schedule in [warmup(), EpochSchedule::mainnet()] {
+            for epoch in 0..12 {
+                let first = schedule.first_slot_in_epoch(epoch);
+                assert_eq!(schedule.epoch_for_slot(first), epoch, "{:?} epoch {}", schedule, epoch);
+                if first > 0 {
+                    assert_eq!(schedule.epoch_for_slot(first - 1), epoch - 1, "{:?} epoch {}", schedule, epoch);
+                }
+            }
+        }
+        assert_eq!(warmup().first_slot_in_epoch(8), 8160);
+        assert_eq!(warmup().first_slot_in_epoch(9), 8160 + 8192);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn the_sql_expression_agrees_with_epoch_for_slot() {
+        let pool = sqlx::PgPool::connect(&fresh_database().await).await.unwrap();
+        for schedule in [warmup(), EpochSchedule::mainnet()] {
+            let query = format!("SELECT {} FROM (SELECT $1::bigint AS slot) s", schedule.sql_epoch_expr("slot"));
+            let slots = BOUNDARIES.iter().map(|&(slot, _)| slot).chain([431_999, 432_000]);
+            for slot in slots {
+                let epoch: i64 = sqlx::query_scalar(&query).bind(slot as i64).fetch_one(&pool).await.unwrap();
+                assert_eq!(epoch as u64, schedule.epoch_for_slot(slot), "{:?} slot {}", schedule, slot);
+            }
+        }
+    }
+}
@@ -7,9 +7,25 @@ pub enum ETLError {
     #[error("RPC error: {0}")]
     RPC(String),
 
+    /// A JSON-RPC error response, keeping its code for callers that branch on it
+    #[error("RPC error {code}: {message}")]
+    RPCCode { code: i64, message: String },
+
+    /// `ALCHEMY_MAX_REQUESTS_PER_RUN` requests have been sent
+    #[error("RPC request budget of {0} requests for this run is exhausted")]
+    RequestBudgetExhausted(u64),
+
+    /// The RPC node no longer has the slot's block and never will
+    #[error("Slot {0} is no longer available from the RPC node (purged from its ledger); use an archival endpoint")]
+    SlotPurged(u64),
+
     #[error("Database error: {0}")]
     Database(String),
 
+    /// A statement cancelled by `statement_timeout` (SQLSTATE 57014)
+    #[error("Database statement timed out: {0}")]
+    StatementTimeout(String),
+
     #[error("Parse error: {0}")]
     Parse(String),
 
@@ -22,6 +38,9 @@ pub enum ETLError {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("Avro error: {0}")]
+    Avro(#[from] apache_avro::Error),
+
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
 
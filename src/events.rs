@@ -1,7 +1,102 @@
+use crate::error::ETLError;
+use base58::{FromBase58, ToBase58};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How event ids are encoded (`ETL_EVENT_ID_FORMAT`). Both encode the same
+/// SHA-256 digest, so a stored hex id converts to base58 without the event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventIdFormat {
+    /// Full digest as 64 hex chars (the original format)
+    #[default]
+    Sha256Hex,
+    /// First 16 bytes of the digest in base58 (at most 22 chars)
+    Base58_128,
+}
+
+impl EventIdFormat {
+    fn encode(self, digest: &[u8]) -> String {
+        match self {
+            EventIdFormat::Sha256Hex => to_hex(digest),
+            EventIdFormat::Base58_128 => digest[..16].to_base58(),
+        }
+    }
+}
+
+impl fmt::Display for EventIdFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EventIdFormat::Sha256Hex => "sha256_hex",
+            EventIdFormat::Base58_128 => "base58_128",
+        })
+    }
+}
+
+impl FromStr for EventIdFormat {
+    type Err = ETLError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sha256_hex" => Ok(EventIdFormat::Sha256Hex),
+            "base58_128" => Ok(EventIdFormat::Base58_128),
+            other => Err(ETLError::Config(format!(
+                "Unknown event id format {} (use sha256_hex or base58_128)",
+                other
+            ))),
+        }
+    }
+}
+
+/// A stored `sha256_hex` id in `format`, or `None` if `id` isn't hex
+pub fn reencode_event_id(id: &str, format: EventIdFormat) -> Option<String> {
+    if id.len() != 64 {
+        return None;
+    }
+    let digest = (0..64)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(id.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(format.encode(&digest))
+}
+
+/// An amount of lamports. Serialized as a string so values above 2^53
+/// survive JSON consumers that parse numbers as doubles; deserializes from
+/// a string or a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Lamports(pub u64);
+
+impl fmt::Display for Lamports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for Lamports {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Lamports {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u64),
+            String(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(lamports) => Ok(Lamports(lamports)),
+            Repr::String(s) => s.parse().map(Lamports).map_err(serde::de::Error::custom),
+        }
+    }
+}
 
 /// Canonical event model - base fields shared by all events
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,10 +105,26 @@ pub struct CanonicalEvent {
     pub slot: u64,
     pub block_time: DateTime<Utc>,
     pub tx_signature: String,
+    /// Position of the transaction within its block
+    #[serde(default)]
+    pub transaction_index: u32,
     pub program_id: Option<String>,
     pub instruction_index: i32,
     pub event_type: String,
     pub raw_payload: Value,
+    /// Resolved accounts touched by an instruction, in instruction order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<String>,
+    /// Block height of the containing block (`None` for blocks that predate `blockHeight`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u64>,
+    /// Whether the containing transaction succeeded (`meta.err` is null);
+    /// `None` for events outside a transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+    /// Validator scheduled to produce the block (`ETL_RESOLVE_LEADERS`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader: Option<String>,
 }
 
 /// Transaction event with denormalized fields
@@ -24,21 +135,35 @@ pub struct TransactionEvent {
     pub wallet: Option<String>,
     pub wallet_secondary: Option<String>,
     pub token_mint: Option<String>,
-    pub lamports: Option<i64>,
-    pub token_amount: Option<String>, // Use string for precision
+    pub lamports: Option<Lamports>,
+    /// Normalized (decimals applied) token amount, serialized as a string
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub token_amount: Option<Decimal>,
     pub fee_payer: Option<String>,
-    pub transaction_fee: Option<u64>,
+    pub transaction_fee: Option<Lamports>,
     pub success: Option<bool>,
     pub error_message: Option<String>,
+    /// Transaction signers, fee payer first
+    #[serde(default)]
+    pub signers: Vec<String>,
+    /// All transaction signatures; the first is `base.tx_signature`
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Blockhash the transaction was signed against
+    #[serde(default)]
+    pub recent_blockhash: Option<String>,
 }
 
 /// Program event extracted from instructions/logs
+///
+/// The resolved instruction accounts live on `base.accounts`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProgramEvent {
     #[serde(flatten)]
     pub base: CanonicalEvent,
     pub instruction_type: Option<String>,
-    pub accounts: Vec<String>,
+    /// Invocation depth: 1 for top-level instructions, 2+ for CPIs
+    pub stack_height: i32,
     pub data_hex: Option<String>,
     pub log_messages: Vec<String>,
     pub log_pattern_match: Option<String>,
@@ -52,7 +177,9 @@ pub struct TokenTransferEvent {
     pub token_mint: String,
     pub from_wallet: Option<String>,
     pub to_wallet: String,
-    pub token_amount: String, // Normalized amount
+    /// Normalized (decimals applied) amount, serialized as a string
+    #[serde(with = "rust_decimal::serde::str")]
+    pub token_amount: Decimal,
     pub decimals: Option<u8>,
     pub raw_amount: Option<String>,
     pub authority: Option<String>,
@@ -72,17 +199,63 @@ pub struct TelemetryEvent {
 }
 
 impl CanonicalEvent {
-    /// Generate deterministic event_id
+    /// Generate deterministic event_id as `sha256_hex`; parsers re-encode
+    /// it with `encode_event_id` when another format is configured.
+    ///
+    /// The hash input is `slot:tx_signature:instruction_index:event_type`.
+    /// A signature identifies one transaction, so `transaction_index` adds
+    /// nothing; the only inputs that can repeat are a signature listed twice
+    /// in a block or several same-typed events from one instruction, and
+    /// those are disambiguated by the parser. The 128-bit encoding makes an
+    /// accidental collision negligible (birthday bound ~2^64 events).
     pub fn generate_event_id(
         slot: u64,
         tx_signature: &str,
         instruction_index: i32,
         event_type: &str,
     ) -> String {
-        let input = format!("{}:{}:{}:{}", slot, tx_signature, instruction_index, event_type);
+        Self::generate_event_id_as(EventIdFormat::Sha256Hex, slot, tx_signature, instruction_index, event_type)
+    }
+
+    /// `generate_event_id` in an explicit `format`, for rewriting stored ids
+    pub fn generate_event_id_as(
+        format: EventIdFormat,
+        slot: u64,
+        tx_signature: &str,
+        instruction_index: i32,
+        event_type: &str,
+    ) -> String {
+        format.encode(&event_id_digest(slot, tx_signature, instruction_index, event_type))
+    }
+
+    /// Deterministic id, in `format`, for the `occurrence`-th (2, 3, ...)
+    /// event that would otherwise share the id of these fields within a
+    /// block. Hashes the hex form of the base id whatever the format, so
+    /// re-encoding a stored hex id gives the same result as generating it in
+    /// the new format.
+    pub fn disambiguated_event_id(
+        format: EventIdFormat,
+        slot: u64,
+        tx_signature: &str,
+        instruction_index: i32,
+        event_type: &str,
+        occurrence: u32,
+    ) -> String {
+        let base = to_hex(&event_id_digest(slot, tx_signature, instruction_index, event_type));
         let mut hasher = Sha256::new();
-        hasher.update(input.as_bytes());
-        format!("{:x}", hasher.finalize())
+        hasher.update(format!("{}:dup:{}", base, occurrence).as_bytes());
+        format.encode(&hasher.finalize())
+    }
+
+    /// Re-encode this event's `sha256_hex` id (as `new` generates it) in
+    /// `format`; ids that aren't hex are left as they are
+    pub fn encode_event_id(&mut self, format: EventIdFormat) {
+        if format == EventIdFormat::Sha256Hex {
+            return;
+        }
+        if let Some(id) = reencode_event_id(&self.event_id, format) {
+            self.event_id = id;
+        }
     }
 
     pub fn new(
@@ -101,11 +274,351 @@ impl CanonicalEvent {
             slot,
             block_time,
             tx_signature,
+            transaction_index: 0,
             program_id,
             instruction_index,
             event_type,
             raw_payload,
+            accounts: Vec::new(),
+            block_height: None,
+            success: None,
+            leader: None,
+        }
+    }
+}
+
+impl TransactionEvent {
+    /// Build a transaction event from a `transaction` canonical event, whose
+    /// payload is the full transaction with its meta.
+    pub fn from_canonical(event: &CanonicalEvent) -> Option<Self> {
+        if event.event_type != "transaction" {
+            return None;
+        }
+
+        let signers = extract_signers(&event.raw_payload);
+        let fee_payer = signers.first().cloned();
+        let meta = event.raw_payload.get("meta");
+        let err = meta.and_then(|m| m.get("err")).filter(|e| !e.is_null());
+        // `err` is a bare string ("AccountInUse") or an object
+        // ({"InstructionError": [0, {"Custom": 1}]})
+        let error_message = err.map(|e| match e.as_str() {
+            Some(s) => s.to_string(),
+            None => e.to_string(),
+        });
+
+        Some(Self {
+            base: event.clone(),
+            wallet: fee_payer.clone(),
+            wallet_secondary: None,
+            token_mint: None,
+            lamports: None,
+            token_amount: None,
+            fee_payer,
+            transaction_fee: meta.and_then(|m| m.get("fee")).and_then(|v| v.as_u64()).map(Lamports),
+            success: event.success.or_else(|| meta.map(|_| err.is_none())),
+            error_message,
+            signers,
+            signatures: extract_signatures(&event.raw_payload),
+            recent_blockhash: extract_recent_blockhash(&event.raw_payload),
+        })
+    }
+}
+
+impl TokenTransferEvent {
+    /// Build a typed transfer from a `token_transfer` canonical event.
+    ///
+    /// The payload is a `postTokenBalances` entry, so the owner is the receiving
+    /// wallet and the sender is unknown until pre/post balances are matched.
+    /// An amount that can't be read as a decimal is logged and yields `None`
+    /// rather than a transfer of zero.
+    pub fn from_canonical(event: &CanonicalEvent) -> Option<Self> {
+        if event.event_type != "token_transfer" {
+            return None;
+        }
+
+        let payload = &event.raw_payload;
+        let token_mint = payload.get("mint").and_then(|v| v.as_str())?.to_string();
+        let to_wallet = payload
+            .get("owner")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let ui_amount = payload.get("uiTokenAmount");
+        let raw_amount = ui_amount
+            .and_then(|a| a.get("amount"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        let decimals = ui_amount
+            .and_then(|a| a.get("decimals"))
+            .and_then(|v| v.as_u64())
+            .and_then(|d| u8::try_from(d).ok());
+        // Prefer the RPC's normalized string; otherwise scale the raw amount
+        let token_amount = ui_amount
+            .and_then(|a| a.get("uiAmountString"))
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse::<Decimal>().ok())
+            .or_else(|| {
+                let raw: u64 = raw_amount.as_deref()?.parse().ok()?;
+                Decimal::try_from_i128_with_scale(raw as i128, decimals.unwrap_or(0) as u32).ok()
+            });
+        let Some(token_amount) = token_amount else {
+            tracing::warn!(
+                "Skipping typed token transfer {}: unreadable amount {:?}",
+                event.event_id,
+                ui_amount.map(|a| a.to_string())
+            );
+            return None;
+        };
+
+        Some(Self {
+            base: event.clone(),
+            token_mint,
+            from_wallet: None,
+            to_wallet,
+            token_amount,
+            decimals,
+            raw_amount,
+            authority: None,
+        })
+    }
+}
+
+impl ProgramEvent {
+    /// Build a program event from a `program_instruction` canonical event.
+    ///
+    /// Anchor programs prefix instruction data with an 8-byte sighash, so
+    /// `instruction_type` is `anchor:<hex>` (or the name from `names`, keyed
+    /// by the same hex) even without an IDL. Data shorter than 8 bytes leaves
+    /// it unset. `stack_height` comes from the RPC's `stackHeight` when
+    /// present; otherwise the instruction is top-level (1).
+    pub fn from_canonical(event: &CanonicalEvent, names: &HashMap<String, String>) -> Option<Self> {
+        if event.event_type != "program_instruction" {
+            return None;
         }
+
+        let data = event
+            .raw_payload
+            .get("data")
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.from_base58().ok());
+
+        let instruction_type = data
+            .as_deref()
+            .and_then(|d| d.get(..8))
+            .map(|discriminator| {
+                let hex = to_hex(discriminator);
+                names
+                    .get(&hex)
+                    .cloned()
+                    .unwrap_or_else(|| format!("anchor:{}", hex))
+            });
+
+        let stack_height = event
+            .raw_payload
+            .get("stackHeight")
+            .and_then(|v| v.as_i64())
+            .map_or(1, |h| h as i32);
+
+        Some(Self {
+            base: event.clone(),
+            instruction_type,
+            stack_height,
+            data_hex: data.as_deref().map(to_hex),
+            log_messages: Vec::new(),
+            log_pattern_match: None,
+        })
     }
 }
 
+/// All signatures of a transaction, in signer order. The first is the
+/// `tx_signature` used for identity; multisig transactions have more.
+fn extract_signatures(tx: &Value) -> Vec<String> {
+    tx.get("transaction")
+        .and_then(|t| t.get("signatures"))
+        .and_then(|v| v.as_array())
+        .map(|sigs| sigs.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The blockhash a transaction was signed against
+/// (`transaction.message.recentBlockhash`); it expires ~150 blocks later
+fn extract_recent_blockhash(tx: &Value) -> Option<String> {
+    tx.pointer("/transaction/message/recentBlockhash")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
+
+/// Extract the signers of a transaction, fee payer first.
+///
+/// Signers are the first `header.numRequiredSignatures` account keys; for
+/// jsonParsed keys (`{pubkey, signer}` objects) the `signer` flag is used
+/// when the header is absent.
+fn extract_signers(tx: &Value) -> Vec<String> {
+    let message = match tx.get("transaction").and_then(|t| t.get("message")) {
+        Some(message) => message,
+        None => return Vec::new(),
+    };
+    let account_keys = match message.get("accountKeys").and_then(|v| v.as_array()) {
+        Some(keys) => keys,
+        None => return Vec::new(),
+    };
+
+    let num_signers = message
+        .get("header")
+        .and_then(|h| h.get("numRequiredSignatures"))
+        .and_then(|v| v.as_u64());
+
+    account_keys
+        .iter()
+        .enumerate()
+        .filter(|(idx, key)| match num_signers {
+            Some(n) => (*idx as u64) < n,
+            None => key.get("signer").and_then(|v| v.as_bool()).unwrap_or(false),
+        })
+        .filter_map(|(_, key)| {
+            key.as_str()
+                .or_else(|| key.get("pubkey").and_then(|v| v.as_str()))
+                .map(|s| s.to_string())
+        })
+        .collect()
+}
+
+fn event_id_digest(slot: u64, tx_signature: &str, instruction_index: i32, event_type: &str) -> Vec<u8> {
+    let input = format!("{}:{}:{}:{}", slot, tx_signature, instruction_index, event_type);
+    let mut hasher = Sha256::new();
+    hasher.update(input.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base58::ToBase58;
+    use serde_json::json;
+
+    fn instruction(data: &[u8]) -> CanonicalEvent {
+        CanonicalEvent::new(
+            100,
+            DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            "sig1".to_string(),
+            Some("Prog111".to_string()),
+            0,
+            "program_instruction".to_string(),
+            json!({ "programIdIndex": 1, "accounts": [0], "data": data.to_base58() }),
+        )
+    }
+
+    #[test]
+    fn anchor_discriminator_names_the_instruction() {
+        let sighash = Sha256::digest(b"global:initialize");
+        let mut data = sighash[..8].to_vec();
+        data.extend_from_slice(&[7, 0, 0, 0]);
+        let hex = to_hex(&sighash[..8]);
+        assert_eq!(hex, "afaf6d1f0d989bed");
+
+        let event = instruction(&data);
+        let unnamed = ProgramEvent::from_canonical(&event, &HashMap::new()).unwrap();
+        assert_eq!(unnamed.instruction_type.as_deref(), Some("anchor:afaf6d1f0d989bed"));
+
+        let names = HashMap::from([(hex, "initialize".to_string())]);
+        let named = ProgramEvent::from_canonical(&event, &names).unwrap();
+        assert_eq!(named.instruction_type.as_deref(), Some("initialize"));
+    }
+
+    #[test]
+    fn short_data_has_no_discriminator() {
+        let event = instruction(&[1, 2, 3]);
+        let program_event = ProgramEvent::from_canonical(&event, &HashMap::new()).unwrap();
+        assert_eq!(program_event.instruction_type, None);
+    }
+
+    #[test]
+    fn multisig_signers_keep_their_order_with_the_fee_payer_first() {
+        let mut tx = crate::test_support::transaction("sig1", &["payer", "cosigner1", "cosigner2", "Prog111"], json!([]));
+        tx["transaction"]["message"]["header"]["numRequiredSignatures"] = json!(3);
+        let event = |payload| {
+            CanonicalEvent::new(
+                100,
+                DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                "sig1".to_string(),
+                None,
+                -1,
+                "transaction".to_string(),
+                payload,
+            )
+        };
+
+        let tx_event = TransactionEvent::from_canonical(&event(tx.clone())).unwrap();
+        assert_eq!(tx_event.signers, vec!["payer", "cosigner1", "cosigner2"]);
+        assert_eq!(tx_event.fee_payer.as_deref(), Some("payer"));
+
+        // jsonParsed keys carry their own signer flag instead of a header
+        tx["transaction"]["message"]["header"] = Value::Null;
+        tx["transaction"]["message"]["accountKeys"] = json!([
+            { "pubkey": "payer", "signer": true },
+            { "pubkey": "Prog111", "signer": false },
+            { "pubkey": "cosigner1", "signer": true },
+        ]);
+        let tx_event = TransactionEvent::from_canonical(&event(tx)).unwrap();
+        assert_eq!(tx_event.signers, vec!["payer", "cosigner1"]);
+        assert_eq!(tx_event.fee_payer.as_deref(), Some("payer"));
+    }
+
+    #[test]
+    fn max_u64_lamports_round_trip() {
+        let max = Lamports(u64::MAX);
+        let json = serde_json::to_string(&max).unwrap();
+        assert_eq!(json, "\"18446744073709551615\"");
+        assert_eq!(serde_json::from_str::<Lamports>(&json).unwrap(), max);
+        assert_eq!(serde_json::from_str::<Lamports>("18446744073709551615").unwrap(), max);
+        assert!(serde_json::from_str::<Lamports>("\"18446744073709551616\"").is_err());
+
+        let event = CanonicalEvent::new(
+            100,
+            DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            "sig1".to_string(),
+            None,
+            -1,
+            "transaction".to_string(),
+            json!({
+                "transaction": { "signatures": ["sig1"], "message": { "accountKeys": ["payer", "vault"] } },
+                "meta": { "err": null, "fee": u64::MAX },
+            }),
+        );
+        let mut tx_event = TransactionEvent::from_canonical(&event).unwrap();
+        assert_eq!(tx_event.transaction_fee, Some(max));
+        tx_event.lamports = Some(max);
+
+        let json = serde_json::to_value(&tx_event).unwrap();
+        assert_eq!(json["lamports"], json!("18446744073709551615"));
+        let back: TransactionEvent = serde_json::from_value(json).unwrap();
+        assert_eq!((back.lamports, back.transaction_fee), (Some(max), Some(max)));
+    }
+
+    #[test]
+    fn token_amounts_are_exact_or_absent_never_zero() {
+        let transfer = |ui_amount: Value| {
+            CanonicalEvent::new(
+                100,
+                DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                "sig1".to_string(),
+                None,
+                0,
+                "token_transfer".to_string(),
+                json!({ "mint": "MintA", "owner": "alice", "uiTokenAmount": ui_amount }),
+            )
+        };
+
+        let event = transfer(json!({ "amount": u64::MAX.to_string(), "decimals": 9 }));
+        let amount = TokenTransferEvent::from_canonical(&event).unwrap().token_amount;
+        assert_eq!(amount.to_string(), "18446744073.709551615");
+
+        let event = transfer(json!({ "amount": "not a number", "decimals": 9, "uiAmountString": "lots" }));
+        assert!(TokenTransferEvent::from_canonical(&event).is_none());
+    }
+}
@@ -1,8 +1,12 @@
 use crate::config::Config;
 use crate::error::{ETLError, Result};
 use crate::rpc::AlchemyRPCClient;
+use crate::warehouse::connect_postgres;
 use tracing::{info, warn};
 
+/// How far back `status` averages ingestion throughput
+const STATUS_WINDOW_MINUTES: i64 = 60;
+
 /// Check pipeline health
 pub async fn check_health(config: Config) -> Result<()> {
     info!("Running health check");
@@ -57,3 +61,57 @@ pub async fn check_health(config: Config) -> Result<()> {
     Ok(())
 }
 
+/// Show checkpoint, lag behind the chain tip and recent ingestion throughput
+pub async fn run_status(config: Config) -> Result<()> {
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    let chain_tip = rpc_client.get_slot().await?;
+
+    let warehouse = crate::warehouse::create_warehouse(config.warehouse.clone())?;
+    warehouse.connect().await?;
+    match warehouse.get_last_slot(&config.alchemy.commitment).await? {
+        Some(last_slot) => info!(
+            "Checkpoint: slot {} (chain tip {}, {} slots behind)",
+            last_slot,
+            chain_tip,
+            chain_tip.saturating_sub(last_slot)
+        ),
+        None => info!("Checkpoint: none (chain tip {})", chain_tip),
+    }
+
+    if config.warehouse.warehouse_type != "postgres" {
+        info!("Ingestion rate: only tracked for Postgres");
+        return Ok(());
+    }
+    let pool = connect_postgres(&config.warehouse).await?;
+
+    // Windows from parallel backfill workers overlap, so divide by the
+    // wall-clock span they cover rather than their summed durations
+    let (slots, events, span_seconds): (i64, i64, f64) = sqlx::query_as(
+        r#"
+        SELECT COALESCE(SUM(slots_processed), 0)::bigint,
+               COALESCE(SUM(events_inserted), 0)::bigint,
+               COALESCE(EXTRACT(EPOCH FROM MAX(created_at) - MIN(window_start)), 0)::float8
+        FROM ingestion_stats
+        WHERE window_start > NOW() - make_interval(mins => $1::int)
+        "#
+    )
+    .bind(STATUS_WINDOW_MINUTES as i32)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to read ingestion stats: {}", e)))?;
+
+    if slots == 0 || span_seconds <= 0.0 {
+        info!("Ingestion rate: no windows recorded in the last {} minutes", STATUS_WINDOW_MINUTES);
+    } else {
+        info!(
+            "Ingestion rate (last {} min): {:.2} slots/s, {:.1} events/s ({} slots, {} events)",
+            STATUS_WINDOW_MINUTES,
+            slots as f64 / span_seconds,
+            events as f64 / span_seconds,
+            slots,
+            events
+        );
+    }
+
+    Ok(())
+}
@@ -1,106 +1,654 @@
+use crate::block_source::BlockSource;
+use crate::clock::{SharedClock, SystemClock};
 use crate::config::Config;
-use crate::error::Result;
-use crate::parsers::{flatten_instructions, parse_block};
+use crate::error::{ETLError, Result};
+use crate::events::CanonicalEvent;
+use crate::leaders::LeaderSchedules;
+use crate::parsers::{
+    aggregate_instructions, drop_failed_effects, flatten_instructions, limit_payload_size, retain_event_types, widen_transactions,
+    ParseOptions, ParsePool, ParseWarning,
+};
 use crate::rpc::AlchemyRPCClient;
-use crate::warehouse::Warehouse;
+use crate::stats::{BatchThreshold, IngestionWindow, StallWatch};
+use crate::warehouse::{record_parse_warnings, DerivedRows, Progress, Warehouse};
+use rand::Rng;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
+
+/// Slots behind the tip a fresh run starts from, so it begins with a few
+/// already-available blocks
+const START_FROM_TIP_BUFFER_SLOTS: u64 = 10;
+
+/// A null block this close to the chain tip is usually one the node hasn't
+/// made available yet rather than a skipped slot, so it is retried
+const NOT_YET_AVAILABLE_SLOTS: u64 = 32;
+
+/// How often a running pass is checked for a stall, so one that hangs is
+/// reported without waiting for it to return
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Outcome of one `Incremental::process` pass
+struct IncrementalRun {
+    last_slot: u64,
+    behind_tip: bool, // There were new slots to ingest
+}
 
 /// Run incremental loader
-pub async fn run_incremental(config: Config, interval_seconds: u64) -> Result<()> {
+///
+/// With `until_slot` set, the loader stops once that slot has been processed
+/// instead of polling forever. A run with no checkpoint (or with
+/// `start_from_tip`) starts just behind the current chain tip rather than
+/// replaying history from slot 0.
+pub async fn run_incremental(
+    config: Config,
+    interval_seconds: u64,
+    until_slot: Option<u64>,
+    start_from_tip: bool,
+) -> Result<()> {
+    run_incremental_with_clock(config, interval_seconds, until_slot, start_from_tip, SystemClock::shared()).await
+}
+
+/// `run_incremental` with stall detection and ingestion stats timed by
+/// `clock` instead of the system clock
+pub async fn run_incremental_with_clock(
+    config: Config,
+    interval_seconds: u64,
+    until_slot: Option<u64>,
+    start_from_tip: bool,
+    clock: SharedClock,
+) -> Result<()> {
     info!("Starting incremental loader with {}s interval", interval_seconds);
+    if let Some(until_slot) = until_slot {
+        info!("Will stop after slot {}", until_slot);
+    }
 
     let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
     let warehouse = crate::warehouse::create_warehouse(config.warehouse.clone())?;
     warehouse.connect().await?;
+    warehouse.check_event_id_format(config.etl.event_id_format.parse()?).await?;
 
-    let interval = Duration::from_secs(interval_seconds);
+    let loader = Incremental {
+        config: &config,
+        source: &rpc_client,
+        rpc_client: &rpc_client,
+        warehouse: &*warehouse,
+        clock,
+        signatures: Mutex::new(SignatureWindow::new(config.etl.dedup_window_slots)),
+        written: AtomicU64::new(0),
+        stall_check: STALL_CHECK_INTERVAL,
+    };
+    loader.run(Duration::from_secs(interval_seconds), until_slot, start_from_tip).await
+}
 
-    loop {
-        match process_incremental(&rpc_client, &*warehouse, &config).await {
-            Ok(_) => {
-                info!("Incremental run completed");
-            }
-            Err(e) => {
-                warn!("Incremental run failed: {}", e);
+/// Where the incremental loop reads blocks from and writes events to
+struct Incremental<'a> {
+    config: &'a Config,
+    source: &'a dyn BlockSource,
+    rpc_client: &'a AlchemyRPCClient, // Leader schedule lookups
+    warehouse: &'a dyn Warehouse,
+    clock: SharedClock,
+    signatures: Mutex<SignatureWindow>, // `ETL_DEDUP_WINDOW_SLOTS`
+    written: AtomicU64,                 // Events written so far, seen by the stall check mid-pass
+    stall_check: Duration,              // How often the stall check runs
+}
+
+/// Transaction signatures ingested within the last `window_slots` slots, kept
+/// as a ring buffer of slots so evicting a slot drops its signatures and
+/// memory stays flat over long runs
+struct SignatureWindow {
+    window_slots: u64,
+    slots: VecDeque<(u64, Vec<String>)>,
+    last_seen: HashMap<String, u64>,
+}
+
+impl SignatureWindow {
+    fn new(window_slots: u64) -> Self {
+        Self {
+            window_slots,
+            slots: VecDeque::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Record the signatures of `slot`'s transactions and return those already
+    /// seen in a different slot within the window. A slot read again (a retry
+    /// after a failed write) matches only itself, so it is written again.
+    fn observe(&mut self, slot: u64, signatures: Vec<String>) -> HashSet<String> {
+        if self.window_slots == 0 {
+            return HashSet::new();
+        }
+        while self.slots.front().is_some_and(|(oldest, _)| oldest + self.window_slots <= slot) {
+            let (oldest, evicted) = self.slots.pop_front().unwrap();
+            for signature in evicted {
+                // A later sighting keeps the signature alive
+                if self.last_seen.get(&signature) == Some(&oldest) {
+                    self.last_seen.remove(&signature);
+                }
             }
         }
 
-        tokio::time::sleep(interval).await;
+        let mut duplicates = HashSet::new();
+        for signature in &signatures {
+            if let Some(previous) = self.last_seen.insert(signature.clone(), slot) {
+                if previous != slot {
+                    duplicates.insert(signature.clone());
+                }
+            }
+        }
+        self.slots.push_back((slot, signatures));
+        duplicates
     }
 }
 
-/// Process incremental update (new slots since last processed)
-async fn process_incremental(
-    rpc_client: &AlchemyRPCClient,
-    warehouse: &dyn Warehouse,
-    config: &Config,
-) -> Result<()> {
-    // Get current chain tip
-    let chain_tip = rpc_client.get_slot().await?;
+impl Incremental<'_> {
+    /// Poll for new slots every `interval` until `until_slot` (or forever)
+    async fn run(&self, interval: Duration, until_slot: Option<u64>, start_from_tip: bool) -> Result<()> {
+        let config = self.config;
+        let commitment = &config.alchemy.commitment;
+        let checkpoint = self.warehouse.get_last_slot(commitment).await?;
+        match checkpoint {
+            Some(last_slot) if !start_from_tip => info!("Resuming from checkpoint at slot {}", last_slot),
+            _ => {
+                let start = self
+                    .source
+                    .get_slot()
+                    .await?
+                    .saturating_sub(config.etl.finality_confirmations + START_FROM_TIP_BUFFER_SLOTS);
+                match checkpoint {
+                    Some(last_slot) if last_slot >= start => {
+                        info!("Checkpoint at slot {} is already near the tip, resuming", last_slot)
+                    }
+                    Some(last_slot) => {
+                        info!(
+                            "Starting from tip: skipping slots {} to {} after the checkpoint",
+                            last_slot + 1,
+                            start
+                        );
+                        self.warehouse.update_last_slot(commitment, start).await?;
+                    }
+                    None => {
+                        info!("No checkpoint found, starting fresh from the chain tip at slot {}", start + 1);
+                        self.warehouse.update_last_slot(commitment, start).await?;
+                    }
+                }
+            }
+        }
+
+        let leaders = LeaderSchedules::for_ingestion(config, self.rpc_client).await;
+        let mut stall_watch = StallWatch::with_clock(config.etl.stall_timeout_seconds, self.clock.clone());
+        let mut written = 0;
+
+        loop {
+            // Check for a stall while the pass runs too: it has slots to
+            // ingest, so it stalls if it writes nothing for the timeout
+            let pass = self.process(leaders.as_ref(), until_slot);
+            tokio::pin!(pass);
+            let mut checks = tokio::time::interval(self.stall_check);
+            let result = loop {
+                tokio::select! {
+                    result = &mut pass => break result,
+                    _ = checks.tick() => self.check_stall(&mut stall_watch, &mut written, true)?,
+                }
+            };
+
+            // A failed run counts as behind the tip with nothing written
+            let behind_tip = match result {
+                Ok(run) => {
+                    info!("Incremental run completed");
+                    if let Some(until_slot) = until_slot {
+                        if run.last_slot >= until_slot {
+                            info!("Reached until-slot {}, stopping", until_slot);
+                            return Ok(());
+                        }
+                    }
+                    run.behind_tip
+                }
+                Err(e @ ETLError::RequestBudgetExhausted(_)) => return Err(e),
+                Err(e) => {
+                    warn!("Incremental run failed: {}", e);
+                    true
+                }
+            };
+            self.check_stall(&mut stall_watch, &mut written, behind_tip)?;
+
+            tokio::time::sleep(jittered(interval, config.etl.poll_jitter_pct)).await;
+        }
+    }
 
-    // Get last processed slot
-    let last_slot = warehouse.get_last_slot().await?.unwrap_or(0);
+    /// Report a stall if nothing was written since `written` events for the
+    /// stall timeout while behind the tip, failing with `ETL_EXIT_ON_STALL`
+    fn check_stall(&self, stall_watch: &mut StallWatch, written: &mut u64, behind_tip: bool) -> Result<()> {
+        let now_written = self.written.load(Ordering::Relaxed);
+        let events_written = now_written - std::mem::replace(written, now_written);
+        if let Some(stalled) = stall_watch.observe(events_written, behind_tip) {
+            error!(
+                "Ingestion stalled: no events written for {}s while behind the chain tip",
+                stalled.as_secs()
+            );
+            if self.config.etl.exit_on_stall {
+                return Err(ETLError::Generic(anyhow::anyhow!(
+                    "Ingestion stalled for {}s",
+                    stalled.as_secs()
+                )));
+            }
+        }
+        Ok(())
+    }
 
-    if chain_tip <= last_slot {
-        info!("No new slots (tip: {}, last: {})", chain_tip, last_slot);
-        return Ok(());
+    /// Drop the events of transactions already ingested from another slot
+    /// within `ETL_DEDUP_WINDOW_SLOTS`
+    fn drop_seen_transactions(&self, slot: u64, events: &mut Vec<CanonicalEvent>) {
+        if self.config.etl.dedup_window_slots == 0 {
+            return;
+        }
+        // Transaction events may be filtered out, so take every event's signature
+        let signatures: HashSet<&str> = events
+            .iter()
+            .map(|e| e.tx_signature.as_str())
+            .filter(|signature| !signature.is_empty())
+            .collect();
+        let signatures = signatures.into_iter().map(str::to_string).collect();
+        let duplicates = self.signatures.lock().unwrap().observe(slot, signatures);
+        if !duplicates.is_empty() {
+            info!("Slot {}: skipping {} transactions already seen in another slot", slot, duplicates.len());
+            events.retain(|e| !duplicates.contains(&e.tx_signature));
+        }
     }
 
-    let start_slot = last_slot + 1;
-    let end_slot = chain_tip + 1; // Exclusive end
+    /// Process incremental update (new slots since last processed)
+    ///
+    /// Returns the last processed slot and whether there were new slots.
+    /// Events are counted on `written` as each batch lands.
+    async fn process(&self, leaders: Option<&LeaderSchedules>, until_slot: Option<u64>) -> Result<IncrementalRun> {
+        let (config, warehouse) = (self.config, self.warehouse);
+        // Get current chain tip, held back by the finality margin and capped at
+        // the requested stopping slot
+        let raw_tip = self.source.get_slot().await?;
+        let mut chain_tip = raw_tip.saturating_sub(config.etl.finality_confirmations);
+        if let Some(until_slot) = until_slot {
+            chain_tip = std::cmp::min(chain_tip, until_slot);
+        }
+
+        // Get last processed slot
+        let last_slot = warehouse.get_last_slot(&config.alchemy.commitment).await?.unwrap_or(0);
 
-    info!("Processing slots {} to {} ({} slots)", start_slot, end_slot, end_slot - start_slot);
+        if chain_tip <= last_slot {
+            info!("No new slots (tip: {}, last: {})", chain_tip, last_slot);
+            return Ok(IncrementalRun {
+                last_slot,
+                behind_tip: false,
+            });
+        }
+
+        let start_slot = last_slot + 1;
+        let end_slot = chain_tip + 1; // Exclusive end
 
-    let mut batch = Vec::new();
-    let mut processed_slot = start_slot;
+        info!("Processing slots {} to {} ({} slots)", start_slot, end_slot, end_slot - start_slot);
 
-    // Process slots in order (important for incremental)
-    while processed_slot < end_slot {
-        match rpc_client.get_block(processed_slot, None).await? {
-            Some(block) => {
-                match parse_block(&block, processed_slot) {
-                    Ok(mut events) => {
-                        events = flatten_instructions(events);
-                        batch.extend(events);
+        let mut batch = Vec::new();
+        // Rows derived from the buffered slots, written with their events
+        let mut derived = DerivedRows::default();
+        let mut processed_slot = start_slot;
+        let mut window = IngestionWindow::with_clock("incremental", self.clock.clone());
+        let mut threshold = BatchThreshold::new(&config.etl);
+        let parse_pool = ParsePool::new(config.etl.parse_threads, ParseOptions::for_ingestion(&config.etl)?);
+
+        // Process slots in order (important for incremental)
+        while processed_slot < end_slot {
+            let mut purged = false;
+            let block = match self.source.get_block(processed_slot, config.etl.fetch_rewards).await {
+                // Gone for good, so retrying next run can't help: record it and move on
+                Err(e @ ETLError::SlotPurged(_)) => {
+                    warn!("{}", e);
+                    let warning = ParseWarning::new(processed_slot, None, None, "purged", e.to_string());
+                    record_parse_warnings(warehouse, config.etl.record_parse_warnings, &[warning]).await;
+                    purged = true;
+                    None
+                }
+                result => result?,
+            };
+            match block {
+                Some(block) => {
+                    match parse_pool.parse_raw(block, processed_slot).await {
+                        Ok(mut parsed) => {
+                            if config.etl.skip_failed_instruction_events {
+                                drop_failed_effects(&mut parsed.events);
+                            }
+                            self.drop_seen_transactions(processed_slot, &mut parsed.events);
+                            if config.etl.wide_transactions {
+                                derived.wide.extend(widen_transactions(&parsed.events));
+                            }
+                            if config.etl.aggregate_instructions {
+                                derived.invocations.extend(aggregate_instructions(&mut parsed.events));
+                            }
+                            retain_event_types(&mut parsed.events, config.etl.event_types.as_deref(), config.etl.keep_transaction_events);
+                            if let Some(leaders) = leaders {
+                                leaders.annotate(self.rpc_client, processed_slot, &mut parsed.events).await;
+                            }
+                            limit_payload_size(&mut parsed.events, config.etl.max_payload_bytes);
+                            parsed.log_failures(processed_slot, config.etl.parse_failure_alert_ratio);
+                            record_parse_warnings(warehouse, config.etl.record_parse_warnings, &parsed.warnings).await;
+                            let batch_before = batch.len();
+                            window.record_slot(parsed.events.len());
+                            batch.extend(flatten_instructions(parsed.events));
+                            threshold.record_slot(batch.len() - batch_before);
 
-                        // Batch insert periodically
-                        if batch.len() >= config.etl.batch_size {
-                            warehouse.insert_events(batch.clone()).await?;
-                            batch.clear();
+                            // Batch insert periodically
+                            if threshold.should_flush(batch.len()) {
+                                warehouse.insert_events_with_derived(batch.clone(), &derived, None, false).await?;
+                                self.written.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                                batch.clear();
+                                derived = DerivedRows::default();
+                            }
+                        }
+                        Err(e) => {
+                            let warning = ParseWarning::new(processed_slot, None, None, "block", e.to_string());
+                            record_parse_warnings(warehouse, config.etl.record_parse_warnings, &[warning]).await;
+                            window.record_slot(0);
                         }
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse block at slot {}: {}", processed_slot, e);
                     }
                 }
+                None if purged => window.record_slot(0),
+                None if raw_tip - processed_slot < NOT_YET_AVAILABLE_SLOTS => {
+                    // Near the tip a null is often just a block that isn't available
+                    // yet; stop here and retry it next cycle instead of skipping it
+                    info!("Block not yet available at slot {}, retrying next run", processed_slot);
+                    break;
+                }
+                None => {
+                    warn!("Block not found at slot {} (may be skipped slot)", processed_slot);
+                    window.record_slot(0);
+                }
             }
-            None => {
-                warn!("Block not found at slot {} (may be skipped slot)", processed_slot);
-            }
-        }
 
-        processed_slot += 1;
+            processed_slot += 1;
 
-        // Update checkpoint periodically
-        if (processed_slot - start_slot) % config.etl.checkpoint_interval == 0 {
-            if !batch.is_empty() {
-                warehouse.insert_events(batch.clone()).await?;
+            // Update checkpoint periodically
+            if (processed_slot - start_slot).is_multiple_of(config.etl.checkpoint_interval) {
+                let checkpoint = Progress::Checkpoint(&config.alchemy.commitment, processed_slot - 1);
+                warehouse.insert_events_with_derived(batch.clone(), &derived, Some(checkpoint), false).await?;
+                self.written.fetch_add(batch.len() as u64, Ordering::Relaxed);
                 batch.clear();
+                derived = DerivedRows::default();
+                window.flush(warehouse).await;
             }
-            warehouse.update_last_slot(processed_slot - 1).await?;
         }
+
+        // Insert remaining batch and advance to the last slot handled (the chain
+        // tip unless a slot near it was deferred)
+        let last_processed = processed_slot - 1;
+        let batch_len = batch.len() as u64;
+        let checkpoint = Progress::Checkpoint(&config.alchemy.commitment, last_processed);
+        warehouse.insert_events_with_derived(batch, &derived, Some(checkpoint), false).await?;
+        self.written.fetch_add(batch_len, Ordering::Relaxed);
+        window.flush(warehouse).await;
+
+        info!("Processed up to slot {}", last_processed);
+        Ok(IncrementalRun {
+            last_slot: last_processed,
+            behind_tip: true,
+        })
+    }
+}
+
+/// Randomize a poll interval by up to ± `jitter_pct` percent so a fleet of
+/// loaders started together doesn't poll the RPC in lockstep
+fn jittered(interval: Duration, jitter_pct: f64) -> Duration {
+    if jitter_pct <= 0.0 {
+        return interval;
     }
+    let factor = 1.0 + rand::thread_rng().gen_range(-jitter_pct..=jitter_pct) / 100.0;
+    interval.mul_f64(factor.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::test_support::{block, config, transaction, FakeBlocks, MemoryWarehouse};
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn jittered_intervals_stay_within_the_configured_percent() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(jittered(interval, 0.0), interval);
 
-    // Insert remaining batch
-    if !batch.is_empty() {
-        warehouse.insert_events(batch).await?;
+        let samples: Vec<Duration> = (0..1_000).map(|_| jittered(interval, 10.0)).collect();
+        assert!(samples.iter().all(|d| (Duration::from_secs(9)..=Duration::from_secs(11)).contains(d)));
+        // Spread over both sides of the interval, not stuck on one value
+        assert!(samples.iter().any(|d| *d < Duration::from_millis(9_900)));
+        assert!(samples.iter().any(|d| *d > Duration::from_millis(10_100)));
+
+        // The largest allowed jitter never goes negative
+        assert!((0..1_000).all(|_| jittered(interval, 100.0) <= Duration::from_secs(20)));
     }
 
-    // Update to chain tip
-    warehouse.update_last_slot(chain_tip).await?;
+    #[test]
+    fn signature_window_evicts_slots_once_the_window_passes() {
+        let signatures = |sigs: &[&str]| sigs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let duplicates = |found: HashSet<String>| {
+            let mut found: Vec<String> = found.into_iter().collect();
+            found.sort();
+            found
+        };
+        let mut window = SignatureWindow::new(3);
 
-    info!("Processed up to slot {}", chain_tip);
-    Ok(())
-}
+        assert!(window.observe(100, signatures(&["a", "b"])).is_empty());
+        // Reading the same slot again is not a duplicate
+        assert!(window.observe(100, signatures(&["a"])).is_empty());
+        assert_eq!(duplicates(window.observe(101, signatures(&["a"]))), vec!["a"]);
+
+        // Slot 100 leaves the window; "a" stays, seen again at 101
+        assert!(window.observe(103, signatures(&["c"])).is_empty());
+        assert_eq!(window.slots.iter().map(|(slot, _)| *slot).collect::<Vec<_>>(), vec![101, 103]);
+        assert_eq!(window.last_seen.len(), 2);
+        assert!(!window.last_seen.contains_key("b"));
+
+        // Once slot 101 leaves too, "a" is new again
+        assert!(window.observe(104, signatures(&["a"])).is_empty());
+        assert_eq!(window.last_seen.len(), 2);
+
+        // A window of 0 keeps nothing
+        let mut off = SignatureWindow::new(0);
+        off.observe(100, signatures(&["a"]));
+        assert!(off.observe(101, signatures(&["a"])).is_empty());
+        assert!(off.last_seen.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_transaction_seen_again_within_the_window_is_stored_once() {
+        let mut config = config();
+        config.etl.dedup_window_slots = 10;
+        let mut source = FakeBlocks::with_slots(101..=105);
+        let repeated = transaction("sig101", &["payer", "Prog111"], json!([
+            { "programIdIndex": 1, "accounts": [0], "data": "" }
+        ]));
+        source.blocks.insert(103, block(vec![repeated]).to_string());
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+
+        loader(&config, &source, &rpc_client, &warehouse).process(None, None).await.unwrap();
+        let slots: HashSet<u64> = warehouse
+            .state
+            .lock()
+            .unwrap()
+            .events
+            .values()
+            .filter(|e| e.tx_signature == "sig101")
+            .map(|e| e.slot)
+            .collect();
+        assert_eq!(slots, HashSet::from([101]));
+        assert_eq!(warehouse.slots().into_iter().collect::<Vec<_>>(), vec![101, 102, 104, 105]);
+    }
+
+    #[tokio::test]
+    async fn a_purged_slot_is_recorded_and_passed() {
+        let mut config = config();
+        config.etl.record_parse_warnings = true;
+        let mut source = FakeBlocks::with_slots(101..=105);
+        source.purged.insert(102);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+
+        let run = loader(&config, &source, &rpc_client, &warehouse).process(None, None).await.unwrap();
+        assert_eq!(run.last_slot, 105);
+        assert_eq!(warehouse.checkpoint(&config.alchemy.commitment), Some(105));
+        assert_eq!(warehouse.slots().into_iter().collect::<Vec<_>>(), vec![101, 103, 104, 105]);
+        assert_eq!(warehouse.state.lock().unwrap().warnings, 1);
+    }
+
+    fn loader<'a>(
+        config: &'a Config,
+        source: &'a FakeBlocks,
+        rpc_client: &'a AlchemyRPCClient,
+        warehouse: &'a MemoryWarehouse,
+    ) -> Incremental<'a> {
+        Incremental {
+            config,
+            source,
+            rpc_client,
+            warehouse,
+            clock: SystemClock::shared(),
+            signatures: Mutex::new(SignatureWindow::new(config.etl.dedup_window_slots)),
+            written: AtomicU64::new(0),
+            stall_check: STALL_CHECK_INTERVAL,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_pass_that_hangs_is_reported_as_stalled() {
+        use crate::clock::MockClock;
+
+        let mut config = config();
+        config.etl.stall_timeout_seconds = 60;
+        config.etl.exit_on_stall = true;
+        let mut source = FakeBlocks::with_slots(101..=105);
+        // The first fetch never comes back
+        source.fetch_delay = Some(Duration::from_secs(3600));
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+        let clock = std::sync::Arc::new(MockClock::new(chrono::Utc::now()));
+        let loader = Incremental {
+            clock: clock.clone(),
+            stall_check: Duration::from_millis(10),
+            ..loader(&config, &source, &rpc_client, &warehouse)
+        };
 
+        let run = loader.run(Duration::ZERO, None, false);
+        tokio::pin!(run);
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut run).await.is_err());
+        // Within the timeout the hung pass is left alone
+        clock.advance(Duration::from_secs(59));
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut run).await.is_err());
+
+        clock.advance(Duration::from_secs(2));
+        let err = tokio::time::timeout(Duration::from_secs(5), run).await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("Ingestion stalled for 61s"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn until_slot_stops_at_the_bound() {
+        let config = config();
+        let source = FakeBlocks::with_slots(101..=120);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+        loader(&config, &source, &rpc_client, &warehouse).run(Duration::ZERO, Some(105), false).await.unwrap();
+
+        assert_eq!(warehouse.checkpoint(&config.alchemy.commitment), Some(105));
+        assert_eq!(warehouse.slots().into_iter().collect::<Vec<_>>(), vec![101, 102, 103, 104, 105]);
+    }
+
+    #[tokio::test]
+    async fn slots_within_finality_confirmations_wait_until_they_age_out() {
+        let mut config = config();
+        config.etl.finality_confirmations = 5;
+        let source = FakeBlocks::with_slots(101..=125);
+        source.tip.store(120, Ordering::Relaxed);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+        let loader = loader(&config, &source, &rpc_client, &warehouse);
+
+        let run = loader.process(None, None).await.unwrap();
+        assert_eq!(run.last_slot, 115);
+        assert_eq!(warehouse.slots().last(), Some(&115));
+
+        // Nothing new until the tip moves on
+        let written = loader.written.load(Ordering::Relaxed);
+        loader.process(None, None).await.unwrap();
+        assert_eq!(loader.written.load(Ordering::Relaxed), written);
+
+        source.tip.store(125, Ordering::Relaxed);
+        assert_eq!(loader.process(None, None).await.unwrap().last_slot, 120);
+        assert_eq!(warehouse.slots().last(), Some(&120));
+    }
+
+    #[tokio::test]
+    async fn null_block_at_the_tip_is_retried_not_skipped() {
+        let config = config();
+        let source = FakeBlocks::with_slots(101..=110);
+        source.pending.lock().unwrap().insert(110, 1);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+        let loader = loader(&config, &source, &rpc_client, &warehouse);
+
+        assert_eq!(loader.process(None, None).await.unwrap().last_slot, 109);
+        assert_eq!(warehouse.checkpoint(&config.alchemy.commitment), Some(109));
+
+        assert_eq!(loader.process(None, None).await.unwrap().last_slot, 110);
+        assert!(warehouse.slots().contains(&110));
+    }
+
+    #[tokio::test]
+    async fn null_block_well_behind_the_tip_is_skipped() {
+        let config = config();
+        let mut source = FakeBlocks::with_slots(101..=200);
+        source.blocks.remove(&105);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+
+        let run = loader(&config, &source, &rpc_client, &warehouse).process(None, None).await.unwrap();
+        assert_eq!(run.last_slot, 200);
+        assert!(!warehouse.slots().contains(&105));
+    }
+
+    #[tokio::test]
+    async fn a_fresh_run_starts_behind_the_tip_not_at_slot_0() {
+        let config = config();
+        let source = FakeBlocks::with_slots(0..=1_000);
+        let warehouse = MemoryWarehouse::default();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+
+        loader(&config, &source, &rpc_client, &warehouse).run(Duration::ZERO, Some(1_000), false).await.unwrap();
+
+        let first = 1_000 - config.etl.finality_confirmations - START_FROM_TIP_BUFFER_SLOTS + 1;
+        assert_eq!(warehouse.slots().into_iter().collect::<Vec<_>>(), (first..=1_000).collect::<Vec<_>>());
+        assert_eq!(source.fetches.load(Ordering::Relaxed), 1_000 - first + 1);
+        assert_eq!(warehouse.checkpoint(&config.alchemy.commitment), Some(1_000));
+    }
+
+    #[tokio::test]
+    async fn start_from_tip_skips_past_an_old_checkpoint() {
+        let config = config();
+        let source = FakeBlocks::with_slots(0..=1_000);
+        let warehouse = MemoryWarehouse::default();
+        warehouse.update_last_slot(&config.alchemy.commitment, 100).await.unwrap();
+        let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+
+        loader(&config, &source, &rpc_client, &warehouse).run(Duration::ZERO, Some(1_000), true).await.unwrap();
+
+        let first = 1_000 - config.etl.finality_confirmations - START_FROM_TIP_BUFFER_SLOTS + 1;
+        assert_eq!(warehouse.slots().first(), Some(&first));
+    }
+}
@@ -0,0 +1,53 @@
+//! Reserved `instruction_index` ranges.
+//!
+//! `instruction_index` feeds into the deterministic `event_id`, so every event
+//! kind gets its own non-overlapping range:
+//!
+//! | Space              | Range                                   |
+//! |--------------------|-----------------------------------------|
+//! | Transaction        | `-1`                                    |
+//! | Top-level          | `0..1_000`                              |
+//! | Inner              | `1_000_000 + parent * 1_000 + inner`    |
+//! | Token balance      | `2_000_000 + balance index`             |
+//! | SOL balance change | `3_000_000 + account index`             |
+//! | Reward             | `4_000_000 + reward index`              |
+
+/// Max instructions (top-level, or inner per parent) before ranges would overlap
+pub const MAX_INSTRUCTIONS: u32 = 1_000;
+
+const INNER_OFFSET: i32 = 1_000_000;
+const TOKEN_BALANCE_OFFSET: i32 = 2_000_000;
+const BALANCE_CHANGE_OFFSET: i32 = 3_000_000;
+const REWARD_OFFSET: i32 = 4_000_000;
+
+/// Where an event sits within its transaction (or block, for rewards)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexSpace {
+    Transaction,
+    TopLevel(u32),
+    Inner { parent: u32, inner: u32 },
+    TokenBalance(u32),
+    BalanceChange(u32),
+    Reward(u32),
+}
+
+impl IndexSpace {
+    /// The `instruction_index` value for this position
+    pub fn index(self) -> i32 {
+        match self {
+            IndexSpace::Transaction => -1,
+            IndexSpace::TopLevel(idx) => idx as i32,
+            IndexSpace::Inner { parent, inner } => {
+                INNER_OFFSET + (parent * MAX_INSTRUCTIONS + inner) as i32
+            }
+            IndexSpace::TokenBalance(idx) => TOKEN_BALANCE_OFFSET + idx as i32,
+            IndexSpace::BalanceChange(idx) => BALANCE_CHANGE_OFFSET + idx as i32,
+            IndexSpace::Reward(idx) => REWARD_OFFSET + idx as i32,
+        }
+    }
+
+    /// Whether `index` is a top-level instruction's `instruction_index`
+    pub fn is_top_level(index: i32) -> bool {
+        (0..MAX_INSTRUCTIONS as i32).contains(&index)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::Config;
+use crate::epoch::EpochSchedule;
+use crate::error::Result;
+use crate::events::CanonicalEvent;
+use crate::rpc::AlchemyRPCClient;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// An epoch's slot -> leader map, filled by the first lookup that needs it
+type EpochLeaders = Arc<OnceCell<HashMap<u64, String>>>;
+
+/// Slot-to-leader lookup, fetching `getLeaderSchedule` once per epoch.
+///
+/// Shareable between workers: concurrent lookups of an epoch being fetched
+/// wait for that fetch instead of repeating it, while lookups in other
+/// epochs carry on.
+pub struct LeaderSchedules {
+    epoch_schedule: EpochSchedule,
+    by_epoch: Mutex<HashMap<u64, EpochLeaders>>,
+}
+
+impl LeaderSchedules {
+    pub fn new(epoch_schedule: EpochSchedule) -> Self {
+        Self {
+            epoch_schedule,
+            by_epoch: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cache for ingestion when `ETL_RESOLVE_LEADERS` is on. Falls back to
+    /// the mainnet epoch schedule if it can't be fetched.
+    pub async fn for_ingestion(config: &Config, rpc_client: &AlchemyRPCClient) -> Option<Self> {
+        if !config.etl.resolve_leaders {
+            return None;
+        }
+        let epoch_schedule = match config.etl.epoch_schedule {
+            Some(schedule) => schedule,
+            None => rpc_client.get_epoch_schedule().await.unwrap_or_else(|e| {
+                tracing::warn!("Failed to fetch epoch schedule, assuming mainnet: {}", e);
+                EpochSchedule::mainnet()
+            }),
+        };
+        Some(Self::new(epoch_schedule))
+    }
+
+    /// Leader scheduled for `slot`, if the schedule covers it. Epochs the RPC
+    /// no longer (or doesn't yet) serve a schedule for resolve to `None`.
+    pub async fn leader_for_slot(&self, rpc_client: &AlchemyRPCClient, slot: u64) -> Result<Option<String>> {
+        let epoch = self.epoch_schedule.epoch_for_slot(slot);
+        // Only the map lookup holds the lock, never the RPC call; a failed
+        // fetch leaves the cell empty for the next lookup to retry
+        let cell = self.by_epoch.lock().unwrap().entry(epoch).or_default().clone();
+        let leaders = cell
+            .get_or_try_init(|| async {
+                let schedule = rpc_client.get_leader_schedule(slot).await?;
+                let first_slot = self.epoch_schedule.first_slot_in_epoch(epoch);
+                Ok::<_, crate::error::ETLError>(slot_leaders(&schedule, first_slot))
+            })
+            .await?;
+        Ok(leaders.get(&slot).cloned())
+    }
+
+    /// Set `leader` on every event of a block. A schedule fetch failure is
+    /// logged and leaves the leader unset rather than failing the slot.
+    pub async fn annotate(&self, rpc_client: &AlchemyRPCClient, slot: u64, events: &mut [CanonicalEvent]) {
+        match self.leader_for_slot(rpc_client, slot).await {
+            Ok(leader) => {
+                for event in events.iter_mut() {
+                    event.leader = leader.clone();
+                }
+            }
+            Err(e) => tracing::warn!("Failed to resolve leader for slot {}: {}", slot, e),
+        }
+    }
+}
+
+/// Invert a `getLeaderSchedule` response (leader -> relative slot indices)
+/// into absolute slot -> leader
+fn slot_leaders(schedule: &serde_json::Value, first_slot: u64) -> HashMap<u64, String> {
+    let mut leaders = HashMap::new();
+    if let Some(schedule) = schedule.as_object() {
+        for (leader, indices) in schedule {
+            for index in indices.as_array().into_iter().flatten().filter_map(|i| i.as_u64()) {
+                leaders.insert(first_slot + index, leader.clone());
+            }
+        }
+    }
+    leaders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockRpc, Reply};
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn a_slow_schedule_fetch_only_blocks_lookups_in_its_epoch() {
+        // Epoch 0 (slots 0..100) is slow to fetch, epoch 1 answers at once
+        let rpc = MockRpc::start(|_, params| {
+            let schedule = Reply::Result(json!({ "leaderA": [0, 1], "leaderB": [2] }));
+            match params[0].as_u64() {
+                Some(slot) if slot < 100 => Reply::Delayed(Duration::from_millis(1_500), Box::new(schedule)),
+                _ => schedule,
+            }
+        })
+        .await;
+        let rpc_client = Arc::new(AlchemyRPCClient::new(rpc.alchemy_config()));
+        let schedules = Arc::new(LeaderSchedules::new(EpochSchedule {
+            slots_per_epoch: 100,
+            first_normal_epoch: 0,
+            first_normal_slot: 0,
+        }));
+
+        let slow: Vec<_> = [1, 2]
+            .into_iter()
+            .map(|slot| {
+                let (schedules, rpc_client) = (schedules.clone(), rpc_client.clone());
+                tokio::spawn(async move { schedules.leader_for_slot(&rpc_client, slot).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = Instant::now();
+        assert_eq!(schedules.leader_for_slot(&rpc_client, 102).await.unwrap().as_deref(), Some("leaderB"));
+        assert_eq!(schedules.leader_for_slot(&rpc_client, 150).await.unwrap(), None);
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+
+        let leaders: Vec<_> = futures::future::join_all(slow).await.into_iter().map(|r| r.unwrap().unwrap()).collect();
+        assert_eq!(leaders, vec![Some("leaderA".to_string()), Some("leaderB".to_string())]);
+        // One fetch per epoch, however many lookups waited on it
+        assert_eq!(rpc.calls.lock().unwrap().len(), 2);
+    }
+}
@@ -1,13 +1,33 @@
 pub mod config;
 pub mod error;
+pub mod sql;
+pub mod util;
 pub mod rpc;
+pub mod block_source;
 pub mod parsers;
+pub mod program_parsers;
 pub mod events;
+pub mod avro;
+pub mod index_space;
+pub mod epoch;
+pub mod leaders;
 pub mod warehouse;
+pub mod migrations;
+pub mod s3;
+pub mod stdout;
+pub mod clock;
+pub mod stats;
 pub mod backfill;
 pub mod incremental;
 pub mod health;
+pub mod validate;
+pub mod reconcile;
 pub mod analytics;
+pub mod enrich;
+pub mod accounts;
+
+#[cfg(test)]
+mod test_support;
 
 pub use error::{ETLError, Result};
 
@@ -1,11 +1,18 @@
 use clap::{Parser, Subcommand};
 use solana_etl::config::Config;
 use solana_etl::error::ETLError;
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "solana-etl")]
 #[command(about = "Solana Telemetry & ETL Pipeline")]
 struct Cli {
+    /// Increase log verbosity (-v = debug, -vv = trace)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -23,45 +30,237 @@ enum Commands {
         /// Number of parallel workers
         #[arg(long, default_value = "4")]
         workers: usize,
+        /// Resume from the persisted job queue instead of starting over
+        #[arg(long)]
+        resume: bool,
+        /// Abort on the first failed chunk or parse failure instead of skipping it
+        #[arg(long)]
+        fail_fast: bool,
+        /// Cap on simultaneous block fetches across workers (overrides ETL_FETCH_CONCURRENCY)
+        #[arg(long)]
+        fetch_concurrency: Option<usize>,
+        /// Cap on simultaneous warehouse writes across workers (overrides ETL_WRITE_CONCURRENCY)
+        #[arg(long)]
+        write_concurrency: Option<usize>,
+        /// File of slots already processed elsewhere (`100-200,305`), never fetched
+        #[arg(long)]
+        processed_manifest: Option<String>,
+        /// Print the run summary as JSON to stdout
+        #[arg(long)]
+        summary_json: bool,
+        /// Stop at the finalized slot instead of ingesting newer slots that
+        /// may still be rolled back (overrides ETL_BACKFILL_FINALIZED_ONLY)
+        #[arg(long)]
+        finalized_only: bool,
+        /// Fetch at this commitment and advance its checkpoint (overrides ALCHEMY_COMMITMENT)
+        #[arg(long, value_parser = ["confirmed", "finalized"])]
+        commitment: Option<String>,
     },
     /// Run incremental loader
     Incremental {
         /// Interval in seconds between runs
         #[arg(long, default_value = "30")]
         interval: u64,
+        /// Stop after processing this slot (inclusive)
+        #[arg(long)]
+        until_slot: Option<u64>,
+        /// Ignore the stored checkpoint and start just behind the chain tip
+        /// (always the case on a first run with no checkpoint)
+        #[arg(long)]
+        start_from_tip: bool,
+        /// Fetch at this commitment and advance its checkpoint (overrides ALCHEMY_COMMITMENT)
+        #[arg(long, value_parser = ["confirmed", "finalized"])]
+        commitment: Option<String>,
     },
     /// Check pipeline health
     Health,
+    /// Validate configuration and connectivity
+    Validate,
+    /// Compare stored vs on-chain transaction counts for sampled slots
+    Reconcile {
+        /// Start slot (inclusive)
+        #[arg(long)]
+        start_slot: u64,
+        /// End slot (exclusive)
+        #[arg(long)]
+        end_slot: u64,
+        /// Fraction of slots to check
+        #[arg(long, default_value = "0.1")]
+        sample_rate: f64,
+        /// Skip the RPC comparison; re-derive the stored event ids instead
+        /// to detect drift in the id hash between versions
+        #[arg(long)]
+        check_only: bool,
+    },
     /// Generate analytics report
-    Analytics,
+    Analytics {
+        /// Print the computed report as JSON to stdout
+        #[arg(long, conflicts_with_all = ["views", "refresh_views"])]
+        json: bool,
+        /// Create the mv_* materialized views instead of computing the tables
+        #[arg(long)]
+        views: bool,
+        /// Refresh the mv_* materialized views concurrently
+        #[arg(long)]
+        refresh_views: bool,
+        /// Only run these computations (comma-separated: volume, programs,
+        /// tokens, failed, wallets, trends, rewards, leaders, blockhashes)
+        #[arg(long, value_delimiter = ',', conflicts_with_all = ["views", "refresh_views"])]
+        only: Vec<String>,
+        /// Estimate distinct counts from a TABLESAMPLE of fact_transactions
+        /// (ANALYTICS_SAMPLE_FRACTION) instead of scanning it all
+        #[arg(long, conflicts_with_all = ["views", "refresh_views"])]
+        approximate: bool,
+    },
+    /// Fetch supply and decimals for transferred token mints
+    EnrichTokens {
+        /// Re-fetch mints that were already enriched
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Snapshot vote accounts into the validators table
+    RefreshValidators,
+    /// Snapshot (and decode, where a layout is known) accounts owned by a program
+    Accounts {
+        /// Owning program id
+        program_id: String,
+        /// Only accounts with exactly this data length (e.g. 82 for SPL mints)
+        #[arg(long)]
+        data_size: Option<u64>,
+    },
+    /// Apply pending Postgres schema migrations
+    Migrate {
+        /// Also convert stored sha256_hex event ids to ETL_EVENT_ID_FORMAT
+        #[arg(long)]
+        event_ids: bool,
+    },
+    /// Show checkpoint lag and recent ingestion throughput
+    Status,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), ETLError> {
+    let cli = Cli::parse();
+
+    // Log level from -v/-q; RUST_LOG still takes precedence when set
+    let level = if cli.quiet {
+        "error"
+    } else {
+        match cli.verbose {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        }
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+
     // Initialize logging - use try_init to avoid panics
+    // Logs go to stderr so stdout stays clean for machine-readable output
     let _ = tracing_subscriber::fmt()
         .with_target(false)
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
         .try_init();
 
-    let cli = Cli::parse();
-    let config = Config::load()?;
+    let mut config = Config::load()?;
 
     match cli.command {
         Commands::Backfill {
             start_slot,
             end_slot,
             workers,
+            resume,
+            fail_fast,
+            fetch_concurrency,
+            write_concurrency,
+            processed_manifest,
+            summary_json,
+            finalized_only,
+            commitment,
         } => {
-            solana_etl::backfill::run_backfill(config, start_slot, end_slot, workers).await?;
+            if let Some(commitment) = commitment {
+                config.alchemy.commitment = commitment;
+            }
+            if finalized_only {
+                config.etl.backfill_finalized_only = true;
+            }
+            if let Some(fetch_concurrency) = fetch_concurrency {
+                config.etl.fetch_concurrency = fetch_concurrency;
+            }
+            if let Some(write_concurrency) = write_concurrency {
+                config.etl.write_concurrency = write_concurrency;
+            }
+            let result = solana_etl::backfill::run_backfill(
+                config,
+                start_slot,
+                end_slot,
+                workers,
+                resume,
+                fail_fast,
+                processed_manifest,
+            )
+            .await;
+            let summary = match &result {
+                Ok(summary) => summary,
+                Err(failed) => &failed.summary,
+            };
+            if summary_json {
+                println!("{}", serde_json::to_string_pretty(summary)?);
+            }
+            result.map_err(|failed| failed.error)?;
         }
-        Commands::Incremental { interval } => {
-            solana_etl::incremental::run_incremental(config, interval).await?;
+        Commands::Incremental {
+            interval,
+            until_slot,
+            start_from_tip,
+            commitment,
+        } => {
+            if let Some(commitment) = commitment {
+                config.alchemy.commitment = commitment;
+            }
+            solana_etl::incremental::run_incremental(config, interval, until_slot, start_from_tip).await?;
         }
         Commands::Health => {
             solana_etl::health::check_health(config).await?;
         }
-        Commands::Analytics => {
-            solana_etl::analytics::run_analytics(config).await?;
+        Commands::Validate => {
+            solana_etl::validate::run_validate(config).await?;
+        }
+        Commands::Reconcile {
+            start_slot,
+            end_slot,
+            sample_rate,
+            check_only,
+        } => {
+            if check_only {
+                solana_etl::reconcile::run_check_event_ids(config, start_slot, end_slot, sample_rate).await?;
+            } else {
+                solana_etl::reconcile::run_reconcile(config, start_slot, end_slot, sample_rate).await?;
+            }
+        }
+        Commands::Analytics {
+            json,
+            views,
+            refresh_views,
+            only,
+            approximate,
+        } => {
+            solana_etl::analytics::run_analytics(config, json, views, refresh_views, only, approximate).await?;
+        }
+        Commands::EnrichTokens { refresh } => {
+            solana_etl::enrich::run_enrich_tokens(config, refresh).await?;
+        }
+        Commands::RefreshValidators => {
+            solana_etl::enrich::run_refresh_validators(config).await?;
+        }
+        Commands::Accounts { program_id, data_size } => {
+            solana_etl::accounts::run_snapshot_accounts(config, program_id, data_size).await?;
+        }
+        Commands::Migrate { event_ids } => {
+            solana_etl::migrations::run_migrate(config, event_ids).await?;
+        }
+        Commands::Status => {
+            solana_etl::health::run_status(config).await?;
         }
     }
 
@@ -0,0 +1,1018 @@
+use crate::error::{ETLError, Result};
+use crate::events::{reencode_event_id, CanonicalEvent, EventIdFormat};
+use crate::index_space::IndexSpace;
+use crate::sql::migrate_timestamp_columns;
+use crate::warehouse::{connect_postgres, EVENT_ID_FORMAT_KEY};
+use futures::future::BoxFuture;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashSet;
+
+/// One forward schema migration. Steps must be idempotent so they are safe on
+/// databases created before migrations were tracked. Each runs inside the
+/// transaction that records its `schema_migrations` row.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub run: for<'c> fn(&'c mut PgConnection) -> BoxFuture<'c, Result<()>>,
+}
+
+/// All migrations, in the order they are applied. Append new ones; never
+/// edit or reorder applied versions.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "001_init",
+        run: |conn| Box::pin(init(conn)),
+    },
+    Migration {
+        version: 2,
+        name: "002_timestamptz",
+        run: |conn| Box::pin(timestamptz(conn)),
+    },
+    Migration {
+        version: 3,
+        name: "003_stack_height",
+        run: |conn| Box::pin(stack_height(conn)),
+    },
+    Migration {
+        version: 4,
+        name: "004_ingestion_stats",
+        run: |conn| Box::pin(ingestion_stats(conn)),
+    },
+    Migration {
+        version: 5,
+        name: "005_checkpoint_per_commitment",
+        run: |conn| Box::pin(checkpoint_per_commitment(conn)),
+    },
+    Migration {
+        version: 6,
+        name: "006_success",
+        run: |conn| Box::pin(success(conn)),
+    },
+    Migration {
+        version: 7,
+        name: "007_leader",
+        run: |conn| Box::pin(leader(conn)),
+    },
+    Migration {
+        version: 8,
+        name: "008_program_invocations",
+        run: |conn| Box::pin(program_invocations(conn)),
+    },
+    Migration {
+        version: 9,
+        name: "009_signatures",
+        run: |conn| Box::pin(signatures(conn)),
+    },
+    Migration {
+        version: 10,
+        name: "010_transactions_wide",
+        run: |conn| Box::pin(transactions_wide(conn)),
+    },
+    Migration {
+        version: 11,
+        name: "011_recent_blockhash",
+        run: |conn| Box::pin(recent_blockhash(conn)),
+    },
+    Migration {
+        version: 12,
+        name: "012_completed_slots",
+        run: |conn| Box::pin(completed_slots(conn)),
+    },
+    Migration {
+        version: 13,
+        name: "013_index_spaces",
+        run: |conn| Box::pin(index_spaces(conn)),
+    },
+    Migration {
+        version: 14,
+        name: "014_backfill_progress",
+        run: |conn| Box::pin(backfill_progress(conn)),
+    },
+    Migration {
+        version: 15,
+        name: "015_analytics_timestamptz",
+        run: |conn| Box::pin(analytics_timestamptz(conn)),
+    },
+    Migration {
+        version: 16,
+        name: "016_backfill_runs",
+        run: |conn| Box::pin(backfill_runs(conn)),
+    },
+];
+
+/// Apply every migration not yet recorded in `schema_migrations`
+pub async fn run_migrations(pool: &PgPool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create schema_migrations: {}", e)))?;
+
+    let applied: HashSet<i64> = sqlx::query_scalar("SELECT version FROM schema_migrations")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to read schema_migrations: {}", e)))?
+        .into_iter()
+        .collect();
+
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        tracing::info!("Applying migration {}", migration.name);
+        let mut tx = pool.begin().await
+            .map_err(|e| ETLError::Database(format!("Failed to begin transaction: {}", e)))?;
+        (migration.run)(&mut tx).await?;
+
+        sqlx::query("INSERT INTO schema_migrations (version, name) VALUES ($1, $2) ON CONFLICT (version) DO NOTHING")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to record migration {}: {}", migration.name, e)))?;
+        tx.commit().await
+            .map_err(|e| ETLError::Database(format!("Failed to commit migration {}: {}", migration.name, e)))?;
+    }
+
+    tracing::info!("Postgres schema up to date (version {})", MIGRATIONS.last().map_or(0, |m| m.version));
+    Ok(())
+}
+
+/// Connect and apply pending migrations (the `migrate` command). With
+/// `event_ids`, then convert stored hex event ids to the configured format.
+pub async fn run_migrate(config: crate::config::Config, event_ids: bool) -> Result<()> {
+    let pool = connect_postgres(&config.warehouse).await?;
+
+    run_migrations(&pool).await?;
+    if event_ids {
+        migrate_event_ids(&pool, config.etl.event_id_format.parse()?).await?;
+    }
+    Ok(())
+}
+
+/// Rows converted per transaction by `migrate_event_ids`
+const EVENT_ID_MIGRATION_BATCH: i64 = 10_000;
+
+/// Re-encode `sha256_hex` event ids in `fact_transactions` and
+/// `fact_token_transfers` as `format`, then record `format` as the stored
+/// one so ingestion with it may start. Converting is a pure function of the
+/// stored id; a hex row whose new id already exists is dropped.
+async fn migrate_event_ids(pool: &PgPool, format: EventIdFormat) -> Result<()> {
+    if format == EventIdFormat::Sha256Hex {
+        tracing::info!("ETL_EVENT_ID_FORMAT is sha256_hex; no event ids to convert");
+        return Ok(());
+    }
+
+    let mut converted = 0;
+    for table in ["fact_transactions", "fact_token_transfers"] {
+        loop {
+            let old_ids: Vec<String> = sqlx::query_scalar(&format!(
+                "SELECT event_id FROM {} WHERE length(event_id) = 64 AND event_id ~ '^[0-9a-f]+$' LIMIT $1",
+                table
+            ))
+            .bind(EVENT_ID_MIGRATION_BATCH)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to read {} event ids: {}", table, e)))?;
+            if old_ids.is_empty() {
+                break;
+            }
+            let new_ids = old_ids
+                .iter()
+                .map(|id| {
+                    reencode_event_id(id, format).ok_or_else(|| {
+                        ETLError::Database(format!("{} event id {} is not sha256_hex", table, id))
+                    })
+                })
+                .collect::<Result<Vec<String>>>()?;
+
+            let mut tx = pool.begin().await
+                .map_err(|e| ETLError::Database(format!("Failed to begin transaction: {}", e)))?;
+            sqlx::query(&format!(
+                r#"
+                DELETE FROM {table} f
+                USING UNNEST($1::text[], $2::text[]) AS m(old_id, new_id)
+                WHERE f.event_id = m.old_id
+                  AND EXISTS (SELECT 1 FROM {table} n WHERE n.event_id = m.new_id)
+                "#,
+                table = table
+            ))
+            .bind(&old_ids)
+            .bind(&new_ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to drop converted duplicates in {}: {}", table, e)))?;
+            sqlx::query(&format!(
+                r#"
+                UPDATE {} f SET event_id = m.new_id
+                FROM UNNEST($1::text[], $2::text[]) AS m(old_id, new_id)
+                WHERE f.event_id = m.old_id
+                "#,
+                table
+            ))
+            .bind(&old_ids)
+            .bind(&new_ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to convert {} event ids: {}", table, e)))?;
+            tx.commit().await
+                .map_err(|e| ETLError::Database(format!("Failed to commit event id conversion: {}", e)))?;
+
+            converted += old_ids.len();
+            tracing::info!("Converted {} event ids so far", converted);
+        }
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO etl_metadata (key, value, updated_at)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at
+        "#
+    )
+    .bind(EVENT_ID_FORMAT_KEY)
+    .bind(format.to_string())
+    .execute(pool)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to record the event id format: {}", e)))?;
+
+    tracing::info!("Event id conversion complete ({} rows)", converted);
+    Ok(())
+}
+
+/// Core ETL tables. Columns added before migrations were tracked are created
+/// by their `ADD COLUMN IF NOT EXISTS` steps only, so fresh and older
+/// databases end up with the same schema.
+async fn init(conn: &mut PgConnection) -> Result<()> {
+    // Create etl_metadata table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS etl_metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create etl_metadata: {}", e)))?;
+
+    // Create fact_transactions table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fact_transactions (
+            event_id TEXT PRIMARY KEY,
+            slot BIGINT NOT NULL,
+            block_time TIMESTAMPTZ NOT NULL,
+            tx_signature TEXT NOT NULL,
+            program_id TEXT,
+            instruction_index INTEGER NOT NULL,
+            event_type TEXT NOT NULL,
+            raw_payload JSONB,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create fact_transactions: {}", e)))?;
+
+    // Add columns introduced after the initial schema
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS accounts TEXT[]")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add accounts column: {}", e)))?;
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS raw_payload_compressed BYTEA")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add raw_payload_compressed column: {}", e)))?;
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS instruction_type TEXT")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add instruction_type column: {}", e)))?;
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS signers TEXT[]")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add signers column: {}", e)))?;
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS block_height BIGINT")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add block_height column: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_transactions_slot ON fact_transactions(slot)").await?;
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_transactions_block_height ON fact_transactions(block_height)").await?;
+
+    // Create fact_token_transfers table (typed view of token_transfer events)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fact_token_transfers (
+            event_id TEXT PRIMARY KEY,
+            slot BIGINT NOT NULL,
+            block_time TIMESTAMPTZ NOT NULL,
+            signature TEXT NOT NULL,
+            mint TEXT NOT NULL,
+            from_wallet TEXT,
+            to_wallet TEXT NOT NULL,
+            raw_amount NUMERIC,
+            decimals SMALLINT,
+            normalized_amount NUMERIC NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create fact_token_transfers: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_token_transfers_slot ON fact_token_transfers(slot)").await?;
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_token_transfers_mint_time ON fact_token_transfers(mint, block_time)").await?;
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_token_transfers_from ON fact_token_transfers(from_wallet)").await?;
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_token_transfers_to ON fact_token_transfers(to_wallet)").await?;
+
+    // Create backfill_jobs table (durable backfill work queue)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS backfill_jobs (
+            chunk_start BIGINT NOT NULL,
+            chunk_end BIGINT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'claimed', 'done', 'failed'
+            claimed_at TIMESTAMPTZ,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (chunk_start, chunk_end)
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create backfill_jobs: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_backfill_jobs_status ON backfill_jobs(status, chunk_start)").await?;
+
+    // Create parse_warnings table (only written with ETL_RECORD_PARSE_WARNINGS)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS parse_warnings (
+            id BIGSERIAL PRIMARY KEY,
+            slot BIGINT NOT NULL,
+            tx_signature TEXT,
+            instruction_index INTEGER,
+            stage TEXT NOT NULL,
+            message TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create parse_warnings: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_parse_warnings_slot ON parse_warnings(slot)").await?;
+
+    Ok(())
+}
+
+/// Run a `CREATE INDEX IF NOT EXISTS`. A failed statement aborts the
+/// migration's transaction, so it is reported rather than ignored.
+async fn create_index(conn: &mut PgConnection, statement: &str) -> Result<()> {
+    sqlx::query(statement)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to create index ({}): {}", statement, e)))?;
+    Ok(())
+}
+
+/// Older schemas stored naive UTC timestamps; convert them in place
+async fn timestamptz(conn: &mut PgConnection) -> Result<()> {
+    migrate_timestamp_columns(conn, &["fact_transactions", "fact_token_transfers", "etl_metadata"]).await
+}
+
+/// Invocation depth of program instructions (1 = top-level, 2+ = CPI)
+async fn stack_height(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS stack_height INTEGER")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add stack_height column: {}", e)))?;
+    Ok(())
+}
+
+/// Per-window ingestion throughput, written at each checkpoint
+async fn ingestion_stats(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS ingestion_stats (
+            id BIGSERIAL PRIMARY KEY,
+            source TEXT NOT NULL,
+            window_start TIMESTAMPTZ NOT NULL,
+            slots_processed BIGINT NOT NULL,
+            events_inserted BIGINT NOT NULL,
+            duration_seconds DOUBLE PRECISION NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create ingestion_stats: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_ingestion_stats_window ON ingestion_stats(window_start)").await?;
+
+    Ok(())
+}
+
+/// Checkpoints are keyed by commitment; the old single key was always
+/// tracked at `confirmed`
+async fn checkpoint_per_commitment(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE etl_metadata SET key = 'last_slot:confirmed'
+        WHERE key = 'last_confirmed_slot'
+          AND NOT EXISTS (SELECT 1 FROM etl_metadata WHERE key = 'last_slot:confirmed')
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to rename checkpoint key: {}", e)))?;
+    Ok(())
+}
+
+/// Whether the containing transaction succeeded, for every event of it
+async fn success(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS success BOOLEAN")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add success column: {}", e)))?;
+    Ok(())
+}
+
+/// Validator scheduled to produce each event's block
+async fn leader(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS leader TEXT")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add leader column: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_transactions_leader ON fact_transactions(leader) WHERE event_type = 'transaction'").await?;
+
+    Ok(())
+}
+
+async fn program_invocations(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS program_invocations (
+            slot BIGINT NOT NULL,
+            program_id TEXT NOT NULL,
+            invocation_count BIGINT NOT NULL,
+            PRIMARY KEY (slot, program_id)
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create program_invocations: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_program_invocations_program ON program_invocations(program_id, slot)").await?;
+
+    Ok(())
+}
+
+async fn signatures(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS signatures TEXT[]")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add signatures column: {}", e)))?;
+
+    Ok(())
+}
+
+async fn transactions_wide(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fact_transactions_wide (
+            slot BIGINT NOT NULL,
+            tx_signature TEXT NOT NULL,
+            block_time TIMESTAMPTZ NOT NULL,
+            transaction_index INTEGER NOT NULL,
+            success BOOLEAN,
+            fee_lamports BIGINT,
+            program_ids TEXT[] NOT NULL,
+            instruction_count BIGINT NOT NULL,
+            token_transfer_count BIGINT NOT NULL,
+            lamports_moved BIGINT NOT NULL,
+            PRIMARY KEY (slot, tx_signature)
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create fact_transactions_wide: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_transactions_wide_time ON fact_transactions_wide(block_time)").await?;
+
+    Ok(())
+}
+
+async fn recent_blockhash(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE fact_transactions ADD COLUMN IF NOT EXISTS recent_blockhash TEXT")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add recent_blockhash column: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_fact_transactions_recent_blockhash ON fact_transactions(recent_blockhash) WHERE event_type = 'transaction'").await?;
+
+    Ok(())
+}
+
+async fn completed_slots(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS completed_slots (
+            slot BIGINT PRIMARY KEY,
+            event_count BIGINT NOT NULL,
+            completed_at TIMESTAMPTZ NOT NULL
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create completed_slots: {}", e)))?;
+
+    // Rows alone can't tell a whole slot from one cut short by a crash, so
+    // only slots of backfill chunks that finished are seeded; the rest are
+    // fetched again (and rewritten in place) by the next backfill over them
+    sqlx::query(
+        r#"
+        INSERT INTO completed_slots (slot, event_count, completed_at)
+        SELECT f.slot, COUNT(*), NOW()
+        FROM fact_transactions f
+        WHERE EXISTS (
+            SELECT 1 FROM backfill_jobs j
+            WHERE j.status = 'done' AND f.slot >= j.chunk_start AND f.slot < j.chunk_end
+        )
+        GROUP BY f.slot
+        ON CONFLICT (slot) DO NOTHING
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to seed completed_slots: {}", e)))?;
+
+    Ok(())
+}
+
+/// Rows renumbered per batch by `index_spaces`
+const INDEX_SPACE_MIGRATION_BATCH: i64 = 10_000;
+
+/// Token transfers, balance changes and rewards used to be numbered from 0,
+/// overlapping top-level instructions. Move stored rows into their
+/// `IndexSpace` ranges and rewrite their event ids (in whichever format each
+/// id is stored), so re-ingesting those slots updates rows instead of
+/// duplicating them. Ids disambiguated as duplicates are left as they are.
+async fn index_spaces(conn: &mut PgConnection) -> Result<()> {
+    type Space = fn(u32) -> IndexSpace;
+    let spaces: [(&str, Space); 3] = [
+        ("token_transfer", IndexSpace::TokenBalance),
+        ("balance_change", IndexSpace::BalanceChange),
+        ("reward", IndexSpace::Reward),
+    ];
+
+    let mut renumbered = 0;
+    for (event_type, space) in spaces {
+        loop {
+            let rows: Vec<(String, i64, String, i32)> = sqlx::query_as(
+                r#"
+                SELECT event_id, slot, tx_signature, instruction_index FROM fact_transactions
+                WHERE event_type = $1 AND instruction_index >= 0 AND instruction_index < $2
+                LIMIT $3
+                "#
+            )
+            .bind(event_type)
+            .bind(space(0).index())
+            .bind(INDEX_SPACE_MIGRATION_BATCH)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to read {} rows: {}", event_type, e)))?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut old_ids = Vec::with_capacity(rows.len());
+            let mut new_ids = Vec::with_capacity(rows.len());
+            let mut new_indices = Vec::with_capacity(rows.len());
+            for (event_id, slot, tx_signature, instruction_index) in rows {
+                let format = if reencode_event_id(&event_id, EventIdFormat::Sha256Hex).is_some() {
+                    EventIdFormat::Sha256Hex
+                } else {
+                    EventIdFormat::Base58_128
+                };
+                let index = space(instruction_index as u32).index();
+                new_ids.push(CanonicalEvent::generate_event_id_as(format, slot as u64, &tx_signature, index, event_type));
+                new_indices.push(index);
+                old_ids.push(event_id);
+            }
+
+            for table in ["fact_transactions", "fact_token_transfers"] {
+                sqlx::query(&format!(
+                    r#"
+                    DELETE FROM {table} f
+                    USING UNNEST($1::text[], $2::text[]) AS m(old_id, new_id)
+                    WHERE f.event_id = m.old_id
+                      AND EXISTS (SELECT 1 FROM {table} n WHERE n.event_id = m.new_id)
+                    "#,
+                    table = table
+                ))
+                .bind(&old_ids)
+                .bind(&new_ids)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| ETLError::Database(format!("Failed to drop re-ingested rows in {}: {}", table, e)))?;
+            }
+            sqlx::query(
+                r#"
+                UPDATE fact_transactions f SET event_id = m.new_id, instruction_index = m.new_index
+                FROM UNNEST($1::text[], $2::text[], $3::int[]) AS m(old_id, new_id, new_index)
+                WHERE f.event_id = m.old_id
+                "#
+            )
+            .bind(&old_ids)
+            .bind(&new_ids)
+            .bind(&new_indices)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to renumber {} rows: {}", event_type, e)))?;
+            sqlx::query(
+                r#"
+                UPDATE fact_token_transfers f SET event_id = m.new_id
+                FROM UNNEST($1::text[], $2::text[]) AS m(old_id, new_id)
+                WHERE f.event_id = m.old_id
+                "#
+            )
+            .bind(&old_ids)
+            .bind(&new_ids)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to renumber token transfer rows: {}", e)))?;
+
+            renumbered += old_ids.len();
+        }
+    }
+
+    if renumbered > 0 {
+        tracing::info!("Moved {} stored events into their index spaces", renumbered);
+    }
+    Ok(())
+}
+
+/// Last slot a backfill chunk finished, so a resumed chunk continues after it
+async fn backfill_progress(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query("ALTER TABLE backfill_jobs ADD COLUMN IF NOT EXISTS progress_slot BIGINT")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to add progress_slot column: {}", e)))?;
+    Ok(())
+}
+
+/// Analytics tables created before they used TIMESTAMPTZ keep their rows;
+/// the columns are converted in place like the fact tables in 002
+async fn analytics_timestamptz(conn: &mut PgConnection) -> Result<()> {
+    let tables = [
+        "analytics_transaction_volume",
+        "analytics_hourly_volume",
+        "analytics_active_programs",
+        "analytics_token_transfers",
+        "analytics_top_tokens",
+        "analytics_failed_transactions",
+        "analytics_top_errors",
+        "analytics_wallet_activity",
+        "analytics_top_wallets",
+        "analytics_program_trends",
+        "analytics_rewards",
+        "analytics_leaders",
+        "analytics_blockhash_reuse",
+    ];
+    migrate_timestamp_columns(conn, &tables).await
+}
+
+/// One row per backfill run: its `BackfillSummary`, including runs that
+/// stopped on an error
+async fn backfill_runs(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS backfill_runs (
+            run_id TEXT PRIMARY KEY,
+            start_slot BIGINT NOT NULL,
+            end_slot BIGINT NOT NULL,
+            slots_attempted BIGINT NOT NULL,
+            slots_with_blocks BIGINT NOT NULL,
+            slots_skipped BIGINT NOT NULL,
+            slots_already_processed BIGINT NOT NULL,
+            chunks_completed BIGINT NOT NULL,
+            chunks_failed BIGINT NOT NULL,
+            events_inserted BIGINT NOT NULL,
+            events_by_type JSONB NOT NULL,
+            elapsed_seconds DOUBLE PRECISION NOT NULL,
+            error TEXT,
+            finished_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to create backfill_runs: {}", e)))?;
+
+    create_index(conn, "CREATE INDEX IF NOT EXISTS idx_backfill_runs_finished ON backfill_runs(finished_at)").await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fresh_database, postgres_config};
+    use crate::warehouse::{PostgresWarehouse, Warehouse};
+
+    /// Every column and index in the public schema, for comparing databases
+    async fn schema(pool: &PgPool) -> (Vec<(String, String, String)>, Vec<String>) {
+        let columns = sqlx::query_as(
+            r#"
+            SELECT table_name::text, column_name::text, data_type::text FROM information_schema.columns
+            WHERE table_schema = 'public' ORDER BY table_name, column_name
+            "#,
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap();
+        let indexes = sqlx::query_scalar("SELECT indexname::text FROM pg_indexes WHERE schemaname = 'public' ORDER BY indexname")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+        (columns, indexes)
+    }
+
+    async fn applied(pool: &PgPool) -> Vec<(i64, String)> {
+        sqlx::query_as("SELECT version, applied_at::text FROM schema_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn fresh_and_baseline_databases_migrate_to_the_same_schema_once() {
+        let fresh = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        run_migrations(&fresh).await.unwrap();
+
+        // The schema the ETL created for itself before migrations were tracked
+        let baseline = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        for statement in [
+            r#"
+            CREATE TABLE etl_metadata (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+            "#,
+            r#"
+            CREATE TABLE fact_transactions (
+                event_id TEXT PRIMARY KEY,
+                slot BIGINT NOT NULL,
+                block_time TIMESTAMPTZ NOT NULL,
+                tx_signature TEXT NOT NULL,
+                program_id TEXT,
+                instruction_index INTEGER NOT NULL,
+                event_type TEXT NOT NULL,
+                raw_payload JSONB,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+            "#,
+            "CREATE INDEX idx_fact_transactions_slot ON fact_transactions(slot)",
+            "INSERT INTO etl_metadata (key, value) VALUES ('last_processed_slot', '100')",
+            r#"
+            INSERT INTO fact_transactions (event_id, slot, block_time, tx_signature, instruction_index, event_type)
+            VALUES ('event1', 100, NOW(), 'sig1', -1, 'transaction')
+            "#,
+        ] {
+            sqlx::query(statement).execute(&baseline).await.unwrap();
+        }
+        run_migrations(&baseline).await.unwrap();
+
+        assert_eq!(schema(&baseline).await, schema(&fresh).await);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM fact_transactions")
+            .fetch_one(&baseline)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        // A second run applies nothing and changes nothing
+        for pool in [&fresh, &baseline] {
+            let before = (schema(pool).await, applied(pool).await);
+            assert_eq!(before.1.len(), MIGRATIONS.len());
+            run_migrations(pool).await.unwrap();
+            assert_eq!((schema(pool).await, applied(pool).await), before);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_slots_of_finished_chunks_are_seeded_as_completed() {
+        let config = postgres_config(&fresh_database().await);
+        let pool = connect_postgres(&config).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+        // Rows written before completed_slots existed: a finished chunk, a
+        // failed one and slots outside any chunk, any of them maybe partial
+        for slot in [100_i64, 105, 200, 300] {
+            sqlx::query(
+                r#"
+                INSERT INTO fact_transactions (event_id, slot, block_time, tx_signature, instruction_index, event_type)
+                VALUES ($1, $2, NOW(), 'sig1', -1, 'transaction')
+                "#
+            )
+            .bind(format!("event{}", slot))
+            .bind(slot)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        for (start, end, status) in [(100_i64, 110_i64, "done"), (200, 210, "failed")] {
+            sqlx::query("INSERT INTO backfill_jobs (chunk_start, chunk_end, status) VALUES ($1, $2, $3)")
+                .bind(start)
+                .bind(end)
+                .bind(status)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        completed_slots(&mut pool.acquire().await.unwrap()).await.unwrap();
+
+        let warehouse = PostgresWarehouse::new(config).unwrap();
+        for (slot, processed) in [(100, true), (105, true), (200, false), (300, false)] {
+            assert_eq!(warehouse.is_slot_processed(slot).await.unwrap(), processed, "slot {}", slot);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn legacy_meta_events_move_into_their_index_spaces() {
+        let pool = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let legacy = [
+            ("token_transfer", 0, EventIdFormat::Sha256Hex),
+            ("balance_change", 1, EventIdFormat::Base58_128),
+            ("program_instruction", 0, EventIdFormat::Sha256Hex),
+        ];
+        for (event_type, index, format) in legacy {
+            let event_id = CanonicalEvent::generate_event_id_as(format, 100, "sig1", index, event_type);
+            sqlx::query(
+                r#"
+                INSERT INTO fact_transactions (event_id, slot, block_time, tx_signature, instruction_index, event_type)
+                VALUES ($1, 100, NOW(), 'sig1', $2, $3)
+                "#
+            )
+            .bind(&event_id)
+            .bind(index)
+            .bind(event_type)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        index_spaces(&mut pool.acquire().await.unwrap()).await.unwrap();
+
+        let mut rows: Vec<(String, i32, String)> = sqlx::query_as(
+            "SELECT event_type, instruction_index, event_id FROM fact_transactions ORDER BY event_type",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        rows.sort();
+        let expected = |format, index, event_type| CanonicalEvent::generate_event_id_as(format, 100, "sig1", index, event_type);
+        let transfer = IndexSpace::TokenBalance(0).index();
+        let change = IndexSpace::BalanceChange(1).index();
+        assert_eq!(
+            rows,
+            vec![
+                ("balance_change".to_string(), change, expected(EventIdFormat::Base58_128, change, "balance_change")),
+                ("program_instruction".to_string(), 0, expected(EventIdFormat::Sha256Hex, 0, "program_instruction")),
+                ("token_transfer".to_string(), transfer, expected(EventIdFormat::Sha256Hex, transfer, "token_transfer")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn ingestion_refuses_a_format_the_stored_ids_are_not_in_until_they_are_converted() {
+        use crate::parsers::parse_block;
+        use crate::test_support::{block, transaction};
+        use crate::warehouse::{PostgresWarehouse, Warehouse};
+        use serde_json::json;
+
+        let config = postgres_config(&fresh_database().await);
+        let warehouse = PostgresWarehouse::new(config.clone()).unwrap();
+        warehouse.connect().await.unwrap();
+        let events = parse_block(&block(vec![transaction("sig1", &["payer", "Prog111"], json!([]))]), 100).unwrap();
+        warehouse.insert_events(events).await.unwrap();
+
+        // Ids written before the format was recorded are inferred as hex
+        warehouse.check_event_id_format(EventIdFormat::Sha256Hex).await.unwrap();
+        let err = warehouse.check_event_id_format(EventIdFormat::Base58_128).await.unwrap_err().to_string();
+        assert!(err.contains("Stored event ids are sha256_hex but ETL_EVENT_ID_FORMAT is base58_128"), "{}", err);
+
+        let pool = connect_postgres(&config).await.unwrap();
+        migrate_event_ids(&pool, EventIdFormat::Base58_128).await.unwrap();
+        warehouse.check_event_id_format(EventIdFormat::Base58_128).await.unwrap();
+        assert!(warehouse.check_event_id_format(EventIdFormat::Sha256Hex).await.is_err());
+        let hex_ids: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM fact_transactions WHERE length(event_id) = 64")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(hex_ids, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn an_empty_database_adopts_the_configured_format() {
+        use crate::warehouse::{PostgresWarehouse, Warehouse};
+
+        let warehouse = PostgresWarehouse::new(postgres_config(&fresh_database().await)).unwrap();
+        warehouse.check_event_id_format(EventIdFormat::Base58_128).await.unwrap();
+        assert!(warehouse.check_event_id_format(EventIdFormat::Sha256Hex).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn old_analytics_tables_are_converted_to_timestamptz_in_place() {
+        let pool = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        sqlx::query(
+            r#"
+            CREATE TABLE analytics_transaction_volume (
+                id SERIAL PRIMARY KEY,
+                period_type TEXT NOT NULL,
+                transaction_count BIGINT NOT NULL,
+                updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                UNIQUE(period_type)
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO analytics_transaction_volume (period_type, transaction_count, updated_at) VALUES ('total', 7, '2024-01-01 12:00:00')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        run_migrations(&pool).await.unwrap();
+
+        let data_type: String = sqlx::query_scalar(
+            "SELECT data_type::text FROM information_schema.columns WHERE table_name = 'analytics_transaction_volume' AND column_name = 'updated_at'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(data_type, "timestamp with time zone");
+        let (count, utc): (i64, String) = sqlx::query_as(
+            "SELECT transaction_count, to_char(updated_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI') FROM analytics_transaction_volume",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((count, utc.as_str()), (7, "2024-01-01 12:00"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failed_migration_leaves_neither_its_tables_nor_its_version_row() {
+        let pool = connect_postgres(&postgres_config(&fresh_database().await)).await.unwrap();
+        // 001_init skips creating backfill_jobs, then fails to index the view
+        sqlx::query("CREATE VIEW backfill_jobs AS SELECT 1 AS chunk_start")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(run_migrations(&pool).await.is_err());
+
+        let versions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(versions, 0);
+        let created: Option<String> = sqlx::query_scalar("SELECT to_regclass('fact_transactions')::text")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(created, None);
+    }
+}
@@ -1,51 +1,503 @@
-use crate::events::CanonicalEvent;
+use crate::events::{CanonicalEvent, EventIdFormat};
+use crate::index_space::{IndexSpace, MAX_INSTRUCTIONS};
+use crate::program_parsers::{default_registry, ParseContext, TOKEN_PROGRAM_ID};
 use crate::error::{ETLError, Result};
+use crate::config::ETLConfig;
+use crate::util::glob_match;
+use crate::warehouse::replace_lone_surrogates;
 use chrono::{DateTime, Utc};
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 
-const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
-const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+/// Bytes of instruction `data` kept when a payload is truncated
+const TRUNCATED_DATA_PREFIX_BYTES: usize = 64;
+
+
+/// A parse failure that was skipped rather than aborting the block
+#[derive(Debug, Clone)]
+pub struct ParseWarning {
+    pub slot: u64,
+    pub tx_signature: Option<String>,
+    pub instruction_index: Option<i32>,
+    pub stage: &'static str, // "block", "transaction", "instruction" or "dedup"
+    pub message: String,
+}
+
+impl ParseWarning {
+    /// Build a warning and log it
+    pub fn new(
+        slot: u64,
+        tx_signature: Option<String>,
+        instruction_index: Option<i32>,
+        stage: &'static str,
+        message: String,
+    ) -> Self {
+        tracing::warn!(
+            "Parse warning at slot {} ({}, tx {:?}, instruction {:?}): {}",
+            slot,
+            stage,
+            tx_signature,
+            instruction_index,
+            message
+        );
+        Self {
+            slot,
+            tx_signature,
+            instruction_index,
+            stage,
+            message,
+        }
+    }
+}
+
+/// Number of instructions a program executed in one slot, stored in
+/// `program_invocations` when instruction events are aggregated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramInvocation {
+    pub slot: u64,
+    pub program_id: String,
+    pub count: u64,
+}
+
+/// One transaction denormalized into a single wide row, stored in
+/// `fact_transactions_wide` when `ETL_WIDE_TRANSACTIONS` is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct WideTransaction {
+    pub slot: u64,
+    pub block_time: DateTime<Utc>,
+    pub tx_signature: String,
+    pub transaction_index: u32,
+    pub success: Option<bool>,
+    pub fee_lamports: Option<u64>,
+    /// Distinct programs of the top-level instructions, in instruction order
+    pub program_ids: Vec<String>,
+    /// Top-level instructions; inner (CPI) ones are not counted
+    pub instruction_count: u64,
+    pub token_transfer_count: u64,
+    /// Sum of the positive SOL balance changes (lamports credited to accounts)
+    pub lamports_moved: u64,
+}
+
+/// Events parsed from one block, plus what was skipped along the way
+#[derive(Debug, Clone)]
+pub struct ParsedBlock {
+    pub events: Vec<CanonicalEvent>,
+    pub warnings: Vec<ParseWarning>,
+    pub transaction_count: usize,
+    /// Transactions that failed to parse and contributed no events
+    pub failed_transactions: usize,
+    /// Transactions skipped for exceeding `max_transaction_bytes`
+    pub oversized_transactions: usize,
+}
+
+impl ParsedBlock {
+    /// Fraction of the block's transactions that failed to parse
+    pub fn failure_ratio(&self) -> f64 {
+        if self.transaction_count == 0 {
+            0.0
+        } else {
+            self.failed_transactions as f64 / self.transaction_count as f64
+        }
+    }
+
+    /// Log the block's transaction failure count, as an error once the
+    /// failed fraction exceeds `alert_ratio`
+    pub fn log_failures(&self, slot: u64, alert_ratio: f64) {
+        if self.oversized_transactions > 0 {
+            tracing::warn!(
+                "Slot {}: skipped {} oversized transactions",
+                slot, self.oversized_transactions
+            );
+        }
+        if self.failed_transactions == 0 {
+            return;
+        }
+        if self.failure_ratio() > alert_ratio {
+            tracing::error!(
+                "Slot {}: {} of {} transactions failed to parse ({:.1}%)",
+                slot,
+                self.failed_transactions,
+                self.transaction_count,
+                self.failure_ratio() * 100.0
+            );
+        } else {
+            tracing::info!(
+                "Slot {}: {} of {} transactions failed to parse",
+                slot, self.failed_transactions, self.transaction_count
+            );
+        }
+    }
+}
+
+/// What a block parse leaves out, and how its event ids are encoded
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Skip transactions whose JSON is larger than this; 0 = no limit
+    pub max_transaction_bytes: usize,
+    /// Only emit these event types (`*` globs); None = all
+    pub event_types: Option<Vec<String>>,
+    /// With `event_types`, still emit the base `transaction` events
+    pub keep_transaction_events: bool,
+    /// Encoding of the emitted event ids (`ETL_EVENT_ID_FORMAT`)
+    pub event_id_format: EventIdFormat,
+}
+
+impl ParseOptions {
+    /// Options for ingestion. `ETL_EVENT_TYPES` only applies at parse time
+    /// when nothing downstream needs the full parse: wide rows and
+    /// instruction aggregates are built from every event, and
+    /// `retain_event_types` filters after them instead.
+    pub fn for_ingestion(etl: &ETLConfig) -> Result<Self> {
+        let needs_every_event = etl.wide_transactions || etl.aggregate_instructions;
+        Ok(Self {
+            max_transaction_bytes: etl.max_transaction_bytes,
+            event_types: if needs_every_event { None } else { etl.event_types.clone() },
+            keep_transaction_events: etl.keep_transaction_events,
+            event_id_format: etl.event_id_format.parse()?,
+        })
+    }
+
+    /// Whether events of `event_type` are emitted
+    pub fn keeps(&self, event_type: &str) -> bool {
+        event_type_kept(self.event_types.as_deref(), self.keep_transaction_events, event_type)
+    }
+}
+
+/// Parses blocks on tokio's blocking threads, at most `threads` at a time,
+/// so CPU-heavy dense blocks don't stall the async workers doing RPC and
+/// database IO. With 0 threads, blocks are parsed inline.
+pub struct ParsePool {
+    permits: Option<Semaphore>,
+    options: Arc<ParseOptions>,
+}
+
+impl ParsePool {
+    pub fn new(threads: usize, options: ParseOptions) -> Self {
+        Self {
+            permits: (threads > 0).then(|| Semaphore::new(threads)),
+            options: Arc::new(options),
+        }
+    }
+
+    /// `parse_raw_block` on the pool
+    pub async fn parse_raw(&self, block: RawBlock, slot: u64) -> Result<ParsedBlock> {
+        let Some(permits) = &self.permits else {
+            return parse_raw_block(&block, slot, &self.options);
+        };
+        let _permit = permits
+            .acquire()
+            .await
+            .map_err(|e| ETLError::Generic(anyhow::anyhow!("Parse pool closed: {}", e)))?;
+        let options = self.options.clone();
+        tokio::task::spawn_blocking(move || parse_raw_block(&block, slot, &options))
+            .await
+            .map_err(|e| ETLError::Parse(format!("Parse task for slot {} failed: {}", slot, e)))?
+    }
+}
+
+/// `getBlock` result for the ingestion fast path. The block-level fields
+/// are typed and each transaction is kept as its raw JSON text, parsed into
+/// a `Value` one at a time by `parse_raw_block`. Decoding a whole block into
+/// a `Value` tree takes several times its JSON size (every object is a map
+/// of heap strings) on top of the response buffer; this way a dense block
+/// costs roughly its JSON size plus one transaction's tree. Measured on a
+/// 3.3 MB block of 3,000 transactions: 29 MB as a `Value` (8.9x), 3.3 MB as
+/// a `RawBlock` plus about 10 KB for the transaction being parsed.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawBlock {
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+    pub transactions: Option<Vec<Box<RawValue>>>,
+    /// Only present when requested
+    pub rewards: Option<Vec<Value>>,
+}
+
+/// Just the signatures of a raw transaction, for warnings about
+/// transactions that are never parsed in full
+#[derive(Deserialize)]
+struct RawTransactionSignatures {
+    transaction: RawSignatureList,
+}
+
+#[derive(Deserialize)]
+struct RawSignatureList {
+    signatures: Vec<String>,
+}
+
+fn raw_signature(tx: &RawValue) -> Option<String> {
+    serde_json::from_str::<RawTransactionSignatures>(tx.get())
+        .ok()
+        .and_then(|tx| tx.transaction.signatures.into_iter().next())
+}
 
 /// Parse a Solana block into canonical events
 pub fn parse_block(block: &Value, slot: u64) -> Result<Vec<CanonicalEvent>> {
+    parse_block_with_warnings(block, slot).map(|parsed| parsed.events)
+}
+
+/// Parse a Solana block, also reporting the transactions and instructions
+/// that failed to parse and were skipped
+pub fn parse_block_with_warnings(block: &Value, slot: u64) -> Result<ParsedBlock> {
+    parse_block_limited(block, slot, &ParseOptions::default())
+}
+
+/// `parse_block_with_warnings`, skipping (with an `oversized` warning)
+/// transactions whose serialized JSON exceeds `options.max_transaction_bytes`
+/// and the event types `options` leaves out
+pub fn parse_block_limited(block: &Value, slot: u64, options: &ParseOptions) -> Result<ParsedBlock> {
     let block_time = extract_block_time(block)?;
+    let block_height = extract_block_height(block);
     let transactions = block
         .get("transactions")
         .and_then(|v| v.as_array())
         .ok_or_else(|| ETLError::Parse("Missing transactions array".to_string()))?;
 
-    let mut events = Vec::new();
-
+    let mut parse = BlockParse::new(slot, block_time, options);
     for (tx_idx, tx) in transactions.iter().enumerate() {
-        match parse_transaction(tx, slot, block_time, tx_idx) {
-            Ok(mut tx_events) => events.append(&mut tx_events),
-            Err(e) => {
-                tracing::warn!("Failed to parse transaction {}: {}", tx_idx, e);
-                // Continue processing other transactions
+        if options.max_transaction_bytes > 0 {
+            let size = json_size(tx);
+            if parse.skip_oversized(tx_idx, size, || tx.get("transaction").and_then(|t| extract_signature(t).ok())) {
+                continue;
             }
         }
+        parse.transaction(tx_idx, tx);
     }
 
-    Ok(events)
+    // Rewards are only present when requested
+    let rewards = block.get("rewards").and_then(|v| v.as_array()).map(Vec::as_slice).unwrap_or_default();
+    Ok(parse.finish(rewards, block_height, transactions.len()))
+}
+
+/// Length of `value` serialized as JSON, counted without building the string
+fn json_size(value: &Value) -> usize {
+    struct Counter(usize);
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    match serde_json::to_writer(&mut counter, value) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
+
+/// `parse_block_limited` for a `RawBlock`, decoding one transaction at a time
+pub fn parse_raw_block(block: &RawBlock, slot: u64, options: &ParseOptions) -> Result<ParsedBlock> {
+    let block_time = block_time_from(block.block_time)?;
+    let transactions = block
+        .transactions
+        .as_ref()
+        .ok_or_else(|| ETLError::Parse("Missing transactions array".to_string()))?;
+
+    let mut parse = BlockParse::new(slot, block_time, options);
+    for (tx_idx, raw) in transactions.iter().enumerate() {
+        if parse.skip_oversized(tx_idx, raw.get().len(), || raw_signature(raw)) {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&replace_lone_surrogates(raw.get())) {
+            Ok(tx) => parse.transaction(tx_idx, &tx),
+            Err(e) => parse.failed(tx_idx, raw_signature(raw), e),
+        }
+    }
+
+    Ok(parse.finish(block.rewards.as_deref().unwrap_or_default(), block.block_height, transactions.len()))
+}
+
+/// Events and warnings accumulated while parsing one block's transactions
+struct BlockParse<'a> {
+    slot: u64,
+    block_time: DateTime<Utc>,
+    options: &'a ParseOptions,
+    events: Vec<CanonicalEvent>,
+    warnings: Vec<ParseWarning>,
+    failed_transactions: usize,
+    oversized_transactions: usize,
+}
+
+impl<'a> BlockParse<'a> {
+    fn new(slot: u64, block_time: DateTime<Utc>, options: &'a ParseOptions) -> Self {
+        Self {
+            slot,
+            block_time,
+            options,
+            events: Vec::new(),
+            warnings: Vec::new(),
+            failed_transactions: 0,
+            oversized_transactions: 0,
+        }
+    }
+
+    /// Skip (with an `oversized` warning) a transaction of `size` bytes over
+    /// the limit; false when it's within it
+    fn skip_oversized(&mut self, tx_idx: usize, size: usize, signature: impl FnOnce() -> Option<String>) -> bool {
+        let max_transaction_bytes = self.options.max_transaction_bytes;
+        if max_transaction_bytes == 0 || size <= max_transaction_bytes {
+            return false;
+        }
+        self.oversized_transactions += 1;
+        self.warnings.push(ParseWarning::new(
+            self.slot,
+            signature(),
+            None,
+            "oversized",
+            format!(
+                "Skipped transaction {}: {} bytes exceeds the {} byte limit",
+                tx_idx, size, max_transaction_bytes
+            ),
+        ));
+        true
+    }
+
+    fn transaction(&mut self, tx_idx: usize, tx: &Value) {
+        match parse_transaction(tx, self.slot, self.block_time, tx_idx, self.options, &mut self.warnings) {
+            Ok(mut tx_events) => self.events.append(&mut tx_events),
+            // Continue processing other transactions
+            Err(e) => self.failed(tx_idx, tx.get("transaction").and_then(|t| extract_signature(t).ok()), e),
+        }
+    }
+
+    fn failed(&mut self, tx_idx: usize, signature: Option<String>, error: impl std::fmt::Display) {
+        self.failed_transactions += 1;
+        self.warnings.push(ParseWarning::new(
+            self.slot,
+            signature,
+            None,
+            "transaction",
+            format!("Failed to parse transaction {}: {}", tx_idx, error),
+        ));
+    }
+
+    fn finish(mut self, rewards: &[Value], block_height: Option<u64>, transaction_count: usize) -> ParsedBlock {
+        if self.options.keeps("reward") {
+            self.events.extend(parse_rewards(rewards, self.slot, self.block_time));
+        }
+
+        let format = self.options.event_id_format;
+        for event in self.events.iter_mut() {
+            event.encode_event_id(format);
+        }
+        disambiguate_duplicate_ids(&mut self.events, format, &mut self.warnings);
+
+        for event in self.events.iter_mut() {
+            event.block_height = block_height;
+        }
+
+        ParsedBlock {
+            events: self.events,
+            warnings: self.warnings,
+            transaction_count,
+            failed_transactions: self.failed_transactions,
+            oversized_transactions: self.oversized_transactions,
+        }
+    }
+}
+
+/// Give repeated event_ids within a block (e.g. a signature appearing twice)
+/// a distinct, deterministic id so neither row silently overwrites the other
+fn disambiguate_duplicate_ids(events: &mut [CanonicalEvent], format: EventIdFormat, warnings: &mut Vec<ParseWarning>) {
+    let mut seen: HashMap<String, u32> = HashMap::new();
+
+    for event in events.iter_mut() {
+        let occurrence = seen.entry(event.event_id.clone()).or_insert(0);
+        *occurrence += 1;
+        if *occurrence == 1 {
+            continue;
+        }
+
+        let original_id = event.event_id.clone();
+        event.event_id = CanonicalEvent::disambiguated_event_id(
+            format,
+            event.slot,
+            &event.tx_signature,
+            event.instruction_index,
+            &event.event_type,
+            *occurrence,
+        );
+        warnings.push(ParseWarning::new(
+            event.slot,
+            Some(event.tx_signature.clone()),
+            Some(event.instruction_index),
+            "dedup",
+            format!(
+                "Duplicate {} event_id {} (occurrence {}), stored as {}",
+                event.event_type, original_id, occurrence, event.event_id
+            ),
+        ));
+    }
+}
+
+/// Parse block-level `rewards[]` into reward events.
+///
+/// Rewards aren't tied to a transaction, so `tx_signature` is empty and
+/// `instruction_index` comes from the reward's position in `IndexSpace::Reward`.
+fn parse_rewards(rewards: &[Value], slot: u64, block_time: DateTime<Utc>) -> Vec<CanonicalEvent> {
+    rewards
+        .iter()
+        .enumerate()
+        .map(|(idx, reward)| {
+            let payload = json!({
+                "pubkey": reward.get("pubkey"),
+                "lamports": reward.get("lamports"),
+                "post_balance": reward.get("postBalance"),
+                "reward_type": reward.get("rewardType"),
+                "commission": reward.get("commission"),
+            });
+            let mut event = CanonicalEvent::new(
+                slot,
+                block_time,
+                String::new(),
+                None,
+                IndexSpace::Reward(idx as u32).index(),
+                "reward".to_string(),
+                payload,
+            );
+            // Sort after every transaction in the block
+            event.transaction_index = u32::MAX;
+            event
+        })
+        .collect()
 }
 
 /// Extract block timestamp
 fn extract_block_time(block: &Value) -> Result<DateTime<Utc>> {
-    let timestamp = block
-        .get("blockTime")
-        .and_then(|v| v.as_i64())
-        .ok_or_else(|| ETLError::Parse("Missing blockTime".to_string()))?;
+    block_time_from(block.get("blockTime").and_then(|v| v.as_i64()))
+}
+
+fn block_time_from(timestamp: Option<i64>) -> Result<DateTime<Utc>> {
+    let timestamp = timestamp.ok_or_else(|| ETLError::Parse("Missing blockTime".to_string()))?;
 
     DateTime::from_timestamp(timestamp, 0)
         .ok_or_else(|| ETLError::Parse(format!("Invalid timestamp: {}", timestamp)))
 }
 
-/// Parse a single transaction into events
+/// Extract block height; older blocks don't carry `blockHeight`
+pub fn extract_block_height(block: &Value) -> Option<u64> {
+    block.get("blockHeight").and_then(|v| v.as_u64())
+}
+
+/// Parse a single transaction into the events `options` keeps
 fn parse_transaction(
     tx: &Value,
     slot: u64,
     block_time: DateTime<Utc>,
-    _tx_idx: usize,
+    tx_idx: usize,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarning>,
 ) -> Result<Vec<CanonicalEvent>> {
     let meta = tx
         .get("meta")
@@ -56,49 +508,113 @@ fn parse_transaction(
         .ok_or_else(|| ETLError::Parse("Missing transaction data".to_string()))?;
 
     let signature = extract_signature(tx_data)?;
-    let _success = meta
-        .get("err")
-        .map(|v| v.is_null())
-        .unwrap_or(false);
+    let success = transaction_succeeded(meta);
 
     let instructions = extract_instructions(tx_data)?;
+    let account_keys = resolve_account_keys(tx_data, meta);
     let mut events = Vec::new();
 
     // Create base transaction event
-    let base_event = CanonicalEvent::new(
-        slot,
-        block_time,
-        signature.clone(),
-        None,
-        -1, // Transaction-level event
-        "transaction".to_string(),
-        tx.clone(),
-    );
-    events.push(base_event);
+    if options.keeps("transaction") {
+        let base_event = CanonicalEvent::new(
+            slot,
+            block_time,
+            signature.clone(),
+            None,
+            IndexSpace::Transaction.index(),
+            "transaction".to_string(),
+            tx.clone(),
+        );
+        events.push(base_event);
+    }
+
+    // Instructions past MAX_INSTRUCTIONS would index into the inner range
+    if instructions.len() > MAX_INSTRUCTIONS as usize {
+        warnings.push(ParseWarning::new(
+            slot,
+            Some(signature.clone()),
+            None,
+            "instruction",
+            format!(
+                "Transaction has {} instructions; skipping those past {}",
+                instructions.len(),
+                MAX_INSTRUCTIONS
+            ),
+        ));
+    }
 
     // Parse each instruction
-    for (inst_idx, instruction) in instructions.iter().enumerate() {
-        match parse_instruction(instruction, slot, block_time, &signature, inst_idx as i32) {
-            Ok(inst_events) => events.extend(inst_events),
+    for (inst_idx, instruction) in instructions.iter().take(MAX_INSTRUCTIONS as usize).enumerate() {
+        match parse_instruction(instruction, &account_keys, slot, block_time, &signature, IndexSpace::TopLevel(inst_idx as u32).index()) {
+            Ok(inst_events) => events.extend(inst_events.into_iter().filter(|e| options.keeps(&e.event_type))),
+            Err(e) => {
+                warnings.push(ParseWarning::new(
+                    slot,
+                    Some(signature.clone()),
+                    Some(IndexSpace::TopLevel(inst_idx as u32).index()),
+                    "instruction",
+                    format!("Failed to parse instruction {}: {}", inst_idx, e),
+                ));
+            }
+        }
+    }
+
+    // Parse inner (CPI) instructions in their parent's index range
+    for (parent, inner_idx, instruction) in extract_inner_instructions(meta) {
+        if parent >= MAX_INSTRUCTIONS || inner_idx >= MAX_INSTRUCTIONS {
+            warnings.push(ParseWarning::new(
+                slot,
+                Some(signature.clone()),
+                None,
+                "instruction",
+                format!("Skipping inner instruction {} of instruction {}: past {}", inner_idx, parent, MAX_INSTRUCTIONS),
+            ));
+            continue;
+        }
+        let index = IndexSpace::Inner { parent, inner: inner_idx }.index();
+        match parse_instruction(&instruction, &account_keys, slot, block_time, &signature, index) {
+            Ok(inst_events) => events.extend(inst_events.into_iter().filter(|e| options.keeps(&e.event_type))),
             Err(e) => {
-                tracing::warn!(
-                    "Failed to parse instruction {} in tx {}: {}",
-                    inst_idx,
-                    signature,
-                    e
-                );
+                warnings.push(ParseWarning::new(
+                    slot,
+                    Some(signature.clone()),
+                    Some(index),
+                    "instruction",
+                    format!("Failed to parse inner instruction {} of instruction {}: {}", inner_idx, parent, e),
+                ));
             }
         }
     }
 
     // Extract token transfers from meta
-    if let Ok(transfers) = extract_token_transfers(meta, slot, block_time, &signature) {
-        events.extend(transfers);
+    if options.keeps("token_transfer") {
+        if let Ok(transfers) = extract_token_transfers(meta, slot, block_time, &signature) {
+            events.extend(transfers);
+        }
+    }
+
+    // Extract native SOL balance changes from meta
+    if options.keeps("balance_change") {
+        if let Ok(changes) = extract_balance_changes(meta, tx_data, slot, block_time, &signature) {
+            events.extend(changes);
+        }
+    }
+
+    for event in events.iter_mut() {
+        event.transaction_index = tx_idx as u32;
+        event.success = Some(success);
     }
 
     Ok(events)
 }
 
+/// A transaction succeeded when `meta.err` is null. Any other shape (a bare
+/// string like `"AccountInUse"` or an object like
+/// `{"InstructionError": [0, {"Custom": 1}]}`) is a failure.
+pub fn transaction_succeeded(meta: &Value) -> bool {
+    meta.get("err").is_none_or(|err| err.is_null())
+}
+
 /// Extract transaction signature
 fn extract_signature(tx: &Value) -> Result<String> {
     tx.get("signatures")
@@ -118,43 +634,164 @@ fn extract_instructions(tx: &Value) -> Result<Vec<Value>> {
         .ok_or_else(|| ETLError::Parse("Missing instructions".to_string()))
 }
 
+/// Inner (CPI) instructions as (parent index, position, instruction). The RPC
+/// reports each one's `stackHeight`; where it doesn't (older blocks), the
+/// height is taken from the `invoke [N]` log lines when they line up.
+fn extract_inner_instructions(meta: &Value) -> Vec<(u32, u32, Value)> {
+    let log_heights = invoke_heights(meta);
+    let mut inner = Vec::new();
+
+    let groups = meta.get("innerInstructions").and_then(|v| v.as_array());
+    for group in groups.into_iter().flatten() {
+        let Some(parent) = group.get("index").and_then(|v| v.as_u64()) else {
+            continue;
+        };
+        let instructions = group
+            .get("instructions")
+            .and_then(|v| v.as_array())
+            .map_or(&[][..], |v| v.as_slice());
+        let heights = log_heights
+            .as_ref()
+            .and_then(|heights| heights.get(parent as usize))
+            .filter(|heights| heights.len() == instructions.len());
+
+        for (idx, instruction) in instructions.iter().enumerate() {
+            let mut instruction = instruction.clone();
+            if let (Some(fields), Some(heights)) = (instruction.as_object_mut(), heights) {
+                if fields.get("stackHeight").is_none_or(|h| h.is_null()) {
+                    fields.insert("stackHeight".to_string(), json!(heights[idx]));
+                }
+            }
+            inner.push((parent as u32, idx as u32, instruction));
+        }
+    }
+
+    inner
+}
+
+/// Stack heights of the CPIs under each top-level instruction, in execution
+/// order, from `Program <id> invoke [N]` log lines. `None` when the logs are
+/// missing or truncated.
+fn invoke_heights(meta: &Value) -> Option<Vec<Vec<i64>>> {
+    let logs = meta.get("logMessages")?.as_array()?;
+    let mut heights: Vec<Vec<i64>> = Vec::new();
+    for line in logs.iter().filter_map(|line| line.as_str()) {
+        if line == "Log truncated" {
+            return None;
+        }
+        let height = line
+            .strip_prefix("Program ")
+            .and_then(|rest| rest.split_once(" invoke ["))
+            .and_then(|(_, height)| height.strip_suffix(']'))
+            .and_then(|height| height.parse::<i64>().ok());
+        match height {
+            Some(1) => heights.push(Vec::new()),
+            Some(height) => heights.last_mut()?.push(height),
+            None => {}
+        }
+    }
+    Some(heights)
+}
+
+/// Build the full account-key list: static keys followed by lookup-table
+/// writable then readonly addresses, matching the runtime's index order
+fn resolve_account_keys(tx: &Value, meta: &Value) -> Vec<String> {
+    let mut keys = Vec::new();
+    // jsonParsed already lists lookup-table keys inline (source = "lookupTable")
+    let mut has_inline_lookups = false;
+
+    if let Some(account_keys) = tx
+        .get("message")
+        .and_then(|m| m.get("accountKeys"))
+        .and_then(|v| v.as_array())
+    {
+        for key in account_keys {
+            if let Some(addr) = key.as_str() {
+                keys.push(addr.to_string());
+            } else if let Some(addr) = key.get("pubkey").and_then(|v| v.as_str()) {
+                keys.push(addr.to_string());
+                if key.get("source").and_then(|v| v.as_str()) == Some("lookupTable") {
+                    has_inline_lookups = true;
+                }
+            }
+        }
+    }
+
+    if !has_inline_lookups {
+        if let Some(loaded) = meta.get("loadedAddresses") {
+            for group in ["writable", "readonly"] {
+                if let Some(addrs) = loaded.get(group).and_then(|v| v.as_array()) {
+                    keys.extend(addrs.iter().filter_map(|v| v.as_str()).map(|s| s.to_string()));
+                }
+            }
+        }
+    }
+
+    keys
+}
+
+/// Resolve an instruction's `accounts` into addresses, in instruction order.
+/// Accepts both index arrays (json encoding) and address arrays (jsonParsed).
+fn resolve_instruction_accounts(instruction: &Value, account_keys: &[String]) -> Vec<String> {
+    let accounts = match instruction.get("accounts").and_then(|v| v.as_array()) {
+        Some(accounts) => accounts,
+        None => return Vec::new(),
+    };
+
+    accounts
+        .iter()
+        .filter_map(|account| {
+            if let Some(addr) = account.as_str() {
+                Some(addr.to_string())
+            } else {
+                account
+                    .as_u64()
+                    .and_then(|idx| account_keys.get(idx as usize))
+                    .cloned()
+            }
+        })
+        .collect()
+}
+
 /// Parse an instruction into events
 fn parse_instruction(
     instruction: &Value,
+    account_keys: &[String],
     slot: u64,
     block_time: DateTime<Utc>,
     tx_signature: &str,
     instruction_index: i32,
 ) -> Result<Vec<CanonicalEvent>> {
+    // jsonParsed gives programId directly; json encoding only has programIdIndex
     let program_id = instruction
         .get("programId")
         .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-
-    let program_id_str = program_id.as_deref().unwrap_or("unknown");
-
-    // Determine instruction type based on program
-    let event_type = if program_id_str == TOKEN_PROGRAM_ID || program_id_str == TOKEN_2022_PROGRAM_ID {
-        "token_instruction".to_string()
-    } else {
-        "program_instruction".to_string()
-    };
+        .map(|s| s.to_string())
+        .or_else(|| {
+            instruction
+                .get("programIdIndex")
+                .and_then(|v| v.as_u64())
+                .and_then(|idx| account_keys.get(idx as usize))
+                .cloned()
+        });
 
-    let base_event = CanonicalEvent::new(
+    let accounts = resolve_instruction_accounts(instruction, account_keys);
+    let ctx = ParseContext {
         slot,
         block_time,
-        tx_signature.to_string(),
-        program_id.clone(),
+        tx_signature,
         instruction_index,
-        event_type,
-        instruction.clone(),
-    );
-
-    let mut events = vec![base_event];
+        program_id: program_id.as_deref().unwrap_or("unknown"),
+        accounts: &accounts,
+    };
 
-    // Extract log messages if available
-    // Note: Logs are typically in transaction meta, not instruction
-    // This is a simplified version
+    let mut events = default_registry().parse(instruction, &ctx);
+    if program_id.is_none() {
+        // Keep unresolvable program ids as NULL rather than "unknown"
+        for event in &mut events {
+            event.program_id = None;
+        }
+    }
 
     Ok(events)
 }
@@ -191,7 +828,7 @@ fn extract_token_transfers(
                 block_time,
                 tx_signature.to_string(),
                 Some(TOKEN_PROGRAM_ID.to_string()),
-                idx as i32,
+                IndexSpace::TokenBalance(idx as u32).index(),
                 "token_transfer".to_string(),
                 post_balance.clone(),
             );
@@ -202,7 +839,220 @@ fn extract_token_transfers(
     Ok(events)
 }
 
+/// Extract native SOL balance changes by diffing pre/post balances per account.
+///
+/// The fee payer (account index 0) also pays the transaction fee, so its
+/// payload separates the fee from the rest of its balance movement.
+pub fn extract_balance_changes(
+    meta: &Value,
+    tx_data: &Value,
+    slot: u64,
+    block_time: DateTime<Utc>,
+    tx_signature: &str,
+) -> Result<Vec<CanonicalEvent>> {
+    let pre_balances = meta
+        .get("preBalances")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| ETLError::Parse("Missing preBalances".to_string()))?;
+    let post_balances = meta
+        .get("postBalances")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| ETLError::Parse("Missing postBalances".to_string()))?;
+    let fee = meta.get("fee").and_then(|v| v.as_i64()).unwrap_or(0);
+
+    let account_keys = resolve_account_keys(tx_data, meta);
+    let mut events = Vec::new();
+
+    for (idx, (pre, post)) in pre_balances.iter().zip(post_balances.iter()).enumerate() {
+        let (pre, post) = match (pre.as_i64(), post.as_i64()) {
+            (Some(pre), Some(post)) => (pre, post),
+            _ => continue,
+        };
+        if pre == post {
+            continue;
+        }
+
+        let delta = post - pre;
+        let mut payload = json!({
+            "account": account_keys.get(idx),
+            "account_index": idx,
+            "pre_lamports": pre,
+            "post_lamports": post,
+            "delta_lamports": delta,
+        });
+        if idx == 0 {
+            payload["fee_lamports"] = json!(fee);
+            payload["delta_lamports_excluding_fee"] = json!(delta + fee);
+        }
+
+        events.push(CanonicalEvent::new(
+            slot,
+            block_time,
+            tx_signature.to_string(),
+            None,
+            IndexSpace::BalanceChange(idx as u32).index(),
+            "balance_change".to_string(),
+            payload,
+        ));
+    }
+
+    Ok(events)
+}
+
+/// Keep only events whose type matches one of `event_types` (`*` globs, as in
+/// `WAREHOUSE_ROUTES`); `None` keeps everything. With `keep_transactions`,
+/// base `transaction` events are kept even when not listed.
+pub fn retain_event_types(events: &mut Vec<CanonicalEvent>, event_types: Option<&[String]>, keep_transactions: bool) {
+    if event_types.is_some() {
+        events.retain(|event| event_type_kept(event_types, keep_transactions, &event.event_type));
+    }
+}
+
+fn event_type_kept(event_types: Option<&[String]>, keep_transactions: bool, event_type: &str) -> bool {
+    let Some(patterns) = event_types else {
+        return true;
+    };
+    (keep_transactions && event_type == "transaction") || patterns.iter().any(|pattern| glob_match(pattern, event_type))
+}
+
+/// Drop the instruction and transfer events of failed transactions, whose
+/// effects never happened, keeping their base `transaction` event with
+/// `success = false`. Balance changes stay: a failed transaction's balances
+/// only move by the fee, which is charged all the same.
+pub fn drop_failed_effects(events: &mut Vec<CanonicalEvent>) {
+    events.retain(|event| {
+        event.success != Some(false) || matches!(event.event_type.as_str(), "transaction" | "balance_change")
+    });
+}
+
+/// Fold each transaction's events into one `WideTransaction`, in block
+/// order. Events outside a transaction (rewards) are ignored; `events` is
+/// left as is.
+pub fn widen_transactions(events: &[CanonicalEvent]) -> Vec<WideTransaction> {
+    let mut rows: Vec<WideTransaction> = Vec::new();
+    let mut by_signature: HashMap<&str, usize> = HashMap::new();
+
+    for event in events.iter().filter(|e| e.event_type == "transaction") {
+        by_signature.insert(&event.tx_signature, rows.len());
+        rows.push(WideTransaction {
+            slot: event.slot,
+            block_time: event.block_time,
+            tx_signature: event.tx_signature.clone(),
+            transaction_index: event.transaction_index,
+            success: event.success,
+            fee_lamports: event.raw_payload.pointer("/meta/fee").and_then(|v| v.as_u64()),
+            program_ids: Vec::new(),
+            instruction_count: 0,
+            token_transfer_count: 0,
+            lamports_moved: 0,
+        });
+    }
+
+    for event in events {
+        let Some(&idx) = by_signature.get(event.tx_signature.as_str()) else {
+            continue;
+        };
+        let row = &mut rows[idx];
+        match event.event_type.as_str() {
+            // Inner (CPI) instructions share the signature but aren't counted
+            "program_instruction" | "token_instruction" if IndexSpace::is_top_level(event.instruction_index) => {
+                row.instruction_count += 1;
+                if let Some(program_id) = &event.program_id {
+                    if !row.program_ids.contains(program_id) {
+                        row.program_ids.push(program_id.clone());
+                    }
+                }
+            }
+            "token_transfer" => row.token_transfer_count += 1,
+            "balance_change" => {
+                let delta = event.raw_payload.get("delta_lamports").and_then(|v| v.as_i64()).unwrap_or(0);
+                row.lamports_moved += delta.max(0) as u64;
+            }
+            _ => {}
+        }
+    }
+
+    rows
+}
+
+/// Replace instruction-level events with per-(slot, program) invocation
+/// counts. Every instruction yields one `program_instruction` or
+/// `token_instruction` event, which is what gets counted; those and the
+/// events derived from them (compute budget, memo, lookup tables) are
+/// removed. Transactions, transfers and balance changes are kept.
+pub fn aggregate_instructions(events: &mut Vec<CanonicalEvent>) -> Vec<ProgramInvocation> {
+    let mut counts: BTreeMap<(u64, String), u64> = BTreeMap::new();
+    for event in events.iter() {
+        if event.event_type == "program_instruction" || event.event_type == "token_instruction" {
+            let program_id = event.program_id.clone().unwrap_or_else(|| "unknown".to_string());
+            *counts.entry((event.slot, program_id)).or_insert(0) += 1;
+        }
+    }
+    events.retain(|event| event_rank(event) != 1);
+
+    counts
+        .into_iter()
+        .map(|((slot, program_id), count)| ProgramInvocation { slot, program_id, count })
+        .collect()
+}
+
+/// Bound per-row storage: for events whose serialized `raw_payload` exceeds
+/// `max_bytes`, shorten every `data` string in it (an instruction's own, or
+/// each instruction and inner instruction of a transaction payload) to a
+/// prefix, marked with `data_truncated`, the full length in bytes and a
+/// SHA-256 of the full data. `max_bytes == 0` disables the limit.
+pub fn limit_payload_size(events: &mut [CanonicalEvent], max_bytes: usize) {
+    if max_bytes == 0 {
+        return;
+    }
+
+    for event in events.iter_mut() {
+        let size = serde_json::to_vec(&event.raw_payload).map(|bytes| bytes.len()).unwrap_or(0);
+        if size <= max_bytes {
+            continue;
+        }
+        let truncated = truncate_data_fields(&mut event.raw_payload);
+        if truncated > 0 {
+            tracing::debug!(
+                "Truncated {} data field(s) of {} byte payload for event {} (slot {})",
+                truncated, size, event.event_id, event.slot
+            );
+        }
+    }
+}
+
+/// Truncate every `data` string longer than the prefix, anywhere in `value`;
+/// returns how many were cut
+fn truncate_data_fields(value: &mut Value) -> usize {
+    match value {
+        Value::Object(map) => {
+            let mut truncated = 0;
+            if let Some(data) = map.get("data").and_then(|v| v.as_str()).filter(|d| d.len() > TRUNCATED_DATA_PREFIX_BYTES) {
+                let mut end = TRUNCATED_DATA_PREFIX_BYTES;
+                while !data.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let prefix = data[..end].to_string();
+                let data_len = data.len();
+                let data_sha256 = format!("{:x}", Sha256::digest(data.as_bytes()));
+                map.insert("data".to_string(), json!(prefix));
+                map.insert("data_truncated".to_string(), json!(true));
+                map.insert("data_len".to_string(), json!(data_len));
+                map.insert("data_sha256".to_string(), json!(data_sha256));
+                truncated += 1;
+            }
+            truncated + map.values_mut().map(truncate_data_fields).sum::<usize>()
+        }
+        Value::Array(items) => items.iter_mut().map(truncate_data_fields).sum(),
+        _ => 0,
+    }
+}
+
 /// Flatten instructions - expand into individual instruction events
+///
+/// Output is stable-sorted into canonical order: by slot and transaction, with
+/// the transaction event first, then its instructions by index, then
+/// token transfers and other derived events.
 pub fn flatten_instructions(events: Vec<CanonicalEvent>) -> Vec<CanonicalEvent> {
     let mut flattened = Vec::new();
 
@@ -210,34 +1060,708 @@ pub fn flatten_instructions(events: Vec<CanonicalEvent>) -> Vec<CanonicalEvent>
         if event.event_type == "transaction" {
             flattened.push(event);
         } else if event.event_type == "program_instruction" || event.event_type == "token_instruction" {
-            // For now, just add the instruction event
-            // Could expand inner instructions here if needed
+            // Inner instructions already have their own events
             flattened.push(event);
         } else {
             flattened.push(event);
         }
     }
 
+    flattened.sort_by(|a, b| {
+        (a.slot, a.transaction_index, &a.tx_signature, event_rank(a), a.instruction_index).cmp(&(
+            b.slot,
+            b.transaction_index,
+            &b.tx_signature,
+            event_rank(b),
+            b.instruction_index,
+        ))
+    });
+
     flattened
 }
 
-/// Extract wallet addresses from transaction
+/// Position of an event kind within its transaction for canonical ordering
+fn event_rank(event: &CanonicalEvent) -> u8 {
+    match event.event_type.as_str() {
+        "transaction" => 0,
+        "program_instruction" | "token_instruction" | "compute_budget" | "memo"
+        | "alt_create" | "alt_extend" | "alt_close" => 1,
+        "token_transfer" | "lamports_transfer" => 2,
+        _ => 3,
+    }
+}
+
+/// Extract the distinct wallet addresses of a transaction (static and
+/// lookup-table keys, plain-string or jsonParsed), in first-seen order
 pub fn extract_wallets(tx: &Value) -> Vec<String> {
-    let mut wallets = Vec::new();
-
-    if let Some(message) = tx.get("transaction").and_then(|t| t.get("message")) {
-        // Extract account keys
-        if let Some(account_keys) = message.get("accountKeys").and_then(|v| v.as_array()) {
-            for key in account_keys {
-                if let Some(addr) = key.as_str() {
-                    wallets.push(addr.to_string());
-                } else if let Some(addr) = key.get("pubkey").and_then(|v| v.as_str()) {
-                    wallets.push(addr.to_string());
+    let message_tx = match tx.get("transaction") {
+        Some(message_tx) => message_tx,
+        None => return Vec::new(),
+    };
+    let meta = tx.get("meta").unwrap_or(&Value::Null);
+
+    // An address can be listed more than once (e.g. a static key that is
+    // also loaded from a lookup table); keep the first occurrence only
+    let mut seen = HashSet::new();
+    resolve_account_keys(message_tx, meta)
+        .into_iter()
+        .filter(|addr| seen.insert(addr.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{reencode_event_id, ProgramEvent};
+    use crate::test_support::{block, transaction};
+
+    fn events_of<'a>(events: &'a [CanonicalEvent], event_type: &str) -> Vec<&'a CanonicalEvent> {
+        events.iter().filter(|e| e.event_type == event_type).collect()
+    }
+
+    #[test]
+    fn instruction_accounts_resolve_in_instruction_order() {
+        let mut tx = transaction(
+            "sig1",
+            &["payer", "alice", "bob", "Prog111"],
+            json!([{ "programIdIndex": 3, "accounts": [5, 1, 0, 4], "data": "" }]),
+        );
+        tx["meta"]["loadedAddresses"] = json!({ "writable": ["lookupW"], "readonly": ["lookupR"] });
+
+        let events = parse_block(&block(vec![tx]), 100).unwrap();
+        let instructions = events_of(&events, "program_instruction");
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].program_id.as_deref(), Some("Prog111"));
+        assert_eq!(instructions[0].accounts, vec!["lookupR", "alice", "payer", "lookupW"]);
+    }
+
+    #[test]
+    fn raw_encoded_instructions_resolve_program_ids_from_their_index() {
+        // `json` encoding: no programId, only an index into the account keys
+        let mut tx = transaction(
+            "sig1",
+            &["payer", "Prog111", "Prog222"],
+            json!([
+                { "programIdIndex": 2, "accounts": [0], "data": "3Bxs4NN8M2Yn4TLb" },
+                { "programIdIndex": 9, "accounts": [0], "data": "" },
+            ]),
+        );
+        tx["meta"]["innerInstructions"] =
+            json!([{ "index": 0, "instructions": [{ "programIdIndex": 1, "accounts": [0], "data": "", "stackHeight": 2 }] }]);
+
+        let events = parse_block(&block(vec![tx]), 100).unwrap();
+        let program_ids: Vec<Option<&str>> =
+            events_of(&events, "program_instruction").iter().map(|e| e.program_id.as_deref()).collect();
+        // Top-level first, then the inner instruction; an index past the keys stays NULL
+        assert_eq!(program_ids, vec![Some("Prog222"), None, Some("Prog111")]);
+    }
+
+    #[test]
+    fn balance_changes_separate_the_fee_payer_fee() {
+        let mut tx = transaction(
+            "sig1",
+            &["payer", "receiver", "11111111111111111111111111111111"],
+            json!([{ "programIdIndex": 2, "accounts": [0, 1], "data": "3Bxs4NN8M2Yn4TLb" }]),
+        );
+        tx["meta"]["preBalances"] = json!([1_000_000, 0, 1]);
+        tx["meta"]["postBalances"] = json!([994_000, 1_000, 1]);
+
+        let events = parse_block(&block(vec![tx]), 100).unwrap();
+        let changes = events_of(&events, "balance_change");
+        assert_eq!(changes.len(), 2, "unchanged accounts get no event");
+
+        let payer = &changes[0].raw_payload;
+        assert_eq!(payer["account"], "payer");
+        assert_eq!(payer["pre_lamports"], 1_000_000);
+        assert_eq!(payer["post_lamports"], 994_000);
+        assert_eq!(payer["delta_lamports"], -6_000);
+        assert_eq!(payer["fee_lamports"], 5_000);
+        assert_eq!(payer["delta_lamports_excluding_fee"], -1_000);
+
+        let receiver = &changes[1].raw_payload;
+        assert_eq!(receiver["account"], "receiver");
+        assert_eq!(receiver["delta_lamports"], 1_000);
+        assert!(receiver.get("fee_lamports").is_none());
+    }
+
+    #[test]
+    fn flatten_puts_transaction_then_instructions_then_transfers() {
+        let mut tx = transaction(
+            "sig1",
+            &["payer", "ProgA", "ProgB"],
+            json!([
+                { "programIdIndex": 1, "accounts": [0], "data": "" },
+                { "programIdIndex": 2, "accounts": [0], "data": "" },
+                { "programIdIndex": 1, "accounts": [0], "data": "" },
+            ]),
+        );
+        tx["meta"]["postTokenBalances"] = json!([{ "accountIndex": 0, "mint": "MintA", "owner": "payer" }]);
+        let mut events = parse_block(&block(vec![tx]), 100).unwrap();
+        events.reverse();
+
+        let order: Vec<(String, i32)> = flatten_instructions(events)
+            .into_iter()
+            .map(|e| (e.event_type, e.instruction_index))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("transaction".to_string(), -1),
+                ("program_instruction".to_string(), 0),
+                ("program_instruction".to_string(), 1),
+                ("program_instruction".to_string(), 2),
+                ("token_transfer".to_string(), IndexSpace::TokenBalance(0).index()),
+            ]
+        );
+    }
+
+    /// A block with one event of every type: a transaction with instructions,
+    /// a token transfer and balance changes, plus a reward
+    fn rich_block() -> Value {
+        let mut tx = transaction(
+            "sig1",
+            &[
+                "payer",
+                "receiver",
+                "11111111111111111111111111111111",
+                "ComputeBudget111111111111111111111111111111",
+                "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+            ],
+            json!([
+                { "programIdIndex": 3, "accounts": [], "data": "K1FDJ7" },
+                { "programIdIndex": 2, "accounts": [0, 1], "data": "3Bxs4NN8M2Yn4TLb" },
+                { "programIdIndex": 4, "accounts": [0], "data": "Zbi" },
+            ]),
+        );
+        tx["meta"]["preBalances"] = json!([1_000_000, 0, 1, 1, 1]);
+        tx["meta"]["postBalances"] = json!([994_000, 1_000, 1, 1, 1]);
+        tx["meta"]["preTokenBalances"] = json!([
+            { "accountIndex": 0, "mint": "MintA", "owner": "payer", "uiTokenAmount": { "amount": "10", "decimals": 0 } },
+        ]);
+        tx["meta"]["postTokenBalances"] = json!([
+            { "accountIndex": 0, "mint": "MintA", "owner": "payer", "uiTokenAmount": { "amount": "4", "decimals": 0 } },
+            { "accountIndex": 1, "mint": "MintA", "owner": "receiver", "uiTokenAmount": { "amount": "6", "decimals": 0 } },
+        ]);
+        let mut block = block(vec![tx]);
+        block["rewards"] = json!([
+            { "pubkey": "validator", "lamports": 2_500, "postBalance": 10_000, "rewardType": "Fee" },
+        ]);
+
+        block
+    }
+
+    #[test]
+    fn a_multi_instruction_transaction_widens_into_one_row() {
+        let mut block = rich_block();
+        // A second system transfer: counted again, but its program listed once
+        block["transactions"][0]["transaction"]["message"]["instructions"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "programIdIndex": 2, "accounts": [0, 1], "data": "3Bxs4NN8M2Yn4TLb" }));
+        // A CPI into another program: an event of its own, but not a top-level instruction
+        block["transactions"][0]["transaction"]["message"]["accountKeys"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!("CpiTarget1111111111111111111111111111111111"));
+        let cpi_index = block["transactions"][0]["transaction"]["message"]["accountKeys"].as_array().unwrap().len() - 1;
+        block["transactions"][0]["meta"]["innerInstructions"] =
+            json!([{ "index": 0, "instructions": [{ "programIdIndex": cpi_index, "accounts": [0], "data": "", "stackHeight": 2 }] }]);
+        let events = parse_block(&block, 100).unwrap();
+        assert!(events.iter().any(|e| e.program_id.as_deref() == Some("CpiTarget1111111111111111111111111111111111")));
+
+        let rows = widen_transactions(&events);
+        assert_eq!(rows.len(), 1, "rewards are not transactions");
+        let row = &rows[0];
+        assert_eq!(row.tx_signature, "sig1");
+        assert_eq!(row.slot, 100);
+        assert_eq!(
+            row.program_ids,
+            vec![
+                "ComputeBudget111111111111111111111111111111",
+                "11111111111111111111111111111111",
+                "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+            ]
+        );
+        assert_eq!(row.instruction_count, 4);
+        // One token transfer event per post token balance
+        assert_eq!(row.token_transfer_count, 2);
+        // Only the receiver's credit counts; the payer's debit is not added
+        assert_eq!(row.lamports_moved, 1_000);
+        assert_eq!(row.success, Some(true));
+    }
+
+    #[test]
+    fn every_event_of_a_rich_transaction_gets_its_own_index() {
+        let block = rich_block();
+        let events = parse_block(&block, 100).unwrap();
+        for event_type in ["transaction", "program_instruction", "token_transfer", "balance_change", "reward"] {
+            assert!(!events_of(&events, event_type).is_empty(), "no {} event", event_type);
+        }
+
+        let indices: HashSet<(i32, &str)> = events
+            .iter()
+            .map(|e| (e.instruction_index, e.event_type.as_str()))
+            .collect();
+        let ids: HashSet<&str> = events.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(indices.len(), events.len());
+        assert_eq!(ids.len(), events.len());
+    }
+
+    #[test]
+    fn instructions_past_the_limit_are_skipped_with_a_warning() {
+        let instructions: Vec<Value> = (0..MAX_INSTRUCTIONS + 5)
+            .map(|_| json!({ "programIdIndex": 1, "accounts": [0], "data": "" }))
+            .collect();
+        let tx = transaction("sig1", &["payer", "ProgA"], Value::Array(instructions));
+
+        let mut warnings = Vec::new();
+        let block_time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let events = parse_transaction(&tx, 100, block_time, 0, &ParseOptions::default(), &mut warnings).unwrap();
+
+        let instructions = events_of(&events, "program_instruction");
+        assert_eq!(instructions.len(), MAX_INSTRUCTIONS as usize);
+        assert!(instructions.iter().all(|e| e.instruction_index < MAX_INSTRUCTIONS as i32));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn lone_surrogate_in_a_log_does_not_drop_the_transaction() {
+        let tx = transaction("sig1", &["payer", "ProgA"], json!([]));
+        let text = block(vec![tx]).to_string().replace(r#""logMessages":[]"#, r#""logMessages":["bad \ud800 log"]"#);
+        assert!(text.contains(r"\ud800"));
+        let raw: RawBlock = serde_json::from_str(&text).unwrap();
+
+        let parsed = parse_raw_block(&raw, 100, &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.failed_transactions, 0);
+        let tx_event = &events_of(&parsed.events, "transaction")[0];
+        assert_eq!(tx_event.raw_payload["meta"]["logMessages"][0], "bad \u{FFFD} log");
+    }
+
+    #[test]
+    fn block_height_is_stamped_on_every_event_or_left_null() {
+        let tx = transaction("sig1", &["payer", "ProgA"], json!([
+            { "programIdIndex": 1, "accounts": [0], "data": "" }
+        ]));
+        let mut block = block(vec![tx]);
+        assert_eq!(extract_block_height(&block), Some(250_000_000));
+
+        let events = parse_block(&block, 100).unwrap();
+        assert!(events.iter().all(|e| e.block_height == Some(250_000_000)));
+        let raw: RawBlock = serde_json::from_value(block.clone()).unwrap();
+        let parsed = parse_raw_block(&raw, 100, &ParseOptions::default()).unwrap();
+        assert!(parsed.events.iter().all(|e| e.block_height == Some(250_000_000)));
+
+        // Older blocks have no blockHeight
+        block.as_object_mut().unwrap().remove("blockHeight");
+        assert_eq!(extract_block_height(&block), None);
+        assert!(parse_block(&block, 100).unwrap().iter().all(|e| e.block_height.is_none()));
+    }
+
+    #[test]
+    fn oversized_payloads_truncate_every_data_field() {
+        let big = "x".repeat(5_000);
+        let mut tx = transaction("sig1", &["payer", "ProgA"], json!([
+            { "programIdIndex": 1, "accounts": [0], "data": big },
+            { "programIdIndex": 1, "accounts": [0], "data": "short" },
+        ]));
+        tx["meta"]["innerInstructions"] = json!([
+            { "index": 0, "instructions": [{ "programIdIndex": 1, "accounts": [0], "data": big, "stackHeight": 2 }] }
+        ]);
+        let mut events = parse_block(&block(vec![tx]), 100).unwrap();
+        limit_payload_size(&mut events, 2_000);
+
+        let tx_event = events_of(&events, "transaction")[0];
+        let top_level = &tx_event.raw_payload["transaction"]["message"]["instructions"];
+        let inner = &tx_event.raw_payload["meta"]["innerInstructions"][0]["instructions"][0];
+        for truncated in [&top_level[0], inner] {
+            assert_eq!(truncated["data"].as_str().unwrap().len(), TRUNCATED_DATA_PREFIX_BYTES);
+            assert_eq!(truncated["data_truncated"], true);
+            assert_eq!(truncated["data_len"], 5_000);
+            assert_eq!(truncated["data_sha256"], format!("{:x}", Sha256::digest(big.as_bytes())));
+        }
+        assert_eq!(top_level[1]["data"], "short");
+        assert!(top_level[1].get("data_truncated").is_none());
+        assert!(serde_json::to_vec(&tx_event.raw_payload).unwrap().len() < 2_000);
+
+        // The instruction's own event is cut too
+        let instruction = events_of(&events, "program_instruction")[0];
+        assert_eq!(instruction.raw_payload["data_truncated"], true);
+    }
+
+    #[test]
+    fn truncated_data_keeps_whole_characters_and_small_payloads_are_untouched() {
+        let account = |data: String| {
+            let payload = json!({ "data": data });
+            CanonicalEvent::new(100, Utc::now(), String::new(), None, -1, "program_account".to_string(), payload)
+        };
+
+        // Two bytes per character: the cut lands on a character boundary
+        let mut wide = [account(format!("a{}", "é".repeat(100)))];
+        limit_payload_size(&mut wide, 150);
+        let kept = (TRUNCATED_DATA_PREFIX_BYTES - 1) / 2;
+        assert_eq!(wide[0].raw_payload["data"], format!("a{}", "é".repeat(kept)));
+        assert_eq!(wide[0].raw_payload["data_len"], 201);
+
+        let mut small = [account("y".repeat(100))];
+        let before = small[0].raw_payload.clone();
+        limit_payload_size(&mut small, 1_000);
+        assert_eq!(small[0].raw_payload, before);
+    }
+
+    #[test]
+    fn repeated_signature_in_a_block_gets_distinct_ids_and_warnings() {
+        let tx = transaction("sig1", &["payer", "ProgA"], json!([
+            { "programIdIndex": 1, "accounts": [0], "data": "" }
+        ]));
+        let block = block(vec![tx.clone(), tx]);
+
+        let parsed = parse_block_with_warnings(&block, 100).unwrap();
+        let per_transaction = parsed.events.len() / 2;
+        assert!(per_transaction > 0);
+        let ids: HashSet<&str> = parsed.events.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(ids.len(), parsed.events.len());
+
+        let dedup: Vec<&ParseWarning> = parsed.warnings.iter().filter(|w| w.stage == "dedup").collect();
+        assert_eq!(dedup.len(), per_transaction);
+        assert!(dedup.iter().all(|w| w.tx_signature.as_deref() == Some("sig1")));
+
+        // The second copy's ids are deterministic, so a rerun upserts the same rows
+        let again = parse_block_with_warnings(&block, 100).unwrap();
+        let ids_again: HashSet<&str> = again.events.iter().map(|e| e.event_id.as_str()).collect();
+        assert_eq!(ids, ids_again);
+    }
+
+    #[test]
+    fn three_deep_cpi_gets_inner_indices_and_stack_heights() {
+        let cpi = |with_heights: bool| {
+            let mut tx = transaction("sig1", &["payer", "ProgA", "ProgB", "ProgC"], json!([
+                { "programIdIndex": 1, "accounts": [0], "data": "3Bxs" },
+            ]));
+            let mut inner = json!([
+                { "programIdIndex": 2, "accounts": [0], "data": "3Bxs", "stackHeight": 2 },
+                { "programIdIndex": 3, "accounts": [0], "data": "3Bxs", "stackHeight": 3 },
+            ]);
+            if !with_heights {
+                for instruction in inner.as_array_mut().unwrap() {
+                    instruction.as_object_mut().unwrap().remove("stackHeight");
                 }
             }
+            tx["meta"]["innerInstructions"] = json!([{ "index": 0, "instructions": inner }]);
+            tx["meta"]["logMessages"] = json!([
+                "Program ProgA invoke [1]",
+                "Program ProgB invoke [2]",
+                "Program ProgC invoke [3]",
+                "Program ProgC success",
+                "Program ProgB success",
+                "Program ProgA success",
+            ]);
+            tx
+        };
+
+        // stackHeight from the RPC, then reconstructed from the logs
+        for with_heights in [true, false] {
+            let events = parse_block(&block(vec![cpi(with_heights)]), 100).unwrap();
+            let instructions: Vec<(String, i32, i32)> = events_of(&events, "program_instruction")
+                .into_iter()
+                .map(|e| {
+                    let program = ProgramEvent::from_canonical(e, &HashMap::new()).unwrap();
+                    (e.program_id.clone().unwrap(), e.instruction_index, program.stack_height)
+                })
+                .collect();
+            assert_eq!(
+                instructions,
+                vec![
+                    ("ProgA".to_string(), 0, 1),
+                    ("ProgB".to_string(), IndexSpace::Inner { parent: 0, inner: 0 }.index(), 2),
+                    ("ProgC".to_string(), IndexSpace::Inner { parent: 0, inner: 1 }.index(), 3),
+                ],
+                "with_heights = {}",
+                with_heights
+            );
         }
+
+        // Truncated logs can't be lined up, so the height stays unknown (1)
+        let mut tx = cpi(false);
+        tx["meta"]["logMessages"] = json!(["Program ProgA invoke [1]", "Log truncated"]);
+        let events = parse_block(&block(vec![tx]), 100).unwrap();
+        let heights: Vec<i32> = events_of(&events, "program_instruction")
+            .into_iter()
+            .map(|e| ProgramEvent::from_canonical(e, &HashMap::new()).unwrap().stack_height)
+            .collect();
+        assert_eq!(heights, vec![1, 1, 1]);
     }
 
-    wallets
-}
+    #[test]
+    fn wallets_are_listed_once_in_first_seen_order() {
+        // A static key loaded again through a lookup table
+        let mut tx = transaction("sig1", &["payer", "alice", "Prog111"], json!([]));
+        tx["meta"]["loadedAddresses"] = json!({ "writable": ["bob", "alice"], "readonly": ["payer"] });
+        assert_eq!(extract_wallets(&tx), vec!["payer", "alice", "Prog111", "bob"]);
+
+        // jsonParsed lists keys as objects, in several roles and sources
+        let parsed = json!({
+            "transaction": { "message": { "accountKeys": [
+                { "pubkey": "payer", "signer": true, "writable": true, "source": "transaction" },
+                "alice",
+                { "pubkey": "alice", "signer": false, "writable": false, "source": "lookupTable" },
+                { "pubkey": "payer", "signer": false, "writable": false, "source": "lookupTable" },
+            ] } },
+            "meta": { "loadedAddresses": { "writable": ["ignored"], "readonly": [] } },
+        });
+        assert_eq!(extract_wallets(&parsed), vec!["payer", "alice"]);
+    }
 
+    #[test]
+    fn success_follows_every_err_shape() {
+        use crate::events::TransactionEvent;
+
+        let cases = [
+            (json!(null), true, None),
+            (json!("AccountInUse"), false, Some("AccountInUse")),
+            (json!({ "InstructionError": [0, { "Custom": 1 }] }), false, Some(r#"{"InstructionError":[0,{"Custom":1}]}"#)),
+        ];
+        for (err, success, message) in cases {
+            let mut tx = transaction("sig1", &["payer", "Prog111"], json!([{ "programIdIndex": 1, "accounts": [0], "data": "" }]));
+            tx["meta"]["err"] = err.clone();
+            assert_eq!(transaction_succeeded(&tx["meta"]), success, "err = {}", err);
+
+            let events = parse_block(&block(vec![tx]), 100).unwrap();
+            assert!(events.iter().all(|e| e.success == Some(success)), "err = {}", err);
+            let tx_event = TransactionEvent::from_canonical(events_of(&events, "transaction")[0]).unwrap();
+            assert_eq!(tx_event.success, Some(success));
+            assert_eq!(tx_event.error_message.as_deref(), message);
+        }
+
+        // A missing `err` key counts as success
+        assert!(transaction_succeeded(&json!({ "fee": 5000 })));
+    }
+
+    #[test]
+    fn recent_blockhash_is_extracted_from_the_message() {
+        use crate::events::TransactionEvent;
+
+        let mut tx = transaction("sig1", &["payer", "Prog111"], json!([]));
+        tx["transaction"]["message"]["recentBlockhash"] = json!("9sHcv6xwn9YkB8nxTUGKDwPwNnmqVp5oAXxU8Fdkm4J6");
+        let events = parse_block(&block(vec![tx.clone()]), 100).unwrap();
+        let tx_event = TransactionEvent::from_canonical(events_of(&events, "transaction")[0]).unwrap();
+        assert_eq!(tx_event.recent_blockhash.as_deref(), Some("9sHcv6xwn9YkB8nxTUGKDwPwNnmqVp5oAXxU8Fdkm4J6"));
+
+        tx["transaction"]["message"].as_object_mut().unwrap().remove("recentBlockhash");
+        let events = parse_block(&block(vec![tx]), 100).unwrap();
+        let tx_event = TransactionEvent::from_canonical(events_of(&events, "transaction")[0]).unwrap();
+        assert_eq!(tx_event.recent_blockhash, None);
+    }
+
+    #[test]
+    fn only_the_configured_event_types_are_parsed() {
+        let block = rich_block();
+        let parse = |event_types: &[&str], keep_transaction_events: bool| {
+            let options = ParseOptions {
+                event_types: Some(event_types.iter().map(|t| t.to_string()).collect()),
+                keep_transaction_events,
+                ..ParseOptions::default()
+            };
+            let parsed = parse_block_limited(&block, 100, &options).unwrap();
+            parsed.events.iter().map(|e| e.event_type.clone()).collect::<HashSet<_>>()
+        };
+        let set = |types: &[&str]| types.iter().map(|t| t.to_string()).collect::<HashSet<_>>();
+
+        assert_eq!(parse(&["token_transfer"], false), set(&["token_transfer"]));
+        assert_eq!(parse(&["token_transfer"], true), set(&["transaction", "token_transfer"]));
+        assert_eq!(parse(&["*_change", "reward"], false), set(&["balance_change", "reward"]));
+        assert_eq!(parse(&["program_*"], false), set(&["program_instruction"]));
+        assert_eq!(parse(&[], false), set(&[]));
+
+        // Wide rows and aggregates need the full parse, so the filter waits for them
+        let mut etl = crate::test_support::config().etl;
+        etl.event_types = Some(vec!["token_transfer".to_string()]);
+        assert!(ParseOptions::for_ingestion(&etl).unwrap().event_types.is_some());
+        etl.wide_transactions = true;
+        assert!(ParseOptions::for_ingestion(&etl).unwrap().event_types.is_none());
+    }
+
+    #[test]
+    fn oversized_transactions_are_measured_without_serializing_them() {
+        let block = rich_block();
+        let tx = &block["transactions"][0];
+        let size = serde_json::to_string(tx).unwrap().len();
+        assert_eq!(json_size(tx), size);
+
+        // Both parse paths skip a transaction one byte over the limit, and only that
+        let raw: RawBlock = serde_json::from_value(block.clone()).unwrap();
+        for max_transaction_bytes in [size - 1, size] {
+            let options = ParseOptions { max_transaction_bytes, ..ParseOptions::default() };
+            let parsed = parse_block_limited(&block, 100, &options).unwrap();
+            let raw_parsed = parse_raw_block(&raw, 100, &options).unwrap();
+            let expected = usize::from(max_transaction_bytes < size);
+            assert_eq!(parsed.oversized_transactions, expected);
+            assert_eq!(raw_parsed.oversized_transactions, expected);
+        }
+    }
+
+    #[test]
+    fn failed_transactions_keep_only_their_transaction_and_balance_events() {
+        let mut block = rich_block();
+        let mut failed = block["transactions"][0].clone();
+        failed["transaction"]["signatures"] = json!(["sig2"]);
+        failed["meta"]["err"] = json!({ "InstructionError": [1, { "Custom": 1 }] });
+        block["transactions"].as_array_mut().unwrap().push(failed);
+
+        let mut events = parse_block(&block, 100).unwrap();
+        let types_of = |events: &[CanonicalEvent], signature: &str| {
+            let mut types: Vec<String> = events
+                .iter()
+                .filter(|e| e.tx_signature == signature)
+                .map(|e| e.event_type.clone())
+                .collect();
+            types.sort();
+            types.dedup();
+            types
+        };
+        let succeeded = types_of(&events, "sig1");
+        assert_eq!(types_of(&events, "sig2"), succeeded);
+        let rewards = events_of(&events, "reward").len();
+
+        drop_failed_effects(&mut events);
+        // The fee payer's fee debit is real even though the transaction failed
+        assert_eq!(types_of(&events, "sig2"), vec!["balance_change", "transaction"]);
+        let fee_debit = events_of(&events, "balance_change")
+            .into_iter()
+            .find(|e| e.tx_signature == "sig2" && e.raw_payload.get("fee_lamports").is_some());
+        assert!(fee_debit.is_some());
+        assert_eq!(events_of(&events, "transaction").iter().find(|e| e.tx_signature == "sig2").unwrap().success, Some(false));
+        // Successful transactions and events outside any transaction are untouched
+        assert_eq!(types_of(&events, "sig1"), succeeded);
+        assert_eq!(events_of(&events, "reward").len(), rewards);
+    }
+
+    #[test]
+    fn event_ids_are_encoded_in_the_configured_format() {
+        let mut block = rich_block();
+        // A signature listed twice: its second events are disambiguated
+        let repeated = block["transactions"][0].clone();
+        block["transactions"].as_array_mut().unwrap().push(repeated);
+
+        let hex = parse_block(&block, 100).unwrap();
+        let options = ParseOptions { event_id_format: EventIdFormat::Base58_128, ..ParseOptions::default() };
+        let parsed = parse_block_limited(&block, 100, &options).unwrap();
+
+        // Every id is what `migrate --event-ids` turns the hex id into
+        assert_eq!(parsed.events.len(), hex.len());
+        assert!(parsed.warnings.iter().any(|w| w.stage == "dedup"));
+        for (base58, hex) in parsed.events.iter().zip(&hex) {
+            assert!(base58.event_id.len() <= 22, "{}", base58.event_id);
+            assert_eq!(Some(base58.event_id.clone()), reencode_event_id(&hex.event_id, EventIdFormat::Base58_128));
+        }
+
+        let mut etl = crate::test_support::config().etl;
+        etl.event_id_format = "base58_128".to_string();
+        assert_eq!(ParseOptions::for_ingestion(&etl).unwrap().event_id_format, EventIdFormat::Base58_128);
+        etl.event_id_format = "base64".to_string();
+        assert!(ParseOptions::for_ingestion(&etl).is_err());
+    }
+
+    #[test]
+    fn invocation_counts_match_the_instructions_per_program() {
+        let mut tx = transaction(
+            "sig1",
+            &["payer", "ProgA", "ProgB"],
+            json!([
+                { "programIdIndex": 1, "accounts": [0], "data": "" },
+                { "programIdIndex": 2, "accounts": [0], "data": "" },
+                { "programIdIndex": 1, "accounts": [0], "data": "" },
+            ]),
+        );
+        // ProgA calls ProgB from its first instruction
+        tx["meta"]["innerInstructions"] = json!([
+            { "index": 0, "instructions": [{ "programIdIndex": 2, "accounts": [0], "data": "", "stackHeight": 2 }] },
+        ]);
+        let other = transaction("sig2", &["payer", "ProgB"], json!([{ "programIdIndex": 1, "accounts": [0], "data": "" }]));
+        let mut events = parse_block(&block(vec![tx, other]), 100).unwrap();
+
+        let invocations = aggregate_instructions(&mut events);
+        let counts: Vec<_> = invocations.iter().map(|i| (i.slot, i.program_id.as_str(), i.count)).collect();
+        assert_eq!(counts, vec![(100, "ProgA", 2), (100, "ProgB", 3)]);
+        // Only the per-program counts remain of the instructions
+        assert!(events_of(&events, "program_instruction").is_empty());
+        assert_eq!(events_of(&events, "transaction").len(), 2);
+    }
+
+    #[test]
+    fn a_large_raw_block_parses_like_its_value() {
+        // A dense block of 3,000 varied transactions, about 2 MB of JSON
+        let transactions = (0..3_000)
+            .map(|i| {
+                let instructions = (0..4).map(|j| json!({ "programIdIndex": 1 + j % 2, "accounts": [0, 3], "data": "3Bxs4NN8M2Yn4TLb" }));
+                let mut tx = transaction(&format!("sig{}", i), &["payer", "ProgA", "ProgB", "receiver"], Value::Array(instructions.collect()));
+                tx["meta"]["preBalances"] = json!([1_000_000, 1, 1, 0]);
+                tx["meta"]["postBalances"] = json!([994_000 - i, 1, 1, 1_000 + i]);
+                tx["meta"]["logMessages"] = json!(["Program ProgA invoke [1]", "Program ProgA success"]);
+                if i % 100 == 0 {
+                    tx["meta"]["err"] = json!({ "InstructionError": [0, { "Custom": 1 }] });
+                }
+                tx
+            })
+            .collect();
+        let mut block = block(transactions);
+        block["rewards"] = json!([{ "pubkey": "validator", "lamports": 2_500, "postBalance": 10_000, "rewardType": "Fee" }]);
+        let json = block.to_string();
+        assert!(json.len() > 2_000_000, "{}", json.len());
+
+        // Transactions stay as their JSON text until parsed one at a time
+        let raw: RawBlock = serde_json::from_str(&json).unwrap();
+        let raw_transactions = raw.transactions.as_ref().unwrap();
+        assert_eq!(raw_transactions.len(), 3_000);
+        assert!(raw_transactions.iter().map(|tx| tx.get().len()).sum::<usize>() < json.len());
+
+        let from_raw = parse_raw_block(&raw, 100, &ParseOptions::default()).unwrap();
+        let from_value = parse_block_limited(&block, 100, &ParseOptions::default()).unwrap();
+        assert_eq!(from_raw.failed_transactions, 0);
+        assert_eq!(from_raw.events.len(), from_value.events.len());
+        assert!(from_raw.events.len() > 3_000 * 5, "{}", from_raw.events.len());
+        for (raw_event, value_event) in from_raw.events.iter().zip(&from_value.events) {
+            assert_eq!(raw_event.event_id, value_event.event_id);
+            assert_eq!(raw_event.raw_payload, value_event.raw_payload);
+            assert_eq!(raw_event.success, value_event.success);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_parse_pool_keeps_the_runtime_free_during_a_large_block() {
+        // A dense block: 3,000 transactions of four instructions each
+        let transactions = (0..3_000)
+            .map(|i| {
+                let instructions = (0..4).map(|_| json!({ "programIdIndex": 1, "accounts": [0], "data": "3Bxs4NN8M2Yn4TLb" }));
+                transaction(&format!("sig{}", i), &["payer", "Prog111"], Value::Array(instructions.collect()))
+            })
+            .collect();
+        let json = block(transactions).to_string();
+        let raw = || serde_json::from_str::<RawBlock>(&json).unwrap();
+
+        // Whether a task spawned on this single-threaded runtime just before
+        // the parse got to run by the time the parse returned
+        async fn other_task_ran_during(pool: &ParsePool, block: RawBlock) -> (bool, ParsedBlock) {
+            let ran = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let other = tokio::spawn({
+                let ran = ran.clone();
+                async move { ran.store(true, std::sync::atomic::Ordering::SeqCst) }
+            });
+            let parsed = pool.parse_raw(block, 100).await.unwrap();
+            let ran_during = ran.load(std::sync::atomic::Ordering::SeqCst);
+            other.await.unwrap();
+            (ran_during, parsed)
+        }
+
+        let (inline_ran, inline) = other_task_ran_during(&ParsePool::new(0, ParseOptions::default()), raw()).await;
+        let (pooled_ran, pooled) = other_task_ran_during(&ParsePool::new(2, ParseOptions::default()), raw()).await;
+
+        // The base transaction event plus one per instruction
+        assert_eq!(pooled.events.len(), 3_000 * 5);
+        let ids = |parsed: &ParsedBlock| parsed.events.iter().map(|e| e.event_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&pooled), ids(&inline));
+        // Inline parsing holds the only runtime thread until it is done; the
+        // pool parses on a blocking thread, so the other task runs meanwhile
+        assert!(!inline_ran);
+        assert!(pooled_ran);
+    }
+}
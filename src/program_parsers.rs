@@ -0,0 +1,552 @@
+use crate::events::CanonicalEvent;
+use base58::{FromBase58, ToBase58};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+pub const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+pub const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+pub const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+pub const MEMO_V1_PROGRAM_ID: &str = "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo";
+pub const ADDRESS_LOOKUP_TABLE_PROGRAM_ID: &str = "AddressLookupTab1e1111111111111111111111111";
+
+/// Where an instruction sits, shared by every parser
+pub struct ParseContext<'a> {
+    pub slot: u64,
+    pub block_time: DateTime<Utc>,
+    pub tx_signature: &'a str,
+    pub instruction_index: i32,
+    pub program_id: &'a str,
+    /// Resolved instruction accounts, in instruction order
+    pub accounts: &'a [String],
+}
+
+impl ParseContext<'_> {
+    /// Event for this instruction with the given type and payload
+    pub fn event(&self, event_type: &str, payload: Value) -> CanonicalEvent {
+        CanonicalEvent::new(
+            self.slot,
+            self.block_time,
+            self.tx_signature.to_string(),
+            Some(self.program_id.to_string()),
+            self.instruction_index,
+            event_type.to_string(),
+            payload,
+        )
+    }
+
+    /// The raw instruction as an event, with its resolved accounts
+    pub fn instruction_event(&self, event_type: &str, instruction: &Value) -> CanonicalEvent {
+        let mut event = self.event(event_type, instruction.clone());
+        event.accounts = self.accounts.to_vec();
+        event
+    }
+}
+
+/// Program-specific instruction parsing
+pub trait ProgramParser: Send + Sync {
+    fn program_id(&self) -> &str;
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent>;
+}
+
+/// Parsers by program id. Programs without a parser get a generic
+/// `program_instruction` event.
+#[derive(Default)]
+pub struct ParserRegistry {
+    parsers: HashMap<String, Box<dyn ProgramParser>>,
+}
+
+impl ParserRegistry {
+    /// Registry with the built-in parsers
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::default();
+        registry.register(Box::new(SystemParser));
+        registry.register(Box::new(TokenParser(TOKEN_PROGRAM_ID)));
+        registry.register(Box::new(TokenParser(TOKEN_2022_PROGRAM_ID)));
+        registry.register(Box::new(ComputeBudgetParser));
+        registry.register(Box::new(MemoParser(MEMO_PROGRAM_ID)));
+        registry.register(Box::new(MemoParser(MEMO_V1_PROGRAM_ID)));
+        registry.register(Box::new(AddressLookupTableParser));
+        registry
+    }
+
+    /// Add a parser, replacing any existing one for the same program
+    pub fn register(&mut self, parser: Box<dyn ProgramParser>) {
+        self.parsers.insert(parser.program_id().to_string(), parser);
+    }
+
+    pub fn get(&self, program_id: &str) -> Option<&dyn ProgramParser> {
+        self.parsers.get(program_id).map(|p| p.as_ref())
+    }
+
+    /// Parse with the program's parser, or emit a generic `program_instruction`
+    pub fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        match self.get(ctx.program_id) {
+            Some(parser) => parser.parse(inst, ctx),
+            None => vec![ctx.instruction_event("program_instruction", inst)],
+        }
+    }
+}
+
+/// Registry used by `parse_block`
+pub fn default_registry() -> &'static ParserRegistry {
+    static REGISTRY: OnceLock<ParserRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(ParserRegistry::with_defaults)
+}
+
+/// System program: the instruction plus a `lamports_transfer` event for
+/// Transfer and CreateAccount, which move SOL
+struct SystemParser;
+
+impl ProgramParser for SystemParser {
+    fn program_id(&self) -> &str {
+        SYSTEM_PROGRAM_ID
+    }
+
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        let mut events = vec![ctx.instruction_event("program_instruction", inst)];
+        let decoded = match inst.get("parsed") {
+            Some(parsed) => decode_parsed_system(parsed),
+            None => decode_system(inst, ctx.accounts),
+        };
+        if let Some(payload) = decoded {
+            events.push(ctx.event("lamports_transfer", payload));
+        }
+        events
+    }
+}
+
+/// Decode a `jsonParsed` system transfer or account creation
+fn decode_parsed_system(parsed: &Value) -> Option<Value> {
+    let info = parsed.get("info")?;
+    match parsed.get("type")?.as_str()? {
+        "transfer" => Some(json!({
+            "instruction": "Transfer",
+            "from": info.get("source"),
+            "to": info.get("destination"),
+            "lamports": info.get("lamports"),
+        })),
+        "createAccount" => Some(json!({
+            "instruction": "CreateAccount",
+            "from": info.get("source"),
+            "to": info.get("newAccount"),
+            "lamports": info.get("lamports"),
+            "space": info.get("space"),
+            "owner": info.get("owner"),
+        })),
+        _ => None,
+    }
+}
+
+/// Decode a raw system instruction: a u32 LE discriminant, then for
+/// CreateAccount (0) lamports, space (u64 LE) and the owner, and for
+/// Transfer (2) lamports. Accounts are the payer then the recipient.
+fn decode_system(instruction: &Value, accounts: &[String]) -> Option<Value> {
+    let data = instruction
+        .get("data")
+        .and_then(|v| v.as_str())?
+        .from_base58()
+        .ok()?;
+    let discriminant = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let args = &data[4..];
+    let lamports = u64::from_le_bytes(args.get(..8)?.try_into().ok()?);
+
+    match discriminant {
+        0 => {
+            let space = u64::from_le_bytes(args.get(8..16)?.try_into().ok()?);
+            let owner = args.get(16..48)?.to_base58();
+            Some(json!({
+                "instruction": "CreateAccount",
+                "from": accounts.first(),
+                "to": accounts.get(1),
+                "lamports": lamports,
+                "space": space,
+                "owner": owner,
+            }))
+        }
+        2 => Some(json!({
+            "instruction": "Transfer",
+            "from": accounts.first(),
+            "to": accounts.get(1),
+            "lamports": lamports,
+        })),
+        _ => None,
+    }
+}
+
+/// SPL Token / Token-2022: instructions are stored as `token_instruction`
+struct TokenParser(&'static str);
+
+impl ProgramParser for TokenParser {
+    fn program_id(&self) -> &str {
+        self.0
+    }
+
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        vec![ctx.instruction_event("token_instruction", inst)]
+    }
+}
+
+/// Compute Budget: the instruction plus a decoded `compute_budget` event
+struct ComputeBudgetParser;
+
+impl ProgramParser for ComputeBudgetParser {
+    fn program_id(&self) -> &str {
+        COMPUTE_BUDGET_PROGRAM_ID
+    }
+
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        let mut events = vec![ctx.instruction_event("program_instruction", inst)];
+        if let Some(payload) = decode_compute_budget(inst) {
+            events.push(ctx.event("compute_budget", payload));
+        }
+        events
+    }
+}
+
+/// Address Lookup Table: the instruction plus `alt_create`, `alt_extend` or
+/// `alt_close` with the table address and the addresses it affects
+struct AddressLookupTableParser;
+
+impl ProgramParser for AddressLookupTableParser {
+    fn program_id(&self) -> &str {
+        ADDRESS_LOOKUP_TABLE_PROGRAM_ID
+    }
+
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        let mut events = vec![ctx.instruction_event("program_instruction", inst)];
+        let decoded = match inst.get("parsed") {
+            Some(parsed) => decode_parsed_lookup_table(parsed),
+            None => decode_lookup_table(inst, ctx.accounts),
+        };
+        if let Some((event_type, payload)) = decoded {
+            events.push(ctx.event(event_type, payload));
+        }
+        events
+    }
+}
+
+/// Decode a `jsonParsed` lookup table instruction
+fn decode_parsed_lookup_table(parsed: &Value) -> Option<(&'static str, Value)> {
+    let info = parsed.get("info")?;
+    let table = info.get("lookupTableAccount").cloned().unwrap_or(Value::Null);
+
+    match parsed.get("type")?.as_str()? {
+        "createLookupTable" => Some((
+            "alt_create",
+            json!({
+                "table": table,
+                "authority": info.get("lookupTableAuthority"),
+                "payer": info.get("payerAccount"),
+                "recent_slot": info.get("recentSlot"),
+            }),
+        )),
+        "extendLookupTable" => Some((
+            "alt_extend",
+            json!({
+                "table": table,
+                "authority": info.get("lookupTableAuthority"),
+                "new_addresses": info.get("newAddresses").cloned().unwrap_or_else(|| json!([])),
+            }),
+        )),
+        "closeLookupTable" => Some((
+            "alt_close",
+            json!({
+                "table": table,
+                "authority": info.get("lookupTableAuthority"),
+                "recipient": info.get("recipient"),
+            }),
+        )),
+        _ => None,
+    }
+}
+
+/// Decode a raw lookup table instruction: a u32 LE discriminant (0 create,
+/// 2 extend with a u64-length list of 32-byte addresses, 4 close) followed
+/// by bincode args. Accounts are table, authority, then payer/recipient.
+fn decode_lookup_table(instruction: &Value, accounts: &[String]) -> Option<(&'static str, Value)> {
+    let data = instruction
+        .get("data")
+        .and_then(|v| v.as_str())?
+        .from_base58()
+        .ok()?;
+    let discriminant = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let args = &data[4..];
+
+    match discriminant {
+        0 => {
+            let recent_slot = u64::from_le_bytes(args.get(..8)?.try_into().ok()?);
+            Some((
+                "alt_create",
+                json!({
+                    "table": accounts.first(),
+                    "authority": accounts.get(1),
+                    "payer": accounts.get(2),
+                    "recent_slot": recent_slot,
+                }),
+            ))
+        }
+        2 => {
+            let count = u64::from_le_bytes(args.get(..8)?.try_into().ok()?) as usize;
+            let end = count.checked_mul(32).and_then(|n| n.checked_add(8))?;
+            let new_addresses = args
+                .get(8..end)?
+                .chunks_exact(32)
+                .map(|key| key.to_base58())
+                .collect::<Vec<_>>();
+            Some((
+                "alt_extend",
+                json!({
+                    "table": accounts.first(),
+                    "authority": accounts.get(1),
+                    "new_addresses": new_addresses,
+                }),
+            ))
+        }
+        4 => Some((
+            "alt_close",
+            json!({
+                "table": accounts.first(),
+                "authority": accounts.get(1),
+                "recipient": accounts.get(2),
+            }),
+        )),
+        _ => None,
+    }
+}
+
+/// Decode SetComputeUnitLimit (2, u32 LE) and SetComputeUnitPrice (3, u64 LE)
+/// from the instruction's base58 `data`. Other variants return None.
+fn decode_compute_budget(instruction: &Value) -> Option<Value> {
+    let data = instruction
+        .get("data")
+        .and_then(|v| v.as_str())?
+        .from_base58()
+        .ok()?;
+
+    match data.split_first()? {
+        (2, args) if args.len() >= 4 => {
+            let units = u32::from_le_bytes(args[..4].try_into().ok()?);
+            Some(json!({
+                "instruction": "SetComputeUnitLimit",
+                "compute_unit_limit": units,
+            }))
+        }
+        (3, args) if args.len() >= 8 => {
+            let micro_lamports = u64::from_le_bytes(args[..8].try_into().ok()?);
+            Some(json!({
+                "instruction": "SetComputeUnitPrice",
+                "compute_unit_price_micro_lamports": micro_lamports,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// SPL Memo: the instruction plus a `memo` event with the UTF-8 text
+struct MemoParser(&'static str);
+
+impl ProgramParser for MemoParser {
+    fn program_id(&self) -> &str {
+        self.0
+    }
+
+    fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+        let mut events = vec![ctx.instruction_event("program_instruction", inst)];
+
+        // jsonParsed gives the memo text directly; json encoding has base58 data
+        let memo = inst
+            .get("parsed")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| {
+                let data = inst.get("data")?.as_str()?.from_base58().ok()?;
+                String::from_utf8(data).ok()
+            });
+
+        if let Some(memo) = memo {
+            events.push(ctx.event("memo", json!({ "memo": memo })));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(data: &[u8]) -> Value {
+        json!({ "programIdIndex": 0, "accounts": [], "data": data.to_base58() })
+    }
+
+    fn context(accounts: &[String]) -> ParseContext<'_> {
+        ParseContext {
+            slot: 100,
+            block_time: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            tx_signature: "sig1",
+            instruction_index: 0,
+            program_id: SYSTEM_PROGRAM_ID,
+            accounts,
+        }
+    }
+
+    #[test]
+    fn system_transfers_emit_a_lamports_transfer() {
+        let accounts = vec!["payer".to_string(), "receiver".to_string()];
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&1_500_000u64.to_le_bytes());
+
+        let events = default_registry().parse(&instruction(&data), &context(&accounts));
+        let types: Vec<&str> = events.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types, vec!["program_instruction", "lamports_transfer"]);
+        assert_eq!(
+            events[1].raw_payload,
+            json!({ "instruction": "Transfer", "from": "payer", "to": "receiver", "lamports": 1_500_000 })
+        );
+
+        let parsed = json!({
+            "program": "system",
+            "parsed": {
+                "type": "transfer",
+                "info": { "source": "payer", "destination": "receiver", "lamports": 1_500_000 },
+            },
+        });
+        let events = default_registry().parse(&parsed, &context(&accounts));
+        assert_eq!(events[1].event_type, "lamports_transfer");
+        assert_eq!(events[1].raw_payload["lamports"], 1_500_000);
+    }
+
+    #[test]
+    fn create_account_is_decoded_and_other_system_instructions_are_not() {
+        let accounts = vec!["payer".to_string(), "new".to_string()];
+        let owner = [7u8; 32];
+        let mut data = 0u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&2_039_280u64.to_le_bytes());
+        data.extend_from_slice(&165u64.to_le_bytes());
+        data.extend_from_slice(&owner);
+        assert_eq!(
+            decode_system(&instruction(&data), &accounts),
+            Some(json!({
+                "instruction": "CreateAccount",
+                "from": "payer",
+                "to": "new",
+                "lamports": 2_039_280,
+                "space": 165,
+                "owner": owner.to_base58(),
+            }))
+        );
+
+        // Assign (1) moves no lamports; a truncated transfer is ignored
+        let mut assign = 1u32.to_le_bytes().to_vec();
+        assign.extend_from_slice(&owner);
+        assert_eq!(decode_system(&instruction(&assign), &accounts), None);
+        assert_eq!(decode_system(&instruction(&[2, 0, 0, 0, 1]), &accounts), None);
+    }
+
+    #[test]
+    fn compute_budget_limit_and_price_are_decoded() {
+        let mut limit = vec![2];
+        limit.extend_from_slice(&1_400_000u32.to_le_bytes());
+        assert_eq!(
+            decode_compute_budget(&instruction(&limit)),
+            Some(json!({ "instruction": "SetComputeUnitLimit", "compute_unit_limit": 1_400_000 }))
+        );
+
+        let mut price = vec![3];
+        price.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            decode_compute_budget(&instruction(&price)),
+            Some(json!({ "instruction": "SetComputeUnitPrice", "compute_unit_price_micro_lamports": u64::MAX }))
+        );
+    }
+
+    #[test]
+    fn other_or_short_compute_budget_data_is_not_decoded() {
+        // RequestHeapFrame, and a limit missing its last byte
+        assert_eq!(decode_compute_budget(&instruction(&[1, 0, 0, 1, 0])), None);
+        assert_eq!(decode_compute_budget(&instruction(&[2, 0x40, 0x0d, 0x03])), None);
+    }
+
+    #[test]
+    fn memos_are_decoded_from_either_encoding_under_both_memo_programs() {
+        for program_id in [MEMO_PROGRAM_ID, MEMO_V1_PROGRAM_ID] {
+            let ctx = ParseContext { program_id, ..context(&[]) };
+            let events = default_registry().parse(&instruction("gm wagmi".as_bytes()), &ctx);
+            let types: Vec<&str> = events.iter().map(|e| e.event_type.as_str()).collect();
+            assert_eq!(types, vec!["program_instruction", "memo"]);
+            assert_eq!(events[1].raw_payload, json!({ "memo": "gm wagmi" }));
+
+            let parsed = json!({ "program": "spl-memo", "parsed": "gm wagmi" });
+            assert_eq!(default_registry().parse(&parsed, &ctx)[1].raw_payload, json!({ "memo": "gm wagmi" }));
+
+            // Data that isn't UTF-8 keeps only the instruction
+            assert_eq!(default_registry().parse(&instruction(&[0xff, 0xfe]), &ctx).len(), 1);
+        }
+    }
+
+    #[test]
+    fn programs_without_a_parser_get_a_generic_instruction_event() {
+        let accounts = vec!["payer".to_string(), "vault".to_string()];
+        let ctx = ParseContext { program_id: "Prog111", ..context(&accounts) };
+        let inst = instruction(&[1, 2, 3]);
+        let events = default_registry().parse(&inst, &ctx);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "program_instruction");
+        assert_eq!(events[0].program_id.as_deref(), Some("Prog111"));
+        assert_eq!(events[0].raw_payload, inst);
+        assert_eq!(events[0].accounts, accounts);
+    }
+
+    #[test]
+    fn registered_parsers_add_programs_and_replace_built_ins() {
+        struct Tagging(&'static str);
+        impl ProgramParser for Tagging {
+            fn program_id(&self) -> &str {
+                self.0
+            }
+
+            fn parse(&self, inst: &Value, ctx: &ParseContext) -> Vec<CanonicalEvent> {
+                vec![ctx.instruction_event("tagged", inst)]
+            }
+        }
+
+        let mut registry = ParserRegistry::with_defaults();
+        registry.register(Box::new(Tagging("Prog111")));
+        registry.register(Box::new(Tagging(SYSTEM_PROGRAM_ID)));
+
+        let mut transfer = 2u32.to_le_bytes().to_vec();
+        transfer.extend_from_slice(&1_500_000u64.to_le_bytes());
+        for program_id in ["Prog111", SYSTEM_PROGRAM_ID] {
+            let ctx = ParseContext { program_id, ..context(&[]) };
+            let types: Vec<String> = registry.parse(&instruction(&transfer), &ctx).into_iter().map(|e| e.event_type).collect();
+            assert_eq!(types, vec!["tagged"], "{}", program_id);
+        }
+        // Other built-ins are untouched
+        let ctx = ParseContext { program_id: MEMO_PROGRAM_ID, ..context(&[]) };
+        assert_eq!(registry.parse(&instruction(b"gm"), &ctx)[1].event_type, "memo");
+    }
+
+    #[test]
+    fn lookup_table_extends_with_an_overflowing_count_are_not_decoded() {
+        let accounts = vec!["table".to_string(), "authority".to_string()];
+        let address = [9u8; 32];
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&address);
+        let (name, payload) = decode_lookup_table(&instruction(&data), &accounts).unwrap();
+        assert_eq!(name, "alt_extend");
+        assert_eq!(payload["new_addresses"], json!([address.to_base58()]));
+
+        // A count whose byte length overflows, or runs past the data, is
+        // rejected rather than panicking
+        for count in [u64::MAX, u64::MAX / 16, u64::MAX / 32, 2] {
+            let mut data = 2u32.to_le_bytes().to_vec();
+            data.extend_from_slice(&count.to_le_bytes());
+            data.extend_from_slice(&address);
+            assert_eq!(decode_lookup_table(&instruction(&data), &accounts), None);
+        }
+    }
+}
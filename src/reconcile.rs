@@ -0,0 +1,275 @@
+use crate::config::Config;
+use crate::error::{ETLError, Result};
+use crate::events::{CanonicalEvent, EventIdFormat};
+use crate::leaders::LeaderSchedules;
+use crate::rpc::AlchemyRPCClient;
+use crate::warehouse::connect_postgres;
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+/// Identity columns of a stored event, as used to derive its `event_id`
+#[derive(Debug, Clone)]
+struct StoredEventKey {
+    event_id: String,
+    slot: u64,
+    tx_signature: String,
+    instruction_index: i32,
+    event_type: String,
+    first_account: Option<String>,
+}
+
+impl StoredEventKey {
+    /// The id, in `format`, that parsing gives this event today. Account
+    /// snapshots have no signature and are keyed by the account address.
+    fn derived_event_id(&self, format: EventIdFormat) -> String {
+        CanonicalEvent::generate_event_id_as(format, self.slot, self.id_key(), self.instruction_index, &self.event_type)
+    }
+
+    /// The id of this event's `occurrence`-th duplicate within the block
+    fn disambiguated_event_id(&self, format: EventIdFormat, occurrence: u32) -> String {
+        CanonicalEvent::disambiguated_event_id(
+            format,
+            self.slot,
+            self.id_key(),
+            self.instruction_index,
+            &self.event_type,
+            occurrence,
+        )
+    }
+
+    fn id_key(&self) -> &str {
+        match (&self.first_account, self.tx_signature.is_empty() && self.instruction_index == -1) {
+            (Some(pubkey), true) => pubkey.as_str(),
+            _ => self.tx_signature.as_str(),
+        }
+    }
+}
+
+/// Stored events of one slot whose `event_id` can't be re-derived from their
+/// columns. Ids that collided within the block were disambiguated, so the
+/// 2nd..nth occurrence of a derived id is accepted too.
+fn event_id_mismatches(rows: &[StoredEventKey], format: EventIdFormat) -> Vec<&StoredEventKey> {
+    let derived: Vec<String> = rows.iter().map(|row| row.derived_event_id(format)).collect();
+    let mut occurrences: HashMap<&str, u32> = HashMap::new();
+    for id in &derived {
+        *occurrences.entry(id.as_str()).or_insert(0) += 1;
+    }
+
+    rows.iter()
+        .zip(&derived)
+        .filter(|(row, id)| {
+            row.event_id != **id
+                && !(2..=occurrences[id.as_str()])
+                    .any(|occurrence| row.event_id == row.disambiguated_event_id(format, occurrence))
+        })
+        .map(|(row, _)| row)
+        .collect()
+}
+
+/// Re-derive `event_id` for every stored event in a sample of slots and
+/// report rows whose id no longer matches, i.e. drift in the id hash input
+/// between the version that wrote the data and this one. Postgres only;
+/// makes no RPC calls.
+pub async fn run_check_event_ids(config: Config, start_slot: u64, end_slot: u64, sample_rate: f64) -> Result<()> {
+    if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+        return Err(ETLError::Config("Sample rate must be in (0, 1]".to_string()));
+    }
+    if config.warehouse.warehouse_type != "postgres" {
+        return Err(ETLError::Config("Event id checks need a Postgres warehouse".to_string()));
+    }
+    let format: EventIdFormat = config.etl.event_id_format.parse()?;
+    let stride = std::cmp::max(1, (1.0 / sample_rate).round() as usize);
+    let pool = connect_postgres(&config.warehouse).await?;
+
+    info!(
+        "Checking event ids in slots {} to {} (every {} slot(s))",
+        start_slot, end_slot, stride
+    );
+
+    let mut checked = 0;
+    let mut mismatched = 0;
+    for slot in (start_slot..end_slot).step_by(stride) {
+        let rows: Vec<(String, String, i32, String, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT event_id, tx_signature, instruction_index, event_type, accounts[1]
+            FROM fact_transactions
+            WHERE slot = $1
+            "#
+        )
+        .bind(slot as i64)
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| ETLError::Database(format!("Failed to load events for slot {}: {}", slot, e)))?;
+
+        let keys: Vec<StoredEventKey> = rows
+            .into_iter()
+            .map(|(event_id, tx_signature, instruction_index, event_type, first_account)| StoredEventKey {
+                event_id,
+                slot,
+                tx_signature,
+                instruction_index,
+                event_type,
+                first_account,
+            })
+            .collect();
+        checked += keys.len();
+
+        for row in event_id_mismatches(&keys, format) {
+            warn!(
+                "Slot {}: event {} ({} {} #{}) does not match its derived id",
+                slot, row.event_id, row.event_type, row.tx_signature, row.instruction_index
+            );
+            mismatched += 1;
+        }
+    }
+
+    if mismatched == 0 {
+        info!("Event id check passed ({} events checked)", checked);
+        Ok(())
+    } else {
+        Err(ETLError::Generic(anyhow::anyhow!(
+            "{} of {} stored event ids do not match generate_event_id; the id hash input has drifted",
+            mismatched, checked
+        )))
+    }
+}
+
+/// Compare on-chain transaction counts against stored `transaction` events
+/// for a sample of slots in `[start_slot, end_slot)`.
+///
+/// `sample_rate` is the fraction of slots checked; slots are sampled at a
+/// fixed stride so repeated runs check the same slots. A slot with no block
+/// counts as skipped only if `getBlocks` omits it too (its scheduled leader
+/// is logged); a block `getBlocks` lists but `getBlock` doesn't return is a gap.
+pub async fn run_reconcile(config: Config, start_slot: u64, end_slot: u64, sample_rate: f64) -> Result<()> {
+    if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+        return Err(ETLError::Config("Sample rate must be in (0, 1]".to_string()));
+    }
+    let stride = std::cmp::max(1, (1.0 / sample_rate).round() as usize);
+
+    info!(
+        "Reconciling slots {} to {} (every {} slot(s))",
+        start_slot, end_slot, stride
+    );
+
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    let warehouse = crate::warehouse::create_warehouse(config.warehouse.clone())?;
+    warehouse.connect().await?;
+
+    let epoch_schedule = match config.etl.epoch_schedule {
+        Some(schedule) => schedule,
+        None => rpc_client.get_epoch_schedule().await?,
+    };
+    let leader_schedules = LeaderSchedules::new(epoch_schedule);
+
+    let mut checked = 0;
+    let mut skipped = 0;
+    let mut mismatches = Vec::new();
+
+    for slot in (start_slot..end_slot).step_by(stride) {
+        let stored = warehouse.count_events(slot, "transaction").await?;
+        checked += 1;
+
+        let on_chain = match rpc_client.get_block_signatures(slot).await? {
+            Some(signatures) => signatures.len() as u64,
+            None => {
+                if rpc_client.get_blocks(slot, slot).await?.contains(&slot) {
+                    warn!("Slot {}: listed by getBlocks but getBlock returned no block", slot);
+                    mismatches.push(slot);
+                    continue;
+                }
+                // Every slot has a scheduled leader, so it only names who skipped it
+                let leader = leader_schedules.leader_for_slot(&rpc_client, slot).await.ok().flatten();
+                debug!("Slot {} skipped by its leader {}", slot, leader.as_deref().unwrap_or("(unknown)"));
+                skipped += 1;
+                0
+            }
+        };
+
+        if on_chain != stored {
+            warn!("Slot {}: on-chain {} transactions, stored {}", slot, on_chain, stored);
+            mismatches.push(slot);
+        }
+    }
+
+    if mismatches.is_empty() {
+        info!(
+            "Reconciliation passed ({} slots checked, {} skipped by their leader)",
+            checked, skipped
+        );
+        Ok(())
+    } else {
+        Err(ETLError::Generic(anyhow::anyhow!(
+            "Reconciliation found {} mismatched slot(s) out of {} checked ({} skipped by their leader): {:?}",
+            mismatches.len(),
+            checked,
+            skipped,
+            mismatches
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epoch::EpochSchedule;
+    use crate::test_support::{config, MockRpc, Reply};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn only_slots_missing_from_get_blocks_count_as_skipped() {
+        let rpc = MockRpc::start(|method, params| match (method, params[0].as_u64()) {
+            ("getBlock", Some(100)) => Reply::Result(json!({ "blockhash": "hash", "signatures": [] })),
+            // 101 was skipped by its leader; 102 has a block this node didn't return
+            ("getBlocks", Some(102)) => Reply::Result(json!([102])),
+            ("getBlocks", _) => Reply::Result(json!([])),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = config();
+        config.alchemy = rpc.alchemy_config();
+        config.warehouse.warehouse_type = "stdout".to_string();
+        config.etl.epoch_schedule = Some(EpochSchedule::mainnet());
+
+        let err = run_reconcile(config, 100, 103, 1.0).await.unwrap_err().to_string();
+        assert!(err.contains("1 mismatched slot(s) out of 3 checked (1 skipped by their leader): [102]"), "{}", err);
+    }
+
+    #[test]
+    fn event_ids_that_no_longer_derive_from_their_columns_are_reported() {
+        let format = EventIdFormat::Base58_128;
+        let key = |tx_signature: &str, instruction_index: i32, event_type: &str, first_account: Option<&str>| {
+            let mut key = StoredEventKey {
+                event_id: String::new(),
+                slot: 100,
+                tx_signature: tx_signature.to_string(),
+                instruction_index,
+                event_type: event_type.to_string(),
+                first_account: first_account.map(str::to_string),
+            };
+            key.event_id = key.derived_event_id(format);
+            key
+        };
+
+        let transaction = key("sig1", -1, "transaction", Some("payer"));
+        let instruction = key("sig1", 0, "program_instruction", Some("payer"));
+        // A signature repeated in the block: its second event was disambiguated
+        let mut repeated = key("sig1", 0, "program_instruction", Some("payer"));
+        repeated.event_id = repeated.disambiguated_event_id(format, 2);
+        // Account snapshots are keyed by the account address
+        let snapshot = key("", -1, "token_account", Some("Acct111"));
+        assert_ne!(snapshot.event_id, key("", -1, "token_account", None).event_id);
+
+        let rows = vec![transaction.clone(), instruction, repeated, snapshot];
+        assert!(event_id_mismatches(&rows, format).is_empty());
+
+        // Drift in any hashed column shows up as a mismatch
+        let mut drifted = transaction.clone();
+        drifted.instruction_index = 0;
+        let mut renamed = transaction;
+        renamed.event_type = "tx".to_string();
+        let rows = vec![rows[1].clone(), drifted, renamed];
+        let mismatched: Vec<_> = event_id_mismatches(&rows, format).into_iter().map(|row| row.event_type.as_str()).collect();
+        assert_eq!(mismatched, vec!["transaction", "tx"]);
+    }
+}
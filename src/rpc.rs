@@ -1,11 +1,42 @@
 use crate::config::AlchemyConfig;
+use crate::epoch::EpochSchedule;
 use crate::error::{ETLError, Result};
+use crate::parsers::RawBlock;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use futures::stream::{self, BoxStream, StreamExt};
 use serde_json::{json, Value};
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use governor::{Quota, RateLimiter, state::direct::NotKeyed, state::InMemoryState, clock::DefaultClock, middleware::NoOpMiddleware};
 use std::num::NonZeroU32;
+use uuid::Uuid;
+
+/// Cheap control-plane calls (checkpoint lag, health) that get the reserved
+/// rate-limit lane, so block fetches can't starve them
+const CONTROL_METHODS: &[&str] = &["getSlot", "getBlockHeight"];
+
+/// Widest range one `getBlocks` call may cover
+const MAX_GET_BLOCKS_RANGE: u64 = 500_000;
+
+// getBlock error codes from the Solana JSON-RPC server. Skipped or not yet
+// available slots are treated like a null block; cleaned-up slots and ones
+// missing after a snapshot jump will never be served by this node.
+const BLOCK_CLEANED_UP: i64 = -32001;
+const BLOCK_NOT_AVAILABLE: i64 = -32004;
+const SLOT_SKIPPED_OR_MISSING: i64 = -32007;
+const LONG_TERM_STORAGE_SLOT_SKIPPED: i64 = -32009;
+
+/// JSON-RPC `id` source, shared by every client so ids never repeat in a process
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Next JSON-RPC request id
+fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RPCRequest {
@@ -16,10 +47,10 @@ pub struct RPCRequest {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RPCResponse {
+pub struct RPCResponse<T = Value> {
     jsonrpc: String,
     id: u64,
-    result: Option<Value>,
+    result: Option<T>,
     error: Option<RPCError>,
 }
 
@@ -30,110 +61,525 @@ pub struct RPCError {
     data: Option<Value>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CircuitState {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+/// Circuit breaker tracking the recent failure rate of an endpoint
+pub struct CircuitBreaker {
+    failure_threshold: f64,
+    window: usize,
+    cooldown: Duration,
+    inner: Mutex<CircuitInner>,
+}
+
+struct CircuitInner {
+    state: CircuitState,
+    outcomes: VecDeque<bool>, // true = failure
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: f64, window: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            window: std::cmp::max(1, window) as usize,
+            cooldown,
+            inner: Mutex::new(CircuitInner {
+                state: CircuitState::Closed,
+                outcomes: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Check whether a call may proceed; moves Open -> HalfOpen once the
+    /// cooldown elapses, letting exactly one probe call through. Its outcome
+    /// closes or re-opens the circuit; a probe that never reports back (e.g.
+    /// cancelled) is replaced after another cooldown.
+    pub fn allow_request(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::HalfOpen { probe_started } if now < probe_started + self.cooldown => false,
+            CircuitState::Open { until } if now < until => false,
+            CircuitState::HalfOpen { .. } | CircuitState::Open { .. } => {
+                inner.state = CircuitState::HalfOpen { probe_started: now };
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(inner.state, CircuitState::HalfOpen { .. }) {
+            tracing::info!("RPC circuit closed after successful probe");
+            inner.state = CircuitState::Closed;
+            inner.outcomes.clear();
+        }
+        Self::push_outcome(&mut inner, self.window, false);
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if matches!(inner.state, CircuitState::HalfOpen { .. }) {
+            self.open(&mut inner);
+            return;
+        }
+
+        Self::push_outcome(&mut inner, self.window, true);
+        if inner.outcomes.len() >= self.window {
+            let failures = inner.outcomes.iter().filter(|f| **f).count();
+            if failures as f64 / inner.outcomes.len() as f64 >= self.failure_threshold {
+                self.open(&mut inner);
+            }
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.inner.lock().unwrap().state, CircuitState::Open { .. })
+    }
+
+    fn open(&self, inner: &mut CircuitInner) {
+        tracing::warn!("RPC circuit opened for {:?}", self.cooldown);
+        inner.state = CircuitState::Open {
+            until: Instant::now() + self.cooldown,
+        };
+        inner.outcomes.clear();
+    }
+
+    fn push_outcome(inner: &mut CircuitInner, window: usize, failed: bool) {
+        inner.outcomes.push_back(failed);
+        while inner.outcomes.len() > window {
+            inner.outcomes.pop_front();
+        }
+    }
+}
+
+/// Build the rate limiter quota from config.
+///
+/// A per-minute quota takes precedence over per-second. Without an explicit
+/// burst, governor lets the full per-period count through back-to-back;
+/// `rate_limit_burst` caps that while keeping the same long-run rate. The
+/// control lane's rate is reserved out of this quota, so both lanes together
+/// stay within the configured limit.
+fn build_quota(config: &AlchemyConfig) -> Quota {
+    let one = NonZeroU32::new(1).unwrap();
+    let control = config.control_rate_limit_per_second;
+    let quota = match (config.rate_limit_per_minute, config.rate_limit_per_second) {
+        (Some(per_minute), _) => {
+            let rate = per_minute.saturating_sub(control.saturating_mul(60));
+            Quota::per_minute(NonZeroU32::new(rate).unwrap_or(one))
+        }
+        (None, per_second) => {
+            let rate = per_second.unwrap_or(1).saturating_sub(control);
+            Quota::per_second(NonZeroU32::new(rate).unwrap_or(one))
+        }
+    };
+
+    match config.rate_limit_burst.and_then(NonZeroU32::new) {
+        Some(burst) => quota.allow_burst(burst),
+        None => quota,
+    }
+}
+
+type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware>;
+
+/// The rate limiters of one endpoint, shared by every client calling it
+pub struct RateLimits {
+    /// Everything else, at the configured rate minus the control lane's
+    main: DirectRateLimiter,
+    /// Reserved lane for `CONTROL_METHODS` (ALCHEMY_CONTROL_RATE_LIMIT)
+    control: Option<DirectRateLimiter>,
+}
+
+impl RateLimits {
+    pub fn new(config: &AlchemyConfig) -> Self {
+        Self {
+            main: RateLimiter::direct(build_quota(config)),
+            control: NonZeroU32::new(config.control_rate_limit_per_second)
+                .map(|rate| RateLimiter::direct(Quota::per_second(rate))),
+        }
+    }
+}
+
+/// Rate limits keyed by endpoint URL, so clients that each have their own
+/// connections and circuit breaker (`ALCHEMY_RATE_LIMIT_SCOPE=per_worker`)
+/// still stay within the quota of the endpoint they share
+#[derive(Default)]
+pub struct EndpointRateLimits {
+    endpoints: Mutex<HashMap<String, Arc<RateLimits>>>,
+    /// HTTP requests sent by every client this made, so they all draw on
+    /// one `max_requests_per_run` budget
+    requests_sent: Arc<AtomicU64>,
+}
+
+impl EndpointRateLimits {
+    /// A client for `config.rpc_url`, limited together with every other
+    /// client this made for the same URL. All of them share one request budget.
+    pub fn client(&self, config: AlchemyConfig) -> AlchemyRPCClient {
+        let limits = self
+            .endpoints
+            .lock()
+            .unwrap()
+            .entry(config.rpc_url.clone())
+            .or_insert_with(|| Arc::new(RateLimits::new(&config)))
+            .clone();
+        AlchemyRPCClient::with_rate_limits(config, limits).with_requests_sent(self.requests_sent.clone())
+    }
+}
+
 pub struct AlchemyRPCClient {
     config: AlchemyConfig,
     client: reqwest::Client,
-    rate_limiter: RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware>,
+    rate_limits: Arc<RateLimits>,
+    circuit_breaker: CircuitBreaker,
+    epoch_schedule: OnceLock<EpochSchedule>,
+    /// HTTP requests counted against `max_requests_per_run`, possibly
+    /// shared with other clients
+    requests_sent: Arc<AtomicU64>,
 }
 
 impl AlchemyRPCClient {
     pub fn new(config: AlchemyConfig) -> Self {
-        let client = reqwest::Client::builder()
+        let rate_limits = Arc::new(RateLimits::new(&config));
+        Self::with_rate_limits(config, rate_limits)
+    }
+
+    /// A client drawing on `rate_limits`, which other clients may share
+    pub fn with_rate_limits(config: AlchemyConfig, rate_limits: Arc<RateLimits>) -> Self {
+        if config.accept_invalid_certs {
+            tracing::warn!(
+                "TLS certificate validation is DISABLED for {} (ALCHEMY_INSECURE_TLS) - never use this in production",
+                config.rpc_url
+            );
+        }
+        let mut builder = reqwest::Client::builder()
             .timeout(Duration::from_secs(config.timeout_seconds))
             .no_proxy() // Disable system proxy detection to avoid system-configuration issues
-            .danger_accept_invalid_certs(false) // Use proper cert validation
-            .build()
-            .expect("Failed to create HTTP client");
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_seconds))
+            .tcp_keepalive(Duration::from_secs(config.tcp_keepalive_seconds));
+
+        // HTTP/2 is negotiated via ALPN; http1_only opts out for endpoints that misbehave
+        builder = if config.http2 {
+            builder.http2_adaptive_window(true)
+        } else {
+            builder.http1_only()
+        };
+
+        let client = builder.build().expect("Failed to create HTTP client");
 
-        let rate_limit = std::cmp::max(1, config.rate_limit_per_second);
-        let quota = Quota::per_second(
-            NonZeroU32::new(rate_limit).unwrap_or(NonZeroU32::new(1).unwrap())
+        let circuit_breaker = CircuitBreaker::new(
+            config.circuit_failure_threshold,
+            config.circuit_window,
+            Duration::from_secs(config.circuit_cooldown_seconds),
         );
-        let rate_limiter: RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware> = RateLimiter::direct(quota);
 
         Self {
             config,
             client,
-            rate_limiter,
+            rate_limits,
+            circuit_breaker,
+            epoch_schedule: OnceLock::new(),
+            requests_sent: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// Count requests on `requests_sent`, which other clients may share
+    pub fn with_requests_sent(mut self, requests_sent: Arc<AtomicU64>) -> Self {
+        self.requests_sent = requests_sent;
+        self
+    }
+
     async fn rpc_call(&self, method: &str, params: Value) -> Result<Value> {
-        // Rate limit
-        self.rate_limiter.until_ready().await;
+        Ok(self.rpc_call_as(method, params).await?.unwrap_or(Value::Null))
+    }
+
+    /// `rpc_call` decoding the result straight into `T`; None for a null result
+    async fn rpc_call_as<T: DeserializeOwned + Send>(&self, method: &str, params: Value) -> Result<Option<T>> {
+        // Fail fast while the endpoint is considered down
+        if !self.circuit_breaker.allow_request() {
+            return Err(ETLError::RPC("circuit open".to_string()));
+        }
 
+        // Rate limit; control-plane calls skip the queue of block fetches
+        // when a reserved lane is configured
+        match &self.rate_limits.control {
+            Some(control) if CONTROL_METHODS.contains(&method) => control.until_ready().await,
+            _ => self.rate_limits.main.until_ready().await,
+        }
+
+        // The deadline covers every attempt and backoff sleep, but not the
+        // rate limiter wait above
+        if self.config.call_deadline_seconds == 0 {
+            return self.send_with_retries(method, params).await;
+        }
+        let deadline = Duration::from_secs(self.config.call_deadline_seconds);
+        match tokio::time::timeout(deadline, self.send_with_retries(method, params)).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.circuit_breaker.record_failure();
+                Err(ETLError::RPC(format!("{} deadline exceeded after {:?}", method, deadline)))
+            }
+        }
+    }
+
+    /// Send one JSON-RPC call, retrying rate-limit and server errors with
+    /// exponential backoff capped at `max_backoff_seconds`
+    async fn send_with_retries<T: DeserializeOwned + Send>(&self, method: &str, params: Value) -> Result<Option<T>> {
         let request = RPCRequest {
             jsonrpc: "2.0".to_string(),
-            id: 1,
+            id: next_request_id(),
             method: method.to_string(),
             params,
         };
+        // Sent as X-Request-Id so a failing call can be traced in provider logs
+        let request_id = Uuid::new_v4().to_string();
+        tracing::debug!("RPC {} (id {}, request {})", method, request.id, request_id);
 
         let mut retries = 0;
         loop {
-            let response = self
+            self.spend_request()?;
+            let response = match self
                 .client
                 .post(&self.config.rpc_url)
+                .header("X-Request-Id", &request_id)
                 .json(&request)
                 .send()
-                .await?;
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    self.circuit_breaker.record_failure();
+                    return Err(ETLError::RPC(format!(
+                        "{} request failed (request {}): {}",
+                        method, request_id, e
+                    )));
+                }
+            };
 
-            let rpc_response: RPCResponse = response.json().await?;
+            let rpc_response: RPCResponse<T> = match response.json().await {
+                Ok(rpc_response) => rpc_response,
+                Err(e) => {
+                    self.circuit_breaker.record_failure();
+                    return Err(ETLError::RPC(format!(
+                        "{} returned an invalid response (request {}): {}",
+                        method, request_id, e
+                    )));
+                }
+            };
 
             if let Some(error) = rpc_response.error {
                 // Rate limit or server error - retry
-                if error.code == 429 || (error.code >= 500 && error.code < 600) {
-                    if retries < self.config.max_retries {
-                        let backoff = Duration::from_secs(2_u64.pow(retries));
-                        tracing::warn!(
-                            "RPC error {}, retrying in {:?} (attempt {}/{})",
-                            error.message,
-                            backoff,
-                            retries + 1,
-                            self.config.max_retries
-                        );
-                        sleep(backoff).await;
-                        retries += 1;
-                        continue;
-                    }
+                let retryable = error.code == 429 || (error.code >= 500 && error.code < 600);
+                if retryable && retries < self.config.max_retries {
+                    let backoff = Duration::from_secs(
+                        2_u64.saturating_pow(retries).min(self.config.max_backoff_seconds),
+                    );
+                    tracing::warn!(
+                        "RPC error {} on {} (request {}), retrying in {:?} (attempt {}/{})",
+                        error.message,
+                        method,
+                        request_id,
+                        backoff,
+                        retries + 1,
+                        self.config.max_retries
+                    );
+                    sleep(backoff).await;
+                    retries += 1;
+                    continue;
+                }
+
+                // Only endpoint-level failures count against the circuit
+                if retryable {
+                    self.circuit_breaker.record_failure();
+                } else {
+                    self.circuit_breaker.record_success();
                 }
-                return Err(ETLError::RPC(format!(
-                    "RPC error {}: {}",
-                    error.code, error.message
-                )));
+                return Err(ETLError::RPCCode {
+                    code: error.code,
+                    message: format!("{} (request {}): {}", method, request_id, error.message),
+                });
             }
 
-            return Ok(rpc_response.result.unwrap_or(Value::Null));
+            self.circuit_breaker.record_success();
+            return Ok(rpc_response.result);
         }
     }
 
+    /// Count one HTTP request against `max_requests_per_run`, logging the
+    /// remaining budget every 10% of it
+    fn spend_request(&self) -> Result<()> {
+        let budget = self.config.max_requests_per_run;
+        let sent = self.requests_sent.fetch_add(1, Ordering::Relaxed) + 1;
+        if budget == 0 {
+            return Ok(());
+        }
+        if sent > budget {
+            return Err(ETLError::RequestBudgetExhausted(budget));
+        }
+        if sent.is_multiple_of((budget / 10).max(1)) {
+            tracing::info!("RPC request budget: {} of {} requests left", budget - sent, budget);
+        }
+        Ok(())
+    }
+
+    /// Commitment level blocks and slots are fetched at
+    pub fn commitment(&self) -> &str {
+        &self.config.commitment
+    }
+
     pub async fn get_slot(&self) -> Result<u64> {
+        self.get_slot_with_commitment(&self.config.commitment).await
+    }
+
+    /// Current slot at an explicit commitment, e.g. "finalized" regardless
+    /// of the configured one
+    pub async fn get_slot_with_commitment(&self, commitment: &str) -> Result<u64> {
+        let result = self
+            .rpc_call("getSlot", json!([{"commitment": commitment}]))
+            .await?;
+        result.as_u64().ok_or_else(|| ETLError::RPC("Invalid slot response".to_string()))
+    }
+
+    /// List the slots in `[start_slot, end_slot]` (inclusive) that produced a
+    /// block (`getBlocks`). The RPC caps a call at 500,000 slots, so longer
+    /// ranges are listed one piece at a time.
+    pub async fn get_blocks(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>> {
+        let mut slots = Vec::new();
+        let mut piece_start = start_slot;
+        while piece_start <= end_slot {
+            let piece_end = end_slot.min(piece_start.saturating_add(MAX_GET_BLOCKS_RANGE - 1));
+            let result = self
+                .rpc_call("getBlocks", json!([piece_start, piece_end, {"commitment": self.config.commitment}]))
+                .await?;
+            for v in result
+                .as_array()
+                .ok_or_else(|| ETLError::RPC("Invalid getBlocks response".to_string()))?
+            {
+                slots.push(v.as_u64().ok_or_else(|| ETLError::RPC("Invalid slot in getBlocks response".to_string()))?);
+            }
+            if piece_end == u64::MAX {
+                break;
+            }
+            piece_start = piece_end + 1;
+        }
+        Ok(slots)
+    }
+
+    /// Fetch the leader schedule for the epoch containing `slot`
+    /// (`getLeaderSchedule`): a map of leader identity to slot indices
+    /// relative to the epoch's first slot. Null if the epoch isn't known yet.
+    pub async fn get_leader_schedule(&self, slot: u64) -> Result<Value> {
+        self.rpc_call("getLeaderSchedule", json!([slot, {"commitment": "confirmed"}])).await
+    }
+
+    /// Fetch current and delinquent vote accounts (`getVoteAccounts`)
+    pub async fn get_vote_accounts(&self) -> Result<Value> {
+        self.rpc_call("getVoteAccounts", json!([{"commitment": "confirmed"}])).await
+    }
+
+    /// Fetch a mint's total supply (`getTokenSupply`). Returns the `value`
+    /// object: `amount`, `decimals`, `uiAmountString`.
+    pub async fn get_token_supply(&self, mint: &str) -> Result<Value> {
         let result = self
-            .rpc_call("getSlot", json!([{"commitment": "confirmed"}]))
+            .rpc_call("getTokenSupply", json!([mint, {"commitment": "confirmed"}]))
             .await?;
-        Ok(result.as_u64().ok_or_else(|| ETLError::RPC("Invalid slot response".to_string()))?)
+        result
+            .get("value")
+            .cloned()
+            .ok_or_else(|| ETLError::RPC(format!("Missing value in token supply response for {}", mint)))
     }
 
     pub async fn get_block(&self, slot: u64, encoding: Option<&str>) -> Result<Option<Value>> {
-        let encoding = encoding.unwrap_or("jsonParsed");
+        self.get_block_with_rewards(slot, encoding, false).await
+    }
+
+    /// Fetch a block, optionally including its `rewards` array
+    pub async fn get_block_with_rewards(
+        &self,
+        slot: u64,
+        encoding: Option<&str>,
+        rewards: bool,
+    ) -> Result<Option<Value>> {
+        // Null means slot doesn't exist
+        self.fetch_block(slot, encoding.unwrap_or("jsonParsed"), rewards).await
+    }
+
+    /// Fetch a jsonParsed block as a `RawBlock` for the ingestion fast path,
+    /// without building a `Value` tree of the whole block
+    pub async fn get_raw_block(&self, slot: u64, rewards: bool) -> Result<Option<RawBlock>> {
+        self.fetch_block(slot, "jsonParsed", rewards).await
+    }
+
+    async fn fetch_block<T: DeserializeOwned + Send>(&self, slot: u64, encoding: &str, rewards: bool) -> Result<Option<T>> {
         let params = json!([
             slot,
             {
                 "encoding": encoding,
                 "transactionDetails": "full",
+                "rewards": rewards,
+                "maxSupportedTransactionVersion": 0,
+                "commitment": self.config.commitment,
+            }
+        ]);
+
+        match self.rpc_call_as("getBlock", params).await {
+            Err(ETLError::RPCCode { code: BLOCK_NOT_AVAILABLE | LONG_TERM_STORAGE_SLOT_SKIPPED, .. }) => Ok(None),
+            Err(ETLError::RPCCode { code: BLOCK_CLEANED_UP | SLOT_SKIPPED_OR_MISSING, message }) => {
+                tracing::warn!("Slot {} is gone from the RPC node: {}", slot, message);
+                Err(ETLError::SlotPurged(slot))
+            }
+            result => result,
+        }
+    }
+
+    /// Fetch the cluster epoch schedule. The schedule never changes, so the
+    /// first successful response is cached for the life of the client.
+    pub async fn get_epoch_schedule(&self) -> Result<EpochSchedule> {
+        if let Some(schedule) = self.epoch_schedule.get() {
+            return Ok(*schedule);
+        }
+
+        let result = self.rpc_call("getEpochSchedule", json!([])).await?;
+        let schedule: EpochSchedule = serde_json::from_value(result)?;
+        Ok(*self.epoch_schedule.get_or_init(|| schedule))
+    }
+
+    /// Fetch only the transaction signatures of a block (`transactionDetails: "signatures"`).
+    /// Much lighter than a full block when only counts or coverage are needed.
+    pub async fn get_block_signatures(&self, slot: u64) -> Result<Option<Vec<String>>> {
+        let params = json!([
+            slot,
+            {
+                "encoding": "json",
+                "transactionDetails": "signatures",
                 "rewards": false,
                 "maxSupportedTransactionVersion": 0,
+                "commitment": self.config.commitment,
             }
         ]);
 
         let result = self.rpc_call("getBlock", params).await?;
 
-        // Null means slot doesn't exist
         if result.is_null() {
             return Ok(None);
         }
 
-        Ok(Some(result))
+        let signatures = result
+            .get("signatures")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| ETLError::RPC("Missing signatures in block response".to_string()))?
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|s| s.to_string())
+            .collect();
+
+        Ok(Some(signatures))
     }
 
     pub async fn get_transaction(
@@ -186,30 +632,389 @@ impl AlchemyRPCClient {
         }
     }
 
+    /// All accounts owned by `program_id`, in one response. For large
+    /// programs prefer `get_program_accounts_stream`.
+    ///
+    /// `data_slice` is `(offset, length)`: only that byte range of each
+    /// account's data is returned.
     pub async fn get_program_accounts(
         &self,
         program_id: &str,
         encoding: Option<&str>,
         filters: Option<Value>,
+        data_slice: Option<(usize, usize)>,
     ) -> Result<Vec<Value>> {
-        let encoding = encoding.unwrap_or("jsonParsed");
-        let mut params_obj = json!({"encoding": encoding});
+        let mut accounts = Vec::new();
+        let mut pages = self.get_program_accounts_stream(program_id, encoding, filters, data_slice);
+        while let Some(page) = pages.next().await {
+            accounts.extend(page?);
+        }
+        Ok(accounts)
+    }
+
+    /// Accounts owned by `program_id`, one page at a time. Endpoints that
+    /// paginate (Alchemy's `pageKey`) are followed page by page so only one
+    /// page is held in memory.
+    ///
+    /// Other endpoints return everything as a single page: the whole
+    /// response body and its parsed accounts are in memory at once, a few
+    /// times the response size, which for the Token program is several GB.
+    /// Narrow those requests with `filters` (e.g. `dataSize`) or `data_slice`.
+    pub fn get_program_accounts_stream<'a>(
+        &'a self,
+        program_id: &'a str,
+        encoding: Option<&'a str>,
+        filters: Option<Value>,
+        data_slice: Option<(usize, usize)>,
+    ) -> BoxStream<'a, Result<Vec<Value>>> {
+        let mut params_obj = json!({"encoding": encoding.unwrap_or("jsonParsed")});
         if let Some(filters) = filters {
             params_obj["filters"] = filters;
         }
-
-        let params = json!([program_id, params_obj]);
-        let result = self.rpc_call("getProgramAccounts", params).await?;
-
-        match result.as_array() {
-            Some(arr) => Ok(arr.clone()),
-            None => Ok(vec![]),
+        if let Some((offset, length)) = data_slice {
+            params_obj["dataSlice"] = json!({"offset": offset, "length": length});
         }
+
+        // State: the next page key, or None once the last page was returned
+        stream::try_unfold(Some(None::<String>), move |page_key| {
+            let mut params_obj = params_obj.clone();
+            async move {
+                let Some(page_key) = page_key else {
+                    return Ok(None);
+                };
+                if let Some(key) = &page_key {
+                    params_obj["pageKey"] = json!(key);
+                }
+                let result = self.rpc_call("getProgramAccounts", json!([program_id, params_obj])).await?;
+                let (accounts, next_key) = split_program_accounts_page(result);
+                Ok(Some((accounts, next_key.map(Some))))
+            }
+        })
+        .boxed()
     }
 
     pub async fn get_block_height(&self) -> Result<u64> {
         let result = self.rpc_call("getBlockHeight", json!([])).await?;
-        Ok(result.as_u64().ok_or_else(|| ETLError::RPC("Invalid block height response".to_string()))?)
+        result.as_u64().ok_or_else(|| ETLError::RPC("Invalid block height response".to_string()))
     }
 }
 
+/// Accounts and next page key of a `getProgramAccounts` response: a plain
+/// array, or a paginated `{"programAccounts": [...], "pageKey": ...}` object
+fn split_program_accounts_page(result: Value) -> (Vec<Value>, Option<String>) {
+    match result {
+        Value::Array(accounts) => (accounts, None),
+        Value::Object(mut page) => {
+            let accounts = match page.remove("programAccounts").or_else(|| page.remove("value")) {
+                Some(Value::Array(accounts)) => accounts,
+                _ => Vec::new(),
+            };
+            let next_key = page
+                .get("pageKey")
+                .and_then(|v| v.as_str())
+                .filter(|key| !key.is_empty())
+                .map(|key| key.to_string());
+            (accounts, next_key)
+        }
+        _ => (Vec::new(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_support::{MockRpc, Reply};
+    use std::collections::HashSet;
+
+    #[test]
+    fn circuit_opens_on_failure_ratio_and_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(0.5, 4, Duration::from_millis(20));
+
+        // Under the window size the ratio doesn't apply yet
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        assert!(!breaker.is_open());
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(!breaker.allow_request());
+
+        // After the cooldown exactly one probe is allowed
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow_request());
+        assert!(!breaker.allow_request());
+        assert!(!breaker.allow_request());
+
+        // A failed probe re-opens the circuit
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(!breaker.allow_request());
+
+        // A successful probe closes it and lets everyone through again
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow_request());
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        assert!(breaker.allow_request());
+        assert!(breaker.allow_request());
+    }
+
+    #[test]
+    fn lost_probe_is_replaced_after_a_cooldown() {
+        let breaker = CircuitBreaker::new(1.0, 1, Duration::from_millis(20));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow_request());
+
+        // The probe never reports back
+        assert!(!breaker.allow_request());
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow_request());
+    }
+
+    #[tokio::test]
+    async fn block_signatures_request_only_signatures() {
+        let rpc = MockRpc::start(|_, params| match params[0].as_u64() {
+            Some(100) => Reply::Result(json!({ "blockhash": "hash", "signatures": ["sigA", "sigB"] })),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let client = AlchemyRPCClient::new(rpc.alchemy_config());
+
+        assert_eq!(
+            client.get_block_signatures(100).await.unwrap(),
+            Some(vec!["sigA".to_string(), "sigB".to_string()])
+        );
+        assert_eq!(client.get_block_signatures(101).await.unwrap(), None);
+
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls[0].method, "getBlock");
+        assert_eq!(calls[0].params[1]["transactionDetails"], "signatures");
+        assert_eq!(calls[0].params[1]["rewards"], false);
+    }
+
+    #[tokio::test]
+    async fn long_get_blocks_ranges_are_split_into_500k_pieces() {
+        let rpc = MockRpc::start(|_, params| Reply::Result(json!([params[0], params[1]]))).await;
+        let client = AlchemyRPCClient::new(rpc.alchemy_config());
+
+        let slots = client.get_blocks(100, 1_200_000).await.unwrap();
+        assert_eq!(slots, vec![100, 500_099, 500_100, 1_000_099, 1_000_100, 1_200_000]);
+
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|call| call.method == "getBlocks"));
+    }
+
+    #[tokio::test]
+    async fn large_program_account_sets_stream_one_page_at_a_time() {
+        fn accounts(page: u64, count: u64) -> Value {
+            let accounts: Vec<Value> = (0..count)
+                .map(|i| json!({ "pubkey": format!("acct-{}-{}", page, i), "account": { "data": ["", "base64"] } }))
+                .collect();
+            json!(accounts)
+        }
+        let rpc = MockRpc::start(|_, params| {
+            let page = params[1]["pageKey"].as_str().map_or(0, |key| key.parse().unwrap());
+            match params[0].as_str() {
+                // Paginated: 20 pages of 5000, the last without a key
+                Some("Paged111") => {
+                    let next = if page < 19 { json!((page + 1).to_string()) } else { Value::Null };
+                    Reply::Result(json!({ "programAccounts": accounts(page, 5000), "pageKey": next }))
+                }
+                _ => Reply::Result(accounts(0, 100_000)),
+            }
+        })
+        .await;
+        let client = AlchemyRPCClient::new(rpc.alchemy_config());
+
+        let pages: Vec<usize> = client
+            .get_program_accounts_stream("Paged111", Some("base64"), None, None)
+            .map(|page| page.unwrap().len())
+            .collect()
+            .await;
+        assert_eq!(pages, vec![5000; 20]);
+
+        // Without pagination the whole response is one page
+        let pages: Vec<usize> = client
+            .get_program_accounts_stream("Plain111", Some("base64"), None, None)
+            .map(|page| page.unwrap().len())
+            .collect()
+            .await;
+        assert_eq!(pages, vec![100_000]);
+
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(calls.len(), 21);
+        assert_eq!(calls[1].params[1]["pageKey"], "1");
+    }
+
+    #[tokio::test]
+    async fn every_call_gets_a_unique_id_and_request_id_header() {
+        let rpc = MockRpc::start(|_, _| Reply::Result(json!(42))).await;
+        let first = AlchemyRPCClient::new(rpc.alchemy_config());
+        let second = AlchemyRPCClient::new(rpc.alchemy_config());
+        for client in [&first, &second, &first] {
+            client.get_slot().await.unwrap();
+        }
+
+        let calls = rpc.calls.lock().unwrap();
+        let ids: HashSet<u64> = calls.iter().map(|call| call.id).collect();
+        assert_eq!(ids.len(), 3);
+        let request_ids: HashSet<&str> = calls
+            .iter()
+            .map(|call| call.headers.get("x-request-id").expect("X-Request-Id header").as_str())
+            .collect();
+        assert_eq!(request_ids.len(), 3);
+        assert!(request_ids.iter().all(|id| Uuid::parse_str(id).is_ok()));
+    }
+
+    #[tokio::test]
+    async fn skipped_slot_errors_read_as_no_block() {
+        for code in [BLOCK_NOT_AVAILABLE, LONG_TERM_STORAGE_SLOT_SKIPPED] {
+            let rpc = MockRpc::start(move |_, _| Reply::Error { code, message: "Slot 5 was skipped".to_string() }).await;
+            let client = AlchemyRPCClient::new(rpc.alchemy_config());
+
+            let block = client.get_block(5, None).await;
+            assert!(matches!(block, Ok(None)), "code {}: {:?}", code, block);
+            assert!(client.get_raw_block(5, false).await.unwrap().is_none(), "code {}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn purged_slot_errors_are_permanent_and_not_retried() {
+        for code in [BLOCK_CLEANED_UP, SLOT_SKIPPED_OR_MISSING] {
+            let rpc = MockRpc::start(move |_, _| Reply::Error { code, message: "Slot 5 was cleaned up".to_string() }).await;
+            let mut config = rpc.alchemy_config();
+            config.max_retries = 3;
+            let client = AlchemyRPCClient::new(config);
+
+            let err = client.get_block(5, None).await.unwrap_err();
+            assert!(matches!(err, ETLError::SlotPurged(5)), "code {}: {:?}", code, err);
+            assert_eq!(rpc.calls.lock().unwrap().len(), 1, "code {}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn rpc_error_codes_are_named_once() {
+        let rpc = MockRpc::start(|_, _| Reply::Error { code: -32602, message: "Invalid params".to_string() }).await;
+        let client = AlchemyRPCClient::new(rpc.alchemy_config());
+
+        let err = client.get_slot().await.unwrap_err().to_string();
+        assert!(err.starts_with("RPC error -32602: getSlot (request "), "{}", err);
+        assert!(err.ends_with("): Invalid params"), "{}", err);
+        assert_eq!(err.matches("RPC error").count(), 1, "{}", err);
+    }
+
+    #[tokio::test]
+    async fn retry_backoff_is_capped_at_max_backoff_seconds() {
+        let rpc = MockRpc::start(|_, _| Reply::Error { code: 429, message: "Too many requests".to_string() }).await;
+        let mut config = rpc.alchemy_config();
+        config.max_retries = 3;
+        config.max_backoff_seconds = 1;
+        let client = AlchemyRPCClient::new(config);
+
+        // Uncapped, the sleeps would be 1 + 2 + 4 seconds
+        let started = std::time::Instant::now();
+        let err = client.get_slot().await.unwrap_err();
+        let elapsed = started.elapsed();
+        assert!(matches!(err, ETLError::RPCCode { code: 429, .. }), "{:?}", err);
+        assert_eq!(rpc.calls.lock().unwrap().len(), 4);
+        assert!(elapsed >= Duration::from_secs(3) && elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn call_deadline_bounds_slow_responses_and_retries() {
+        let rpc = MockRpc::start(|method, _| match method {
+            "getSlot" => Reply::Delayed(Duration::from_secs(10), Box::new(Reply::Result(json!(42)))),
+            _ => Reply::Error { code: 503, message: "Service unavailable".to_string() },
+        })
+        .await;
+        let mut config = rpc.alchemy_config();
+        config.call_deadline_seconds = 1;
+        config.max_retries = 10;
+        config.max_backoff_seconds = 1;
+        let client = AlchemyRPCClient::new(config);
+
+        // A response slower than the deadline, then retries that outlast it
+        let started = std::time::Instant::now();
+        let err = client.get_slot().await.unwrap_err();
+        assert!(err.to_string().contains("deadline exceeded"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let started = std::time::Instant::now();
+        let err = client.get_block_height().await.unwrap_err();
+        assert!(err.to_string().contains("deadline exceeded"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // A second or so of 1s backoffs, not ten retries
+        let retried = rpc.calls.lock().unwrap().iter().filter(|c| c.method == "getBlockHeight").count();
+        assert!((1..=3).contains(&retried), "{}", retried);
+    }
+
+    #[test]
+    fn per_worker_clients_share_the_limits_of_their_endpoint() {
+        let limits = EndpointRateLimits::default();
+        let mut config = Config::default().alchemy;
+        config.rpc_url = "http://one.invalid".to_string();
+        let first = limits.client(config.clone());
+        let second = limits.client(config.clone());
+        config.rpc_url = "http://two.invalid".to_string();
+        let other = limits.client(config);
+
+        assert!(Arc::ptr_eq(&first.rate_limits, &second.rate_limits));
+        assert!(!Arc::ptr_eq(&first.rate_limits, &other.rate_limits));
+        // Whatever the endpoint, they spend one request budget
+        assert!(Arc::ptr_eq(&first.requests_sent, &other.requests_sent));
+    }
+
+    #[test]
+    fn the_control_lane_is_carved_out_of_the_main_quota() {
+        let mut config = Config::default().alchemy;
+        config.rate_limit_per_second = Some(10);
+        config.rate_limit_per_minute = None;
+        config.rate_limit_burst = None;
+        config.control_rate_limit_per_second = 4;
+        assert_eq!(build_quota(&config), Quota::per_second(NonZeroU32::new(6).unwrap()));
+
+        config.rate_limit_per_second = None;
+        config.rate_limit_per_minute = Some(600);
+        assert_eq!(build_quota(&config), Quota::per_minute(NonZeroU32::new(360).unwrap()));
+
+        config.control_rate_limit_per_second = 0;
+        assert_eq!(build_quota(&config), Quota::per_minute(NonZeroU32::new(600).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn get_slot_is_not_starved_by_queued_block_fetches() {
+        let rpc = MockRpc::start(|method, _| match method {
+            "getSlot" => Reply::Result(json!(42)),
+            _ => Reply::Result(Value::Null),
+        })
+        .await;
+        let mut config = rpc.alchemy_config();
+        config.rate_limit_per_second = Some(3);
+        config.rate_limit_burst = Some(1);
+        config.control_rate_limit_per_second = 1;
+        let client = Arc::new(AlchemyRPCClient::new(config));
+
+        // Two block fetches a second: twenty take ~10s to drain
+        let fetches: Vec<_> = (0..20u64)
+            .map(|slot| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get_block(slot, None).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = std::time::Instant::now();
+        assert_eq!(client.get_slot().await.unwrap(), 42);
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        let blocks = rpc.calls.lock().unwrap().iter().filter(|c| c.method == "getBlock").count();
+        assert!(blocks < 5, "{}", blocks);
+
+        for fetch in fetches {
+            fetch.abort();
+        }
+    }
+}
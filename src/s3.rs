@@ -0,0 +1,530 @@
+use crate::config::WarehouseConfig;
+use crate::error::{ETLError, Result};
+use crate::events::CanonicalEvent;
+use crate::parsers::{ParseWarning, ProgramInvocation, WideTransaction};
+use crate::stats::IngestionStats;
+use crate::warehouse::{checkpoint_key, InMemoryQueue, Warehouse};
+use async_trait::async_trait;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// Objects larger than this are sent with a multipart upload
+const MULTIPART_THRESHOLD_BYTES: usize = 32 * 1024 * 1024;
+
+/// Multipart part size (S3 requires at least 5 MiB for all but the last part)
+const MULTIPART_PART_BYTES: usize = 16 * 1024 * 1024;
+
+/// S3 export target. Events are buffered and written as newline-delimited
+/// JSON objects under `<prefix>/dt=YYYY-MM-DD/`, one per block date, once
+/// about `object_bytes` have accumulated, and before every checkpoint (a
+/// small object under `<prefix>/_checkpoints/`) so the checkpoint never
+/// covers events still in memory.
+///
+/// Configured with `WAREHOUSE_CONNECTION=s3://bucket/prefix`; credentials
+/// come from the standard AWS chain. Nothing can be queried back, so
+/// signature lookups and event counts return empty results.
+pub struct S3Warehouse {
+    bucket: String,
+    prefix: String,
+    object_bytes: usize,
+    client: OnceCell<Client>,
+    pending: Mutex<PendingObjects>,
+    // In-process backfill queue (not durable), as for BigQuery
+    backfill_jobs: InMemoryQueue,
+}
+
+impl S3Warehouse {
+    pub fn new(config: WarehouseConfig) -> Result<Self> {
+        let location = config.connection_string.as_deref().ok_or_else(|| {
+            ETLError::Config("S3 requires connection_string. Set WAREHOUSE_CONNECTION=s3://bucket/prefix".to_string())
+        })?;
+        let (bucket, prefix) = parse_s3_url(location)?;
+        Ok(Self {
+            bucket,
+            prefix,
+            object_bytes: config.object_bytes,
+            client: OnceCell::new(),
+            pending: Mutex::new(PendingObjects::default()),
+            backfill_jobs: InMemoryQueue::default(),
+        })
+    }
+
+    async fn client(&self) -> &Client {
+        self.client
+            .get_or_init(|| async {
+                let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+                Client::new(&sdk_config)
+            })
+            .await
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    fn checkpoint_key(&self, commitment: &str) -> String {
+        self.key(&format!("_checkpoints/{}", checkpoint_key(commitment)))
+    }
+
+    /// Append events to the buffer, returning its objects once they have
+    /// reached `object_bytes` between them
+    fn buffer(&self, events: &[CanonicalEvent]) -> Result<Option<BTreeMap<String, PendingObject>>> {
+        let mut guard = self.pending.lock().unwrap();
+        let pending = &mut *guard;
+        for event in events {
+            let object = pending
+                .by_date
+                .entry(event.block_time.format("%Y-%m-%d").to_string())
+                .or_insert_with(|| PendingObject {
+                    body: Vec::new(),
+                    first_slot: event.slot,
+                    last_slot: event.slot,
+                    events: 0,
+                });
+            let before = object.body.len();
+            serde_json::to_writer(&mut object.body, event)?;
+            object.body.push(b'\n');
+            object.first_slot = object.first_slot.min(event.slot);
+            object.last_slot = object.last_slot.max(event.slot);
+            object.events += 1;
+            pending.bytes += object.body.len() - before;
+        }
+
+        if pending.bytes < self.object_bytes {
+            return Ok(None);
+        }
+        Ok(Some(pending.take()))
+    }
+
+    /// Upload buffered objects, one per block date. The buffer is shared by
+    /// all workers, so objects that fail to upload go back into it rather
+    /// than letting another worker's flush succeed without them.
+    async fn upload(&self, objects: BTreeMap<String, PendingObject>) -> Result<()> {
+        let mut objects = objects.into_iter();
+        while let Some((date, object)) = objects.next() {
+            let key = self.key(&format!(
+                "dt={}/{}-{}-{}.ndjson",
+                date,
+                object.first_slot,
+                object.last_slot,
+                Uuid::new_v4()
+            ));
+            tracing::info!("Writing {} events to s3://{}/{}", object.events, self.bucket, key);
+            if let Err(e) = self.put(&key, object.body.clone()).await {
+                let mut pending = self.pending.lock().unwrap();
+                pending.restore(date, object);
+                for (date, object) in objects {
+                    pending.restore(date, object);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload an object, switching to a multipart upload for large bodies
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        if body.len() > MULTIPART_THRESHOLD_BYTES {
+            return self.put_multipart(key, body).await;
+        }
+
+        self.client()
+            .await
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to write s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e))))?;
+        Ok(())
+    }
+
+    async fn put_multipart(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        let client = self.client().await;
+        let upload = client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to start upload of {}: {}", key, DisplayErrorContext(e))))?;
+        let upload_id = upload
+            .upload_id()
+            .ok_or_else(|| ETLError::Database(format!("No upload id returned for {}", key)))?
+            .to_string();
+
+        let mut parts = Vec::new();
+        for (idx, chunk) in body.chunks(MULTIPART_PART_BYTES).enumerate() {
+            let part_number = idx as i32 + 1;
+            let result = client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(chunk.to_vec()))
+                .send()
+                .await;
+
+            match result {
+                Ok(part) => parts.push(
+                    CompletedPart::builder()
+                        .set_e_tag(part.e_tag().map(str::to_string))
+                        .part_number(part_number)
+                        .build(),
+                ),
+                Err(e) => {
+                    // Don't leave orphaned parts accruing storage
+                    client
+                        .abort_multipart_upload()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .upload_id(&upload_id)
+                        .send()
+                        .await
+                        .ok();
+                    return Err(ETLError::Database(format!(
+                        "Failed to upload part {} of {}: {}",
+                        part_number,
+                        key,
+                        DisplayErrorContext(e)
+                    )));
+                }
+            }
+        }
+
+        client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to complete upload of {}: {}", key, DisplayErrorContext(e))))?;
+        Ok(())
+    }
+}
+
+/// Serialized events of one block date waiting to be uploaded
+struct PendingObject {
+    body: Vec<u8>,
+    first_slot: u64,
+    last_slot: u64,
+    events: usize,
+}
+
+#[derive(Default)]
+struct PendingObjects {
+    by_date: BTreeMap<String, PendingObject>,
+    bytes: usize,
+}
+
+impl PendingObjects {
+    fn take(&mut self) -> BTreeMap<String, PendingObject> {
+        self.bytes = 0;
+        std::mem::take(&mut self.by_date)
+    }
+
+    /// Put back an object that was taken but not uploaded, ahead of
+    /// anything buffered for its date since
+    fn restore(&mut self, date: String, mut object: PendingObject) {
+        self.bytes += object.body.len();
+        if let Some(newer) = self.by_date.remove(&date) {
+            object.body.extend_from_slice(&newer.body);
+            object.first_slot = object.first_slot.min(newer.first_slot);
+            object.last_slot = object.last_slot.max(newer.last_slot);
+            object.events += newer.events;
+        }
+        self.by_date.insert(date, object);
+    }
+}
+
+/// Split `s3://bucket/prefix` into bucket and prefix (without slashes at the ends)
+fn parse_s3_url(url: &str) -> Result<(String, String)> {
+    let path = url
+        .strip_prefix("s3://")
+        .ok_or_else(|| ETLError::Config(format!("S3 location must start with s3://: {}", url)))?;
+    let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+    if bucket.is_empty() {
+        return Err(ETLError::Config(format!("S3 location has no bucket: {}", url)));
+    }
+    Ok((bucket.to_string(), prefix.trim_matches('/').to_string()))
+}
+
+/// Serialize values as newline-delimited JSON
+fn to_ndjson<T: serde::Serialize>(values: &[T]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for value in values {
+        serde_json::to_writer(&mut body, value)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
+#[async_trait]
+impl Warehouse for S3Warehouse {
+    async fn connect(&self) -> Result<()> {
+        tracing::info!("Exporting to s3://{}/{}", self.bucket, self.prefix);
+        Ok(())
+    }
+
+    async fn insert_events(&self, events: Vec<CanonicalEvent>) -> Result<()> {
+        // One object per block date so `dt=` partitions stay accurate
+        match self.buffer(&events)? {
+            Some(objects) => self.upload(objects).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let objects = self.pending.lock().unwrap().take();
+        self.upload(objects).await
+    }
+
+    async fn get_last_slot(&self, commitment: &str) -> Result<Option<u64>> {
+        let key = self.checkpoint_key(commitment);
+        let output = match self.client().await.get_object().bucket(&self.bucket).key(&key).send().await {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => {
+                return Err(ETLError::Database(format!("Failed to read checkpoint: {}", DisplayErrorContext(e))));
+            }
+        };
+
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to read checkpoint: {}", e)))?
+            .into_bytes();
+        Ok(String::from_utf8_lossy(&bytes).trim().parse().ok())
+    }
+
+    async fn update_last_slot(&self, commitment: &str, slot: u64) -> Result<()> {
+        self.flush().await?;
+        self.put(&self.checkpoint_key(commitment), slot.to_string().into_bytes()).await
+    }
+
+    async fn insert_events_with_checkpoint(&self, events: Vec<CanonicalEvent>, commitment: &str, slot: u64) -> Result<()> {
+        // S3 has no transactions; the checkpoint is only written once the events are uploaded
+        self.insert_events(events).await?;
+        self.update_last_slot(commitment, slot).await
+    }
+
+    /// Objects can't be queried by slot, so every slot counts as
+    /// unprocessed: re-running a range writes its events again, and
+    /// consumers deduplicate on `event_id`
+    async fn is_slot_processed(&self, _slot: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn enqueue_backfill_chunks(&self, chunks: &[(u64, u64)], resume: bool, _stale_after_seconds: u64) -> Result<()> {
+        self.backfill_jobs.enqueue(chunks, resume);
+        Ok(())
+    }
+
+    async fn claim_backfill_chunk(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+        _stale_after_seconds: u64,
+    ) -> Result<Option<(u64, u64)>> {
+        Ok(self.backfill_jobs.claim(start_slot, end_slot))
+    }
+
+    async fn complete_backfill_chunk(&self, chunk_start: u64, chunk_end: u64, success: bool) -> Result<()> {
+        // A finished chunk's events are written out, even if short of `object_bytes`
+        self.flush().await?;
+        self.backfill_jobs.complete(chunk_start, chunk_end, success);
+        Ok(())
+    }
+
+    async fn insert_parse_warnings(&self, warnings: &[ParseWarning]) -> Result<()> {
+        if warnings.is_empty() {
+            return Ok(());
+        }
+        let rows: Vec<_> = warnings
+            .iter()
+            .map(|w| {
+                serde_json::json!({
+                    "slot": w.slot,
+                    "tx_signature": w.tx_signature,
+                    "instruction_index": w.instruction_index,
+                    "stage": w.stage,
+                    "message": w.message,
+                })
+            })
+            .collect();
+        let key = self.key(&format!("_parse_warnings/{}-{}.ndjson", warnings[0].slot, Uuid::new_v4()));
+        self.put(&key, to_ndjson(&rows)?).await
+    }
+
+    async fn insert_program_invocations(&self, invocations: &[ProgramInvocation]) -> Result<()> {
+        if invocations.is_empty() {
+            return Ok(());
+        }
+        let rows: Vec<_> = invocations
+            .iter()
+            .map(|i| {
+                serde_json::json!({
+                    "slot": i.slot,
+                    "program_id": i.program_id,
+                    "invocation_count": i.count,
+                })
+            })
+            .collect();
+        let key = self.key(&format!("_program_invocations/{}-{}.ndjson", invocations[0].slot, Uuid::new_v4()));
+        self.put(&key, to_ndjson(&rows)?).await
+    }
+
+    async fn insert_wide_transactions(&self, rows: &[WideTransaction]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<_> = rows
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "slot": r.slot,
+                    "tx_signature": r.tx_signature,
+                    "block_time": r.block_time,
+                    "transaction_index": r.transaction_index,
+                    "success": r.success,
+                    "fee_lamports": r.fee_lamports,
+                    "program_ids": r.program_ids,
+                    "instruction_count": r.instruction_count,
+                    "token_transfer_count": r.token_transfer_count,
+                    "lamports_moved": r.lamports_moved,
+                })
+            })
+            .collect();
+        let key = self.key(&format!("_transactions_wide/{}-{}.ndjson", rows[0].slot, Uuid::new_v4()));
+        self.put(&key, to_ndjson(&lines)?).await
+    }
+
+    async fn insert_ingestion_stats(&self, stats: &IngestionStats) -> Result<()> {
+        tracing::debug!(
+            "{} window: {} slots, {} events in {:.1}s (not stored in S3)",
+            stats.source, stats.slots_processed, stats.events_inserted, stats.duration_seconds
+        );
+        Ok(())
+    }
+
+    async fn get_events_by_signature(&self, _signature: &str) -> Result<Vec<CanonicalEvent>> {
+        Ok(Vec::new())
+    }
+
+    async fn count_events(&self, _slot: u64, _event_type: &str) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.client()
+            .await
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .map_err(|e| ETLError::Database(format!("S3 bucket {} is not reachable: {}", self.bucket, DisplayErrorContext(e))))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::postgres_config;
+    use chrono::DateTime;
+    use serde_json::json;
+
+    fn warehouse(object_bytes: usize) -> S3Warehouse {
+        let mut config = postgres_config("s3://bucket/exports");
+        config.warehouse_type = "s3".to_string();
+        config.object_bytes = object_bytes;
+        S3Warehouse::new(config).unwrap()
+    }
+
+    fn event(slot: u64, timestamp: i64) -> CanonicalEvent {
+        CanonicalEvent::new(
+            slot,
+            DateTime::from_timestamp(timestamp, 0).unwrap(),
+            format!("sig{}", slot),
+            None,
+            -1,
+            "transaction".to_string(),
+            json!({ "fee": 5000 }),
+        )
+    }
+
+    #[test]
+    fn events_are_held_until_the_object_size_is_reached() {
+        let line_bytes = serde_json::to_vec(&event(100, 1_700_000_000)).unwrap().len() + 1;
+        let warehouse = warehouse(line_bytes * 3);
+
+        assert!(warehouse.buffer(&[event(100, 1_700_000_000)]).unwrap().is_none());
+        // 2023-11-14 and 2023-11-15 go to separate objects
+        assert!(warehouse.buffer(&[event(101, 1_700_086_400)]).unwrap().is_none());
+
+        let objects = warehouse.buffer(&[event(102, 1_700_000_001)]).unwrap().unwrap();
+        let summary: Vec<(&str, u64, u64, usize)> = objects
+            .iter()
+            .map(|(date, o)| (date.as_str(), o.first_slot, o.last_slot, o.events))
+            .collect();
+        assert_eq!(summary, vec![("2023-11-14", 100, 102, 2), ("2023-11-15", 101, 101, 1)]);
+        let lines: Vec<CanonicalEvent> = objects["2023-11-14"]
+            .body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.iter().map(|e| e.slot).collect::<Vec<_>>(), vec![100, 102]);
+
+        // The buffer starts over after handing its objects out
+        let pending = warehouse.pending.lock().unwrap();
+        assert!(pending.by_date.is_empty());
+        assert_eq!(pending.bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn objects_that_fail_to_upload_stay_buffered() {
+        let warehouse = warehouse(usize::MAX);
+        // Nothing listens on port 1, so every put fails
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .endpoint_url("http://127.0.0.1:1")
+            .credentials_provider(aws_sdk_s3::config::Credentials::new("key", "secret", None, None, "test"))
+            .retry_config(aws_sdk_s3::config::retry::RetryConfig::disabled())
+            .build();
+        warehouse.client.set(Client::from_conf(config)).unwrap();
+
+        warehouse.insert_events(vec![event(100, 1_700_000_000), event(101, 1_700_086_400)]).await.unwrap();
+        let buffered = warehouse.pending.lock().unwrap().bytes;
+        assert!(warehouse.flush().await.is_err());
+
+        // Still there for the next flush, merged with what came in since
+        warehouse.insert_events(vec![event(102, 1_700_000_001)]).await.unwrap();
+        assert!(warehouse.flush().await.is_err());
+        let pending = warehouse.pending.lock().unwrap();
+        let summary: Vec<(&str, u64, u64, usize)> = pending
+            .by_date
+            .iter()
+            .map(|(date, o)| (date.as_str(), o.first_slot, o.last_slot, o.events))
+            .collect();
+        assert_eq!(summary, vec![("2023-11-14", 100, 102, 2), ("2023-11-15", 101, 101, 1)]);
+        assert!(pending.bytes > buffered);
+        assert_eq!(pending.bytes, pending.by_date.values().map(|o| o.body.len()).sum::<usize>());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::error::{ETLError, Result};
+use sqlx::PgConnection;
+
+/// Validate a SQL identifier and return it double-quoted.
+///
+/// Only `[a-zA-Z_][a-zA-Z0-9_]*` is accepted, so the result is safe to
+/// interpolate into a query string (table/column names can't be bound).
+pub fn quote_ident(name: &str) -> Result<String> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+
+    if !valid {
+        return Err(ETLError::Config(format!("Invalid SQL identifier: {:?}", name)));
+    }
+
+    Ok(format!("\"{}\"", name))
+}
+
+/// Convert any `TIMESTAMP` (without time zone) columns in `tables` to
+/// `TIMESTAMPTZ` in place, treating the stored values as UTC.
+///
+/// Columns that are already `TIMESTAMPTZ` are left alone, so this is a no-op
+/// after the first run.
+pub async fn migrate_timestamp_columns(conn: &mut PgConnection, tables: &[&str]) -> Result<()> {
+    let columns: Vec<(String, String)> = sqlx::query_as(
+        r#"
+        SELECT table_name::text, column_name::text
+        FROM information_schema.columns
+        WHERE table_schema = current_schema()
+          AND table_name = ANY($1)
+          AND data_type = 'timestamp without time zone'
+        "#
+    )
+    .bind(tables)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| ETLError::Database(format!("Failed to inspect timestamp columns: {}", e)))?;
+
+    for (table, column) in columns {
+        tracing::info!("Migrating {}.{} to TIMESTAMPTZ (as UTC)", table, column);
+        let column = quote_ident(&column)?;
+        let query = format!(
+            "ALTER TABLE {} ALTER COLUMN {} TYPE TIMESTAMPTZ USING {} AT TIME ZONE 'UTC'",
+            quote_ident(&table)?,
+            column,
+            column
+        );
+        sqlx::query(&query)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| ETLError::Database(format!("Failed to migrate {} timestamps: {}", table, e)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_identifiers_are_quoted() {
+        assert_eq!(quote_ident("fact_transactions").unwrap(), "\"fact_transactions\"");
+        assert_eq!(quote_ident("_tmp2").unwrap(), "\"_tmp2\"");
+        assert_eq!(quote_ident("Mixed_Case").unwrap(), "\"Mixed_Case\"");
+    }
+
+    #[test]
+    fn malicious_identifiers_are_rejected() {
+        for name in [
+            "",
+            "1table",
+            "fact_transactions; DROP TABLE etl_metadata",
+            "x\" OR \"1\"=\"1",
+            "schema.table",
+            "name--",
+            "naïve",
+            "tab le",
+        ] {
+            assert!(quote_ident(name).is_err(), "{:?} should be rejected", name);
+        }
+    }
+}
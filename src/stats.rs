@@ -0,0 +1,355 @@
+use crate::clock::{SharedClock, SystemClock};
+use crate::config::ETLConfig;
+use crate::warehouse::Warehouse;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Weight of the newest slot in the running events-per-slot average
+const EVENTS_PER_SLOT_SMOOTHING: f64 = 0.1;
+
+/// Decides when a buffered batch is written. With `target_events_per_batch`
+/// set, it flushes once the next slot (at the running events-per-slot
+/// average) would overshoot the target, so dense blocks don't inflate
+/// batches; otherwise it flushes at `batch_size` events.
+pub struct BatchThreshold {
+    batch_size: usize,
+    target: Option<usize>,
+    events_per_slot: Option<f64>,
+}
+
+impl BatchThreshold {
+    pub fn new(config: &ETLConfig) -> Self {
+        Self {
+            batch_size: config.batch_size,
+            target: (config.target_events_per_batch > 0).then_some(config.target_events_per_batch),
+            events_per_slot: None,
+        }
+    }
+
+    /// Fold a parsed slot's event count into the running average
+    pub fn record_slot(&mut self, events: usize) {
+        let events = events as f64;
+        self.events_per_slot = Some(match self.events_per_slot {
+            Some(avg) => avg + EVENTS_PER_SLOT_SMOOTHING * (events - avg),
+            None => events,
+        });
+    }
+
+    pub fn should_flush(&self, batch_len: usize) -> bool {
+        match self.target {
+            None => batch_len >= self.batch_size,
+            Some(target) => {
+                let next_slot = self.events_per_slot.unwrap_or(0.0);
+                batch_len >= target || (batch_len > 0 && batch_len as f64 + next_slot > target as f64)
+            }
+        }
+    }
+}
+
+/// Watermark on written events: reports a stall once nothing has been
+/// written for `timeout` while there were slots to ingest
+pub struct StallWatch {
+    timeout: Option<Duration>,
+    clock: SharedClock,
+    last_progress: Instant,
+}
+
+impl StallWatch {
+    pub fn new(timeout_seconds: u64) -> Self {
+        Self::with_clock(timeout_seconds, SystemClock::shared())
+    }
+
+    pub fn with_clock(timeout_seconds: u64, clock: SharedClock) -> Self {
+        Self {
+            timeout: (timeout_seconds > 0).then(|| Duration::from_secs(timeout_seconds)),
+            last_progress: clock.instant(),
+            clock,
+        }
+    }
+
+    /// Record one run; returns how long ingestion has been stalled when that
+    /// exceeds the timeout. Runs with nothing to ingest are not a stall.
+    pub fn observe(&mut self, events_written: u64, behind_tip: bool) -> Option<Duration> {
+        let timeout = self.timeout?;
+        if events_written > 0 || !behind_tip {
+            self.last_progress = self.clock.instant();
+            return None;
+        }
+        let stalled = self.clock.since(self.last_progress);
+        if stalled < timeout {
+            return None;
+        }
+        // Fire again only after another full timeout
+        self.last_progress = self.clock.instant();
+        Some(stalled)
+    }
+}
+
+/// Throughput of one ingestion window, stored in `ingestion_stats`
+#[derive(Debug, Clone)]
+pub struct IngestionStats {
+    pub source: &'static str, // "incremental" or "backfill"
+    pub window_start: DateTime<Utc>,
+    pub slots_processed: u64,
+    pub events_inserted: u64,
+    pub duration_seconds: f64,
+}
+
+/// Counts slots and events between checkpoints; `flush` writes the window
+/// and starts the next one
+pub struct IngestionWindow {
+    source: &'static str,
+    clock: SharedClock,
+    window_start: DateTime<Utc>,
+    started: Instant,
+    slots: u64,
+    events: u64,
+}
+
+impl IngestionWindow {
+    pub fn new(source: &'static str) -> Self {
+        Self::with_clock(source, SystemClock::shared())
+    }
+
+    pub fn with_clock(source: &'static str, clock: SharedClock) -> Self {
+        Self {
+            source,
+            window_start: clock.now(),
+            started: clock.instant(),
+            clock,
+            slots: 0,
+            events: 0,
+        }
+    }
+
+    pub fn record_slot(&mut self, events: usize) {
+        self.slots += 1;
+        self.events += events as u64;
+    }
+
+    /// Store the window (if it processed anything) and reset. Failures are
+    /// logged, not returned, so stats never stop ingestion.
+    pub async fn flush(&mut self, warehouse: &dyn Warehouse) {
+        if self.slots > 0 {
+            let stats = IngestionStats {
+                source: self.source,
+                window_start: self.window_start,
+                slots_processed: self.slots,
+                events_inserted: self.events,
+                duration_seconds: self.clock.since(self.started).as_secs_f64(),
+            };
+            if let Err(e) = warehouse.insert_ingestion_stats(&stats).await {
+                tracing::warn!("Failed to record ingestion stats: {}", e);
+            }
+        }
+        *self = Self::with_clock(self.source, self.clock.clone());
+    }
+}
+
+/// What a backfill run did, printed by `backfill --summary-json`
+#[derive(Debug, Clone, Serialize)]
+pub struct BackfillSummary {
+    pub run_id: String,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    /// Slots fetched (or known skipped from getBlocks) by this run
+    pub slots_attempted: u64,
+    pub slots_with_blocks: u64,
+    /// Slots with no block (skipped by their leader or not found)
+    pub slots_skipped: u64,
+    /// Slots the RPC node has purged, recorded as `purged` parse warnings
+    pub slots_purged: u64,
+    /// Slots already stored or listed in the processed manifest
+    pub slots_already_processed: u64,
+    /// Transactions skipped for exceeding `ETL_MAX_TRANSACTION_BYTES`
+    pub oversized_transactions: u64,
+    pub chunks_completed: u64,
+    pub chunks_failed: u64,
+    /// Events written by completed chunks
+    pub events_inserted: u64,
+    pub events_by_type: BTreeMap<String, u64>,
+    pub elapsed_seconds: f64,
+    /// What stopped the run early, if anything
+    pub error: Option<String>,
+}
+
+impl BackfillSummary {
+    pub fn log(&self) {
+        info!(
+            "Backfill {} summary for slots {}-{}: {} slots attempted ({} with blocks, {} skipped), {} already processed",
+            self.run_id,
+            self.start_slot,
+            self.end_slot,
+            self.slots_attempted,
+            self.slots_with_blocks,
+            self.slots_skipped,
+            self.slots_already_processed
+        );
+        info!(
+            "Chunks: {} completed, {} failed; {} events inserted in {:.1}s",
+            self.chunks_completed, self.chunks_failed, self.events_inserted, self.elapsed_seconds
+        );
+        for (event_type, count) in &self.events_by_type {
+            info!("  {}: {}", event_type, count);
+        }
+        if self.oversized_transactions > 0 {
+            warn!(
+                "{} transactions over ETL_MAX_TRANSACTION_BYTES were skipped",
+                self.oversized_transactions
+            );
+        }
+        if self.slots_purged > 0 {
+            warn!(
+                "{} slots are purged from the RPC node and were not ingested; see parse_warnings (stage 'purged')",
+                self.slots_purged
+            );
+        }
+        if let Some(error) = &self.error {
+            info!("Stopped by: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::test_support::{config, MemoryWarehouse};
+    use std::sync::Arc;
+
+    /// Feed slots of the given densities through a threshold the way the
+    /// ingestion loop does, returning the size of each flushed batch
+    fn flushed_batches(threshold: &mut BatchThreshold, densities: &[usize]) -> Vec<usize> {
+        let (mut batches, mut batch) = (Vec::new(), 0);
+        for &events in densities {
+            batch += events;
+            threshold.record_slot(events);
+            if threshold.should_flush(batch) {
+                batches.push(batch);
+                batch = 0;
+            }
+        }
+        batches
+    }
+
+    #[test]
+    fn batches_stay_near_the_target_as_block_density_varies() {
+        let mut etl = config().etl;
+        etl.target_events_per_batch = 1_000;
+        let mut threshold = BatchThreshold::new(&etl);
+
+        // Sparse, then dense, then sparse again
+        let sparse = vec![10; 300];
+        let dense = vec![400; 100];
+        let sparse_batches = flushed_batches(&mut threshold, &sparse);
+        let dense_batches = flushed_batches(&mut threshold, &dense);
+        let again = flushed_batches(&mut threshold, &sparse);
+        // Flushes land within one slot of the target whatever the density
+        for batch in sparse_batches.iter().chain(&again) {
+            assert!((990..=1_000).contains(batch), "{:?} / {:?}", sparse_batches, again);
+        }
+        // The running average lags the jump to dense slots for a batch or
+        // two; after that a slot never pushes a batch past the target
+        assert!(dense_batches.iter().all(|&batch| batch <= 1_000 + 400), "{:?}", dense_batches);
+        assert!(dense_batches[2..].iter().all(|&batch| (600..=1_000).contains(&batch)), "{:?}", dense_batches);
+
+        // A fixed batch size overshoots by up to a whole dense slot every time
+        etl.target_events_per_batch = 0;
+        etl.batch_size = 1_000;
+        let fixed = flushed_batches(&mut BatchThreshold::new(&etl), &dense);
+        assert!(fixed.iter().all(|&batch| batch == 1_200), "{:?}", fixed);
+    }
+
+    #[tokio::test]
+    async fn a_window_flush_covers_the_time_since_the_last_one() {
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let warehouse = MemoryWarehouse::default();
+        let mut window = IngestionWindow::with_clock("incremental", clock.clone());
+
+        window.record_slot(10);
+        window.record_slot(0);
+        clock.advance(Duration::from_secs(30));
+        window.flush(&warehouse).await;
+
+        // The next window starts at the flush; an empty one writes nothing
+        clock.advance(Duration::from_secs(5));
+        window.flush(&warehouse).await;
+        window.record_slot(4);
+        clock.advance(Duration::from_millis(2_500));
+        window.flush(&warehouse).await;
+
+        let stats = warehouse.state.lock().unwrap().stats.clone();
+        let windows: Vec<_> = stats
+            .iter()
+            .map(|s| (s.window_start, s.slots_processed, s.events_inserted, s.duration_seconds))
+            .collect();
+        assert_eq!(
+            windows,
+            vec![
+                (start, 2, 10, 30.0),
+                (start + chrono::Duration::seconds(35), 1, 4, 2.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_stall_fires_once_per_timeout_while_behind_the_tip() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let mut watch = StallWatch::with_clock(60, clock.clone());
+        let minute = Duration::from_secs(60);
+
+        // Progress, or nothing to ingest, keeps the watermark fresh
+        clock.advance(minute * 5);
+        assert_eq!(watch.observe(10, true), None);
+        clock.advance(minute * 5);
+        assert_eq!(watch.observe(0, false), None);
+
+        // Behind the tip with no events written: silent until the timeout
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(watch.observe(0, true), None);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(watch.observe(0, true), Some(Duration::from_secs(61)));
+
+        // Fires again only after another full timeout
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(watch.observe(0, true), None);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(watch.observe(0, true), Some(minute));
+
+        // Recovery resets it
+        assert_eq!(watch.observe(1, true), None);
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(watch.observe(0, true), None);
+
+        // A zero timeout never fires
+        let mut off = StallWatch::with_clock(0, clock.clone());
+        clock.advance(minute * 60);
+        assert_eq!(off.observe(0, true), None);
+    }
+
+    #[tokio::test]
+    async fn wall_clock_steps_do_not_move_stall_or_window_timing() {
+        let start = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        let clock = Arc::new(MockClock::new(start));
+        let mut watch = StallWatch::with_clock(60, clock.clone());
+        let warehouse = MemoryWarehouse::default();
+        let mut window = IngestionWindow::with_clock("incremental", clock.clone());
+        window.record_slot(3);
+
+        // Stepping forward is not a stall, and stepping back doesn't hide one
+        clock.set(start + hour);
+        assert_eq!(watch.observe(0, true), None);
+        clock.set(start - hour);
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(watch.observe(0, true), Some(Duration::from_secs(61)));
+
+        window.flush(&warehouse).await;
+        let stats = warehouse.state.lock().unwrap().stats.clone();
+        assert_eq!((stats[0].window_start, stats[0].duration_seconds), (start, 61.0));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::config::WarehouseConfig;
+use crate::error::Result;
+use crate::events::CanonicalEvent;
+use crate::parsers::ParseWarning;
+use crate::stats::IngestionStats;
+use crate::warehouse::{InMemoryQueue, Warehouse};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Streams events to stdout as newline-delimited JSON, one `CanonicalEvent`
+/// per line, for piping into jq or another loader without a database.
+///
+/// Configured with `WAREHOUSE_TYPE=stdout`. Logs already go to stderr, so
+/// stdout carries only events. Checkpoints and the backfill queue live in
+/// memory for the life of the process; parse warnings, invocation counts,
+/// wide transaction rows and stats are not written, and nothing can be
+/// queried back.
+pub struct StdoutWarehouse {
+    // stdout, or a buffer in tests
+    out: Mutex<Box<dyn Write + Send>>,
+    checkpoints: Mutex<HashMap<String, u64>>,
+    // In-process backfill queue (not durable), as for BigQuery
+    backfill_jobs: InMemoryQueue,
+}
+
+impl StdoutWarehouse {
+    pub fn new(_config: WarehouseConfig) -> Result<Self> {
+        Ok(Self::with_writer(Box::new(std::io::stdout())))
+    }
+
+    /// Write the event lines to `out` instead of stdout
+    pub fn with_writer(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+            checkpoints: Mutex::new(HashMap::new()),
+            backfill_jobs: InMemoryQueue::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Warehouse for StdoutWarehouse {
+    async fn connect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn insert_events(&self, events: Vec<CanonicalEvent>) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        // One locked, buffered write per batch keeps lines from concurrent
+        // backfill workers from interleaving
+        let mut out = self.out.lock().unwrap();
+        let mut out = std::io::BufWriter::new(&mut *out);
+        for event in &events {
+            serde_json::to_writer(&mut out, event)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    async fn get_last_slot(&self, commitment: &str) -> Result<Option<u64>> {
+        Ok(self.checkpoints.lock().unwrap().get(commitment).copied())
+    }
+
+    async fn update_last_slot(&self, commitment: &str, slot: u64) -> Result<()> {
+        self.checkpoints.lock().unwrap().insert(commitment.to_string(), slot);
+        Ok(())
+    }
+
+    async fn insert_events_with_checkpoint(&self, events: Vec<CanonicalEvent>, commitment: &str, slot: u64) -> Result<()> {
+        self.insert_events(events).await?;
+        self.update_last_slot(commitment, slot).await
+    }
+
+    async fn is_slot_processed(&self, _slot: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn enqueue_backfill_chunks(&self, chunks: &[(u64, u64)], resume: bool, _stale_after_seconds: u64) -> Result<()> {
+        self.backfill_jobs.enqueue(chunks, resume);
+        Ok(())
+    }
+
+    async fn claim_backfill_chunk(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+        _stale_after_seconds: u64,
+    ) -> Result<Option<(u64, u64)>> {
+        Ok(self.backfill_jobs.claim(start_slot, end_slot))
+    }
+
+    async fn complete_backfill_chunk(&self, chunk_start: u64, chunk_end: u64, success: bool) -> Result<()> {
+        self.backfill_jobs.complete(chunk_start, chunk_end, success);
+        Ok(())
+    }
+
+    async fn insert_parse_warnings(&self, warnings: &[ParseWarning]) -> Result<()> {
+        tracing::debug!("{} parse warnings (not written to stdout)", warnings.len());
+        Ok(())
+    }
+
+    async fn insert_ingestion_stats(&self, stats: &IngestionStats) -> Result<()> {
+        tracing::debug!(
+            "{} window: {} slots, {} events in {:.1}s (not written to stdout)",
+            stats.source, stats.slots_processed, stats.events_inserted, stats.duration_seconds
+        );
+        Ok(())
+    }
+
+    async fn get_events_by_signature(&self, _signature: &str) -> Result<Vec<CanonicalEvent>> {
+        Ok(Vec::new())
+    }
+
+    async fn count_events(&self, _slot: u64, _event_type: &str) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::parse_block;
+    use crate::test_support::{block, transaction};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// A `Write` into a buffer the test keeps a handle to
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_written_as_one_json_object_per_line() {
+        let captured = Captured::default();
+        let warehouse = StdoutWarehouse::with_writer(Box::new(captured.clone()));
+        let events = parse_block(
+            &block(vec![
+                transaction("sig1", &["payer", "Prog111"], json!([{ "programIdIndex": 1, "accounts": [0], "data": "" }])),
+                transaction("sig2", &["payer", "Prog111"], json!([])),
+            ]),
+            100,
+        )
+        .unwrap();
+
+        warehouse.insert_events(events[..2].to_vec()).await.unwrap();
+        warehouse.insert_events_with_checkpoint(events[2..].to_vec(), "confirmed", 100).await.unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with('\n'));
+        let lines: Vec<CanonicalEvent> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let ids = |events: &[CanonicalEvent]| events.iter().map(|e| e.event_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&lines), ids(&events));
+        assert_eq!(lines[0].raw_payload, events[0].raw_payload);
+        assert_eq!(warehouse.get_last_slot("confirmed").await.unwrap(), Some(100));
+    }
+}
@@ -0,0 +1,506 @@
+//! Fixtures shared by the unit tests
+
+use crate::block_source::BlockSource;
+use crate::config::{Config, WarehouseConfig};
+use crate::error::{ETLError, Result};
+use crate::events::CanonicalEvent;
+use crate::parsers::{ParseWarning, ProgramInvocation, RawBlock, WideTransaction};
+use crate::stats::IngestionStats;
+use crate::warehouse::{checkpoint_key, Warehouse};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{ConnectOptions, Connection};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A fresh, empty database on the server named by `TEST_DATABASE_URL`, so
+/// tests that write to Postgres don't see each other's rows
+pub async fn fresh_database() -> String {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set for database tests");
+    let options: PgConnectOptions = url.parse().expect("TEST_DATABASE_URL is not a Postgres URL");
+    let name = format!("etl_test_{}", uuid::Uuid::new_v4().simple());
+    let mut conn = options.connect().await.expect("cannot connect to TEST_DATABASE_URL");
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&mut conn)
+        .await
+        .expect("cannot create test database");
+    conn.close().await.ok();
+
+    let mut test_url = reqwest::Url::parse(&url).expect("TEST_DATABASE_URL is not a URL");
+    test_url.set_path(&name);
+    test_url.to_string()
+}
+
+/// Postgres warehouse settings pointing at `url`, with every optional feature off
+pub fn postgres_config(url: &str) -> WarehouseConfig {
+    WarehouseConfig {
+        warehouse_type: "postgres".to_string(),
+        connection_string: Some(url.to_string()),
+        ssl_mode: None,
+        ssl_root_cert: None,
+        project_id: None,
+        dataset_id: None,
+        credentials_path: None,
+        compress_payload: false,
+        insert_batch_size: 0,
+        object_bytes: 0,
+        discriminator_names_path: None,
+        shadow: None,
+        routes: Vec::new(),
+        sinks: Vec::new(),
+    }
+}
+
+/// A `json`-encoded transaction signed by `signature`, with `account_keys`
+/// as the static keys (the first is the only signer and fee payer) and
+/// `instructions` as given. Balances are 0 before and after, and `meta`
+/// has no inner instructions, token balances or lookup-table keys.
+pub fn transaction(signature: &str, account_keys: &[&str], instructions: Value) -> Value {
+    let zeros = vec![0; account_keys.len()];
+    json!({
+        "transaction": {
+            "signatures": [signature],
+            "message": {
+                "accountKeys": account_keys,
+                "header": { "numRequiredSignatures": 1 },
+                "recentBlockhash": "EkSnNWid2cvwEVnVx9aBqawnmiCNiDgp3gUdkDPTKN1N",
+                "instructions": instructions,
+            },
+        },
+        "meta": {
+            "err": null,
+            "fee": 5000,
+            "preBalances": zeros,
+            "postBalances": zeros,
+            "preTokenBalances": [],
+            "postTokenBalances": [],
+            "innerInstructions": [],
+            "logMessages": [],
+        },
+    })
+}
+
+/// A `getBlock` result holding `transactions`
+pub fn block(transactions: Vec<Value>) -> Value {
+    json!({
+        "blockTime": 1_700_000_000,
+        "blockHeight": 250_000_000,
+        "blockhash": "5Ue8DfjYDyRvzVSh8MJxBjM9ooeAYDi1dmSngkqvpbJz",
+        "transactions": transactions,
+    })
+}
+
+/// Everything a `MemoryWarehouse` has stored
+#[derive(Debug, Default)]
+pub struct MemoryState {
+    /// Stored events by event_id; rewrites replace, as the upserts do
+    pub events: BTreeMap<String, CanonicalEvent>,
+    pub checkpoints: HashMap<String, u64>,
+    pub backfill_jobs: Vec<(u64, u64, &'static str)>,
+    /// Last finished slot per (chunk_start, chunk_end)
+    pub chunk_progress: HashMap<(u64, u64), u64>,
+    pub invocations: Vec<ProgramInvocation>,
+    pub wide_rows: Vec<WideTransaction>,
+    pub stats: Vec<IngestionStats>,
+    pub warnings: usize,
+    /// Successful write calls, each standing for one transaction
+    pub writes: usize,
+    /// Writes carrying an event of this slot fail, writing nothing
+    pub fail_slot: Option<u64>,
+    /// `flush` fails, as a buffering warehouse that can't write out would
+    pub fail_flush: bool,
+    /// Every event write sleeps this long first, as a slow database would
+    pub write_delay: Option<Duration>,
+    /// Writes under way, and the most that were ever under way at once
+    pub writing: usize,
+    pub most_writing: usize,
+}
+
+/// In-memory `Warehouse` for driving the ingestion loops in tests
+#[derive(Debug, Default)]
+pub struct MemoryWarehouse {
+    pub state: Mutex<MemoryState>,
+}
+
+impl MemoryWarehouse {
+    pub fn checkpoint(&self, commitment: &str) -> Option<u64> {
+        self.state.lock().unwrap().checkpoints.get(&checkpoint_key(commitment)).copied()
+    }
+
+    /// Slots with at least one stored event
+    pub fn slots(&self) -> BTreeSet<u64> {
+        self.state.lock().unwrap().events.values().map(|e| e.slot).collect()
+    }
+
+    async fn write(&self, events: Vec<CanonicalEvent>, checkpoint: Option<(&str, u64)>) -> Result<()> {
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            state.writing += 1;
+            state.most_writing = state.most_writing.max(state.writing);
+            state.write_delay
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.writing -= 1;
+        if let Some(slot) = state.fail_slot.filter(|slot| events.iter().any(|e| e.slot == *slot)) {
+            return Err(ETLError::Database(format!("injected failure at slot {}", slot)));
+        }
+        for event in events {
+            state.events.insert(event.event_id.clone(), event);
+        }
+        if let Some((commitment, slot)) = checkpoint {
+            state.checkpoints.insert(checkpoint_key(commitment), slot);
+        }
+        state.writes += 1;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Warehouse for MemoryWarehouse {
+    async fn connect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        if self.state.lock().unwrap().fail_flush {
+            return Err(ETLError::Database("injected flush failure".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn insert_events(&self, events: Vec<CanonicalEvent>) -> Result<()> {
+        self.write(events, None).await
+    }
+
+    async fn get_last_slot(&self, commitment: &str) -> Result<Option<u64>> {
+        Ok(self.checkpoint(commitment))
+    }
+
+    async fn update_last_slot(&self, commitment: &str, slot: u64) -> Result<()> {
+        self.write(Vec::new(), Some((commitment, slot))).await
+    }
+
+    async fn insert_events_with_checkpoint(
+        &self,
+        events: Vec<CanonicalEvent>,
+        commitment: &str,
+        slot: u64,
+    ) -> Result<()> {
+        self.write(events, Some((commitment, slot))).await
+    }
+
+    async fn is_slot_processed(&self, slot: u64) -> Result<bool> {
+        Ok(self.slots().contains(&slot))
+    }
+
+    async fn enqueue_backfill_chunks(&self, chunks: &[(u64, u64)], resume: bool, _stale_after_seconds: u64) -> Result<()> {
+        let state = &mut *self.state.lock().unwrap();
+        for &(chunk_start, chunk_end) in chunks {
+            match state.backfill_jobs.iter_mut().find(|j| j.0 == chunk_start && j.1 == chunk_end) {
+                Some(job) if job.2 != "claimed" && (!resume || job.2 == "failed") => {
+                    job.2 = "pending";
+                    if !resume {
+                        state.chunk_progress.remove(&(chunk_start, chunk_end));
+                    }
+                }
+                Some(_) => {}
+                None => state.backfill_jobs.push((chunk_start, chunk_end, "pending")),
+            }
+        }
+        Ok(())
+    }
+
+    async fn claim_backfill_chunk(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+        _stale_after_seconds: u64,
+    ) -> Result<Option<(u64, u64)>> {
+        let mut state = self.state.lock().unwrap();
+        let job = state
+            .backfill_jobs
+            .iter_mut()
+            .find(|j| j.2 == "pending" && j.0 >= start_slot && j.1 <= end_slot);
+        Ok(job.map(|j| {
+            j.2 = "claimed";
+            (j.0, j.1)
+        }))
+    }
+
+    async fn complete_backfill_chunk(&self, chunk_start: u64, chunk_end: u64, success: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(job) = state.backfill_jobs.iter_mut().find(|j| j.0 == chunk_start && j.1 == chunk_end) {
+            job.2 = if success { "done" } else { "failed" };
+        }
+        Ok(())
+    }
+
+    async fn insert_events_with_chunk_progress(
+        &self,
+        events: Vec<CanonicalEvent>,
+        chunk_start: u64,
+        chunk_end: u64,
+        slot: u64,
+    ) -> Result<()> {
+        self.write(events, None).await?;
+        self.state.lock().unwrap().chunk_progress.insert((chunk_start, chunk_end), slot);
+        Ok(())
+    }
+
+    async fn backfill_chunk_progress(&self, chunk_start: u64, chunk_end: u64) -> Result<Option<u64>> {
+        Ok(self.state.lock().unwrap().chunk_progress.get(&(chunk_start, chunk_end)).copied())
+    }
+
+    async fn insert_parse_warnings(&self, warnings: &[ParseWarning]) -> Result<()> {
+        self.state.lock().unwrap().warnings += warnings.len();
+        Ok(())
+    }
+
+    async fn insert_program_invocations(&self, invocations: &[ProgramInvocation]) -> Result<()> {
+        self.state.lock().unwrap().invocations.extend_from_slice(invocations);
+        Ok(())
+    }
+
+    async fn insert_wide_transactions(&self, rows: &[WideTransaction]) -> Result<()> {
+        self.state.lock().unwrap().wide_rows.extend_from_slice(rows);
+        Ok(())
+    }
+
+    async fn insert_ingestion_stats(&self, stats: &IngestionStats) -> Result<()> {
+        self.state.lock().unwrap().stats.push(stats.clone());
+        Ok(())
+    }
+
+    async fn get_events_by_signature(&self, signature: &str) -> Result<Vec<CanonicalEvent>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.events.values().filter(|e| e.tx_signature == signature).cloned().collect())
+    }
+
+    async fn count_events(&self, slot: u64, event_type: &str) -> Result<u64> {
+        let state = self.state.lock().unwrap();
+        Ok(state.events.values().filter(|e| e.slot == slot && e.event_type == event_type).count() as u64)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// `BlockSource` serving canned blocks; slots without one are skipped
+#[derive(Debug, Default)]
+pub struct FakeBlocks {
+    pub blocks: HashMap<u64, String>,
+    pub tip: AtomicU64,
+    /// Slots that return null this many more times before their block
+    pub pending: Mutex<HashMap<u64, u32>>,
+    /// Slots the source has cleaned up, answered with `SlotPurged`
+    pub purged: BTreeSet<u64>,
+    pub fetches: AtomicU64,
+    /// Every fetch sleeps this long first, as a slow RPC would
+    pub fetch_delay: Option<Duration>,
+    /// Fetches under way, and the most that were ever under way at once
+    pub fetching: AtomicU64,
+    pub most_fetching: AtomicU64,
+}
+
+impl FakeBlocks {
+    /// A block with one transaction in every slot of `slots`, tip at its end
+    pub fn with_slots(slots: RangeInclusive<u64>) -> Self {
+        let tip = *slots.end();
+        let blocks = slots
+            .map(|slot| {
+                let tx = transaction(&format!("sig{}", slot), &["payer", "Prog111"], json!([
+                    { "programIdIndex": 1, "accounts": [0], "data": "" }
+                ]));
+                (slot, block(vec![tx]).to_string())
+            })
+            .collect();
+        Self {
+            blocks,
+            tip: tip.into(),
+            ..Self::default()
+        }
+    }
+}
+
+#[async_trait]
+impl BlockSource for FakeBlocks {
+    async fn get_block(&self, slot: u64, _rewards: bool) -> Result<Option<RawBlock>> {
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        let fetching = self.fetching.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_fetching.fetch_max(fetching, Ordering::SeqCst);
+        if let Some(delay) = self.fetch_delay {
+            tokio::time::sleep(delay).await;
+        }
+        self.fetching.fetch_sub(1, Ordering::SeqCst);
+        if let Some(remaining) = self.pending.lock().unwrap().get_mut(&slot).filter(|n| **n > 0) {
+            *remaining -= 1;
+            return Ok(None);
+        }
+        if self.purged.contains(&slot) {
+            return Err(ETLError::SlotPurged(slot));
+        }
+        Ok(self.blocks.get(&slot).map(|json| serde_json::from_str(json).unwrap()))
+    }
+
+    async fn get_blocks(&self, start_slot: u64, end_slot: u64) -> Result<Vec<u64>> {
+        let mut slots: Vec<u64> = self.blocks.keys().copied().filter(|s| (start_slot..=end_slot).contains(s)).collect();
+        slots.sort_unstable();
+        Ok(slots)
+    }
+
+    async fn get_slot(&self) -> Result<u64> {
+        Ok(self.tip.load(Ordering::Relaxed))
+    }
+}
+
+/// Config for driving the loops against fakes: env defaults, with leader
+/// lookups off and blocks parsed inline
+pub fn config() -> Config {
+    let mut config = Config::default();
+    config.etl.resolve_leaders = false;
+    config.etl.parse_threads = 0;
+    config.etl.poll_jitter_pct = 0.0;
+    config
+}
+
+/// What `MockRpc` answers a JSON-RPC call with
+pub enum Reply {
+    Result(Value),
+    /// A JSON-RPC error object, e.g. 429 to exercise retries
+    Error { code: i64, message: String },
+    /// The inner reply, sent only after waiting this long
+    Delayed(std::time::Duration, Box<Reply>),
+}
+
+/// One call received by `MockRpc`
+#[derive(Debug, Clone)]
+pub struct ReceivedCall {
+    pub id: u64,
+    pub method: String,
+    pub params: Value,
+    /// Request headers, names lowercased
+    pub headers: HashMap<String, String>,
+}
+
+type Handler = dyn Fn(&str, &Value) -> Reply + Send + Sync;
+
+/// A JSON-RPC endpoint on localhost answering every call with `handler`,
+/// for testing `AlchemyRPCClient` without a network. Speaks just enough
+/// HTTP/1.1 (keep-alive, `Content-Length` bodies) for reqwest.
+pub struct MockRpc {
+    pub url: String,
+    pub calls: std::sync::Arc<Mutex<Vec<ReceivedCall>>>,
+}
+
+impl MockRpc {
+    pub async fn start(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let handler: std::sync::Arc<Handler> = std::sync::Arc::new(handler);
+
+        let received = calls.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, handler.clone(), received.clone()));
+            }
+        });
+        Self { url, calls }
+    }
+
+    /// Client settings pointing at this endpoint: no retries or backoff,
+    /// no rate limit to speak of, and the circuit breaker effectively off
+    pub fn alchemy_config(&self) -> crate::config::AlchemyConfig {
+        let mut config = Config::default().alchemy;
+        config.rpc_url = self.url.clone();
+        config.max_retries = 0;
+        config.max_backoff_seconds = 0;
+        config.call_deadline_seconds = 0;
+        config.max_requests_per_run = 0;
+        config.rate_limit_per_second = Some(100_000);
+        config.rate_limit_per_minute = None;
+        config.rate_limit_burst = None;
+        config.control_rate_limit_per_second = 0;
+        config.circuit_failure_threshold = 1.1;
+        config.http2 = false;
+        config
+    }
+}
+
+async fn serve_connection(
+    stream: tokio::net::TcpStream,
+    handler: std::sync::Arc<Handler>,
+    calls: std::sync::Arc<Mutex<Vec<ReceivedCall>>>,
+) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut headers = HashMap::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        let content_length = headers.get("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+        let mut body = vec![0; content_length];
+        if stream.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let id = request["id"].as_u64().unwrap_or(0);
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        let params = request["params"].clone();
+        calls.lock().unwrap().push(ReceivedCall {
+            id,
+            method: method.clone(),
+            params: params.clone(),
+            headers,
+        });
+
+        let mut reply = handler(&method, &params);
+        let response = loop {
+            match reply {
+                Reply::Result(result) => break json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Reply::Error { code, message } => {
+                    break json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+                }
+                Reply::Delayed(delay, inner) => {
+                    tokio::time::sleep(delay).await;
+                    reply = *inner;
+                }
+            }
+        };
+        let body = response.to_string();
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n",
+            body.len()
+        );
+        let written = async {
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(body.as_bytes()).await?;
+            stream.flush().await
+        };
+        if written.await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::config::Config;
+use crate::error::{ETLError, Result};
+use crate::rpc::AlchemyRPCClient;
+use tracing::{info, warn};
+
+/// Preflight check of config correctness and reachability
+pub async fn run_validate(config: Config) -> Result<()> {
+    info!("Validating configuration");
+
+    let mut failed = 0;
+
+    // Check config values
+    match config.validate() {
+        Ok(_) => info!("Config values: OK"),
+        Err(e) => {
+            warn!("Config values: FAILED - {}", e);
+            failed += 1;
+        }
+    }
+
+    // Check RPC endpoint
+    let rpc_client = AlchemyRPCClient::new(config.alchemy.clone());
+    match rpc_client.get_slot().await {
+        Ok(slot) => info!("RPC get_slot: OK (slot {})", slot),
+        Err(e) => {
+            warn!("RPC get_slot: FAILED - {}", e);
+            failed += 1;
+        }
+    }
+
+    // Check warehouse connection
+    match crate::warehouse::create_warehouse(config.warehouse.clone()) {
+        // Postgres connects lazily, so run a trivial query to force the connection
+        Ok(warehouse) => match async { warehouse.connect().await?; warehouse.health_check().await }.await {
+            Ok(_) => info!("Warehouse connect: OK"),
+            Err(e) => {
+                warn!("Warehouse connect: FAILED - {}", e);
+                failed += 1;
+            }
+        },
+        Err(e) => {
+            warn!("Warehouse connect: FAILED - {}", e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(ETLError::Config(format!("{} validation check(s) failed", failed)));
+    }
+
+    info!("Validation passed");
+    Ok(())
+}